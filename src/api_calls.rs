@@ -0,0 +1,86 @@
+//! Counts of the AWS API calls made during the run, so platform teams can
+//! see the impact of their mapping design on API usage and cost.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Secrets Manager price, in USD per 10,000 API calls.
+const SECRETS_MANAGER_PRICE: f64 = 0.05;
+
+const HEADERS: [&str; 4] = ["SERVICE", "OPERATION", "CALLS", "ESTIMATED COST"];
+
+/// An API operation, as its service and operation names.
+type Operation = (&'static str, &'static str);
+
+static CALLS: Mutex<BTreeMap<Operation, u64>> = Mutex::new(BTreeMap::new());
+
+/// Records a call to an API operation.
+pub fn record(service: &'static str, operation: &'static str) {
+    let mut calls = CALLS.lock().expect("API call counter lock poisoned");
+    *calls.entry((service, operation)).or_default() += 1;
+}
+
+/// The number of calls made so far per operation.
+pub fn counts() -> Vec<(Operation, u64)> {
+    let calls = CALLS.lock().expect("API call counter lock poisoned");
+    calls
+        .iter()
+        .map(|(operation, count)| (*operation, *count))
+        .collect()
+}
+
+/// Summarizes the counts on one line for the logs.
+pub fn summary(counts: &[(Operation, u64)]) -> String {
+    if counts.is_empty() {
+        return "none".to_string();
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|((service, operation), count)| format!("{}:{}={}", service, operation, count))
+        .collect();
+    counts.join(", ")
+}
+
+/// Estimates the cost of the calls to an operation, in USD. Parameter Store
+/// calls are free with the standard throughput.
+fn estimated_cost(service: &str, count: u64) -> f64 {
+    match service {
+        "secretsmanager" => count as f64 * SECRETS_MANAGER_PRICE / 10_000.0,
+        _ => 0.0,
+    }
+}
+
+/// Renders the counts as a table with their estimated cost.
+pub fn report_table(counts: &[(Operation, u64)]) -> String {
+    let rows: Vec<[String; 4]> = counts
+        .iter()
+        .map(|((service, operation), count)| {
+            [
+                service.to_string(),
+                operation.to_string(),
+                count.to_string(),
+                format!("${:.6}", estimated_cost(service, *count)),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    let header = HEADERS.map(String::from);
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
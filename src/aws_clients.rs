@@ -0,0 +1,448 @@
+use crate::logging::{info, warn};
+use crate::secret_manager::{PasswordSpec, SecretCopy, SecretsManagerClientTrait};
+use crate::ssm_manager::SsmClientTrait;
+use aws_config::retry::{RetryConfig, RetryMode};
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{Region, SdkConfig};
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sdk_secretsmanager::error::SdkError;
+use aws_sdk_secretsmanager::operation::batch_get_secret_value::BatchGetSecretValueOutput;
+use aws_sdk_secretsmanager::operation::create_secret::{CreateSecretError, CreateSecretOutput};
+use aws_sdk_secretsmanager::operation::describe_secret::{
+    DescribeSecretError, DescribeSecretOutput,
+};
+use aws_sdk_secretsmanager::operation::get_random_password::{
+    GetRandomPasswordError, GetRandomPasswordOutput,
+};
+use aws_sdk_secretsmanager::operation::get_secret_value::{
+    GetSecretValueError, GetSecretValueOutput,
+};
+use aws_sdk_secretsmanager::operation::put_secret_value::{
+    PutSecretValueError, PutSecretValueOutput,
+};
+use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+use aws_sdk_ssm::operation::get_parameter::{GetParameterError, GetParameterOutput};
+use aws_sdk_ssm::operation::get_parameters_by_path::{
+    GetParametersByPathError, GetParametersByPathOutput,
+};
+use aws_sdk_ssm::operation::put_parameter::{PutParameterError, PutParameterOutput};
+use aws_sdk_ssm::Client as SsmClient;
+use aws_types::sdk_config::SharedCredentialsProvider;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+
+/// Session name of the roles assumed, unless another one is given.
+const ASSUME_ROLE_SESSION_NAME: &str = "resolve-aws-secrets";
+
+/// Role assumed with STS before any call, for reading the secrets of another
+/// account such as a central security account. The settings not given as
+/// options are read from the `SECRETS_ASSUME_ROLE_ARN`,
+/// `SECRETS_ASSUME_ROLE_EXTERNAL_ID` and `SECRETS_ASSUME_ROLE_SESSION_NAME`
+/// environment variables.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AssumeRole {
+    pub role_arn: Option<String>,
+    pub external_id: Option<String>,
+    pub session_name: Option<String>,
+}
+
+impl AssumeRole {
+    /// Returns the settings completed from the environment, or None when no
+    /// role is given. Fails when an external id or session name is given
+    /// without a role.
+    pub fn effective(&self) -> Result<Option<AssumeRole>, Box<dyn Error>> {
+        let setting = |value: &Option<String>, variable: &str| {
+            value
+                .clone()
+                .or_else(|| std::env::var(variable).ok())
+                .filter(|value| !value.is_empty())
+        };
+        let assume_role = AssumeRole {
+            role_arn: setting(&self.role_arn, "SECRETS_ASSUME_ROLE_ARN"),
+            external_id: setting(&self.external_id, "SECRETS_ASSUME_ROLE_EXTERNAL_ID"),
+            session_name: setting(&self.session_name, "SECRETS_ASSUME_ROLE_SESSION_NAME"),
+        };
+        match assume_role.role_arn {
+            Some(_) => Ok(Some(assume_role)),
+            None if assume_role == AssumeRole::default() => Ok(None),
+            None => Err(
+                "An external id or session name requires --assume-role or SECRETS_ASSUME_ROLE_ARN"
+                    .into(),
+            ),
+        }
+    }
+
+    /// Replaces the credentials of the configuration with the temporary
+    /// credentials of the role, assumed with the original ones.
+    pub async fn apply(&self, config: SdkConfig) -> SdkConfig {
+        let Some(role_arn) = &self.role_arn else {
+            return config;
+        };
+        info!("Assuming role {}", role_arn);
+        let mut builder = AssumeRoleProvider::builder(role_arn).session_name(
+            self.session_name
+                .as_deref()
+                .unwrap_or(ASSUME_ROLE_SESSION_NAME),
+        );
+        if let Some(external_id) = &self.external_id {
+            builder = builder.external_id(external_id);
+        }
+        let provider = builder.configure(&config).build().await;
+        config
+            .into_builder()
+            .credentials_provider(SharedCredentialsProvider::new(provider))
+            .build()
+    }
+}
+
+/// Retry settings of the AWS calls, overriding the SDK defaults of 3
+/// attempts with jittered exponential backoff from 1 second up to 20 seconds.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RetryOptions {
+    /// Attempts per call, including the first one, or `AWS_MAX_ATTEMPTS`.
+    pub max_attempts: Option<u32>,
+    /// Base of the exponential backoff between attempts.
+    pub initial_backoff: Option<Duration>,
+    /// Longest wait between attempts.
+    pub max_backoff: Option<Duration>,
+    /// `standard`, or `adaptive` to also slow down the calls once throttled,
+    /// or `AWS_RETRY_MODE`.
+    pub mode: Option<RetryMode>,
+}
+
+impl RetryOptions {
+    /// Returns the retry configuration for the options, or None to leave the
+    /// SDK defaults and environment variables in effect.
+    pub fn retry_config(&self) -> Result<Option<RetryConfig>, Box<dyn Error>> {
+        if *self == RetryOptions::default() {
+            return Ok(None);
+        }
+        let max_attempts = match (self.max_attempts, std::env::var("AWS_MAX_ATTEMPTS")) {
+            (Some(max_attempts), _) => Some(max_attempts),
+            (None, Ok(value)) => Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid AWS_MAX_ATTEMPTS: {}", value))?,
+            ),
+            (None, Err(_)) => None,
+        };
+        let mode = match (self.mode, std::env::var("AWS_RETRY_MODE")) {
+            (Some(mode), _) => mode,
+            (None, Ok(value)) => value
+                .parse()
+                .map_err(|_| format!("Invalid AWS_RETRY_MODE: {}", value))?,
+            (None, Err(_)) => RetryMode::Standard,
+        };
+
+        let mut config = RetryConfig::standard().with_retry_mode(mode);
+        if let Some(max_attempts) = max_attempts {
+            config = config.with_max_attempts(max_attempts);
+        }
+        if let Some(initial_backoff) = self.initial_backoff {
+            config = config.with_initial_backoff(initial_backoff);
+        }
+        if let Some(max_backoff) = self.max_backoff {
+            config = config.with_max_backoff(max_backoff);
+        }
+        Ok(Some(config))
+    }
+}
+
+/// Delay before the second attempt to acquire credentials, doubled after
+/// every failed attempt up to `MAX_CREDENTIALS_BACKOFF`.
+const INITIAL_CREDENTIALS_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_CREDENTIALS_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Waits up to `timeout` for the credentials of the configuration to be
+/// available, as instance and task credential endpoints often aren't ready
+/// right after boot. Gives up with a warning, leaving the first AWS call to
+/// report the error.
+pub async fn wait_for_credentials(config: &SdkConfig, timeout: Duration) {
+    let Some(provider) = config.credentials_provider() else {
+        return;
+    };
+    let acquired = retry_with_backoff(timeout, || async {
+        provider.provide_credentials().await.map(|_| ())
+    })
+    .await;
+    if let Err(e) = acquired {
+        warn!("No credentials were available after {:?}: {}", timeout, e);
+    }
+}
+
+/// Retries the attempt with exponential backoff until it succeeds or the
+/// next attempt would start after `timeout`, returning the last error.
+pub async fn retry_with_backoff<F, Fut, E>(timeout: Duration, mut attempt: F) -> Result<(), E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let started = Instant::now();
+    let mut backoff = INITIAL_CREDENTIALS_BACKOFF;
+    loop {
+        let error = match attempt().await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if started.elapsed() + backoff > timeout {
+            return Err(error);
+        }
+        info!(
+            "Credentials are not available yet, retrying in {:?}: {}",
+            backoff, error
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_CREDENTIALS_BACKOFF);
+    }
+}
+
+/// AWS configuration loaded in the background, so that credential and region
+/// discovery overlaps with scanning the environment for references.
+pub struct LazyConfig {
+    loading: Mutex<Option<JoinHandle<SdkConfig>>>,
+    config: OnceCell<SdkConfig>,
+}
+
+impl LazyConfig {
+    /// Starts loading the configuration right away.
+    pub fn spawn<F>(load: F) -> Self
+    where
+        F: Future<Output = SdkConfig> + Send + 'static,
+    {
+        LazyConfig {
+            loading: Mutex::new(Some(tokio::spawn(load))),
+            config: OnceCell::new(),
+        }
+    }
+
+    /// Waits for the configuration to be loaded.
+    pub async fn get(&self) -> &SdkConfig {
+        self.config
+            .get_or_init(|| async {
+                let loading = self
+                    .loading
+                    .lock()
+                    .expect("AWS configuration lock poisoned")
+                    .take()
+                    .expect("AWS configuration is loaded only once");
+                loading.await.expect("Loading the AWS configuration failed")
+            })
+            .await
+    }
+}
+
+/// A client that is only built the first time a request needs it, so that
+/// no client is constructed for services without references.
+pub struct LazyClient<'a, C> {
+    config: &'a LazyConfig,
+    client: OnceCell<C>,
+    build: fn(&SdkConfig) -> C,
+    /// Clients for other regions and roles, built on first use as well.
+    targets: tokio::sync::Mutex<HashMap<Target, C>>,
+}
+
+/// Region and role overriding the default ones.
+type Target = (Option<String>, Option<String>);
+
+impl<'a, C> LazyClient<'a, C> {
+    pub fn new(config: &'a LazyConfig, build: fn(&SdkConfig) -> C) -> Self {
+        LazyClient {
+            config,
+            client: OnceCell::new(),
+            build,
+            targets: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn client(&self) -> &C {
+        self.client
+            .get_or_init(|| async { (self.build)(self.config.get().await) })
+            .await
+    }
+}
+
+impl<C: Clone> LazyClient<'_, C> {
+    /// Returns the client for another region or role, building it on first
+    /// use.
+    async fn target_client(&self, region: Option<&str>, role: Option<&str>) -> C {
+        let target = (region.map(str::to_string), role.map(str::to_string));
+        let mut targets = self.targets.lock().await;
+        if let Some(client) = targets.get(&target) {
+            return client.clone();
+        }
+
+        let mut config = self.config.get().await.to_builder();
+        if let Some(region) = region {
+            config.set_region(Some(Region::new(region.to_string())));
+        }
+        let mut config = config.build();
+        if let Some(role) = role {
+            let provider = AssumeRoleProvider::builder(role)
+                .session_name(ASSUME_ROLE_SESSION_NAME)
+                .configure(&config)
+                .build()
+                .await;
+            config = config
+                .into_builder()
+                .credentials_provider(SharedCredentialsProvider::new(provider))
+                .build();
+        }
+        let client = (self.build)(&config);
+        targets.insert(target, client.clone());
+        client
+    }
+
+    /// Returns the client for the region of the resource when it's given by
+    /// an ARN from another region than the default one, since the calls only
+    /// find the resources of the region they're sent to.
+    async fn routed_client(&self, resource: &str) -> C {
+        let default_region = self.config.get().await.region().map(Region::as_ref);
+        match arn_region(resource) {
+            Some(region) if Some(region) != default_region => {
+                info!("Routing the calls for {} to {}", resource, region);
+                self.target_client(Some(region), None).await
+            }
+            _ => self.client().await.clone(),
+        }
+    }
+}
+
+/// The region of an ARN, `arn:partition:service:region:account:resource`.
+pub fn arn_region(resource: &str) -> Option<&str> {
+    let mut fields = resource.strip_prefix("arn:")?.split(':');
+    fields.nth(2).filter(|region| !region.is_empty())
+}
+
+#[async_trait::async_trait]
+impl SecretsManagerClientTrait for LazyClient<'_, SecretsManagerClient> {
+    async fn get_secret_value(
+        &self,
+        secret_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        SecretsManagerClientTrait::get_secret_value(&self.routed_client(secret_id).await, secret_id)
+            .await
+    }
+
+    async fn get_secret_value_version(
+        &self,
+        secret_id: &str,
+        version_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        self.routed_client(secret_id)
+            .await
+            .get_secret_value_version(secret_id, version_id)
+            .await
+    }
+
+    async fn get_secret_value_stage(
+        &self,
+        secret_id: &str,
+        version_stage: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        self.routed_client(secret_id)
+            .await
+            .get_secret_value_stage(secret_id, version_stage)
+            .await
+    }
+
+    async fn describe_secret(
+        &self,
+        secret_id: &str,
+    ) -> Result<DescribeSecretOutput, SdkError<DescribeSecretError>> {
+        SecretsManagerClientTrait::describe_secret(&self.routed_client(secret_id).await, secret_id)
+            .await
+    }
+
+    async fn get_random_password(
+        &self,
+        spec: &PasswordSpec,
+    ) -> Result<GetRandomPasswordOutput, SdkError<GetRandomPasswordError>> {
+        SecretsManagerClientTrait::get_random_password(self.client().await, spec).await
+    }
+
+    async fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        SecretsManagerClientTrait::create_secret(self.client().await, name, value).await
+    }
+
+    async fn put_secret_value(
+        &self,
+        secret_id: &str,
+        value: &str,
+    ) -> Result<PutSecretValueOutput, SdkError<PutSecretValueError>> {
+        SecretsManagerClientTrait::put_secret_value(
+            &self.routed_client(secret_id).await,
+            secret_id,
+            value,
+        )
+        .await
+    }
+
+    async fn create_secret_copy(
+        &self,
+        copy: &SecretCopy,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        self.client().await.create_secret_copy(copy).await
+    }
+
+    async fn batch_get_secret_value(
+        &self,
+        secret_ids: &[String],
+    ) -> Result<BatchGetSecretValueOutput, Box<dyn Error>> {
+        SecretsManagerClientTrait::batch_get_secret_value(self.client().await, secret_ids).await
+    }
+
+    async fn with_target(
+        &self,
+        region: Option<&str>,
+        role: Option<&str>,
+    ) -> Result<Box<dyn SecretsManagerClientTrait>, Box<dyn Error>> {
+        Ok(Box::new(self.target_client(region, role).await))
+    }
+}
+
+#[async_trait::async_trait]
+impl SsmClientTrait for LazyClient<'_, SsmClient> {
+    async fn get_parameter(
+        &self,
+        name: &str,
+        with_decryption: bool,
+    ) -> Result<GetParameterOutput, aws_sdk_ssm::error::SdkError<GetParameterError>> {
+        SsmClientTrait::get_parameter(&self.routed_client(name).await, name, with_decryption).await
+    }
+
+    async fn get_parameters_by_path(
+        &self,
+        path: &str,
+        with_decryption: bool,
+        next_token: Option<String>,
+    ) -> Result<GetParametersByPathOutput, aws_sdk_ssm::error::SdkError<GetParametersByPathError>>
+    {
+        SsmClientTrait::get_parameters_by_path(
+            self.client().await,
+            path,
+            with_decryption,
+            next_token,
+        )
+        .await
+    }
+
+    async fn put_parameter(
+        &self,
+        name: &str,
+        value: &str,
+        overwrite: bool,
+    ) -> Result<PutParameterOutput, aws_sdk_ssm::error::SdkError<PutParameterError>> {
+        SsmClientTrait::put_parameter(&self.routed_client(name).await, name, value, overwrite).await
+    }
+}
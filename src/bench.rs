@@ -0,0 +1,158 @@
+use crate::environment_processor::{resolve_reference, Provider, Reference, ResolveOptions};
+use crate::errors::ResolveError;
+use crate::secret_manager::SecretsManagerClientTrait;
+use crate::ssm_manager::SsmClientTrait;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Warm resolutions of every reference when no count is given.
+pub const DEFAULT_ITERATIONS: usize = 10;
+
+const REFERENCE_HEADERS: [&str; 5] = ["KEY", "SERVICE", "COLD", "P50", "P95"];
+const SERVICE_HEADERS: [&str; 5] = ["SERVICE", "REFERENCES", "COLD", "P50", "P95"];
+
+/// Resolution latencies of one reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timing {
+    pub key: String,
+    pub provider: Provider,
+    /// Latency of the first resolution, including loading the credentials
+    /// and opening the connections for the first reference of a service.
+    pub cold: Duration,
+    /// Latencies of the following resolutions with the same clients.
+    pub warm: Vec<Duration>,
+}
+
+/// Resolves every reference once with the clients as given, then
+/// `iterations` more times, timing each resolution. Templates and generated
+/// passwords are skipped, as they don't retrieve anything.
+pub async fn bench<S, T>(
+    secretsmanager_client: &S,
+    ssm_client: &T,
+    references: &[Reference],
+    options: &ResolveOptions,
+    iterations: usize,
+) -> Result<Vec<Timing>, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SsmClientTrait + ?Sized,
+{
+    let references: Vec<&Reference> = references
+        .iter()
+        .filter(|reference| {
+            !matches!(
+                reference.provider,
+                Provider::Template | Provider::GeneratedPassword
+            )
+        })
+        .collect();
+    let mut timings: Vec<Timing> = Vec::new();
+
+    for iteration in 0..=iterations {
+        for (index, reference) in references.iter().enumerate() {
+            let started = Instant::now();
+            resolve_reference(secretsmanager_client, ssm_client, reference, options)
+                .await
+                .map_err(|cause| ResolveError {
+                    variable: reference.variable.clone(),
+                    reference: reference.reference.clone(),
+                    cause,
+                })?;
+            let elapsed = started.elapsed();
+            if iteration == 0 {
+                timings.push(Timing {
+                    key: reference.key.clone(),
+                    provider: reference.provider,
+                    cold: elapsed,
+                    warm: Vec::new(),
+                });
+            } else {
+                timings[index].warm.push(elapsed);
+            }
+        }
+    }
+    Ok(timings)
+}
+
+/// The latency below which the given fraction of the samples fall, by the
+/// nearest-rank method.
+pub fn percentile(samples: &[Duration], fraction: f64) -> Option<Duration> {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+fn format_latency(latency: Option<Duration>) -> String {
+    latency.map_or_else(|| "-".to_string(), |latency| format!("{:.1?}", latency))
+}
+
+/// Renders the latencies per reference, then per service, where the
+/// latency of an iteration is the total for the references of the service.
+pub fn bench_table(timings: &[Timing]) -> String {
+    let rows: Vec<[String; 5]> = timings
+        .iter()
+        .map(|timing| {
+            [
+                timing.key.clone(),
+                timing.provider.to_string(),
+                format_latency(Some(timing.cold)),
+                format_latency(percentile(&timing.warm, 0.5)),
+                format_latency(percentile(&timing.warm, 0.95)),
+            ]
+        })
+        .collect();
+
+    let mut services: BTreeMap<String, Vec<&Timing>> = BTreeMap::new();
+    for timing in timings {
+        services
+            .entry(timing.provider.to_string())
+            .or_default()
+            .push(timing);
+    }
+    let service_rows: Vec<[String; 5]> = services
+        .into_iter()
+        .map(|(service, timings)| {
+            let cold = timings.iter().map(|timing| timing.cold).sum();
+            let iterations = timings[0].warm.len();
+            let warm: Vec<Duration> = (0..iterations)
+                .map(|iteration| timings.iter().map(|timing| timing.warm[iteration]).sum())
+                .collect();
+            [
+                service,
+                timings.len().to_string(),
+                format_latency(Some(cold)),
+                format_latency(percentile(&warm, 0.5)),
+                format_latency(percentile(&warm, 0.95)),
+            ]
+        })
+        .collect();
+
+    let mut output = table(REFERENCE_HEADERS, &rows);
+    output.push('\n');
+    output.push_str(&table(SERVICE_HEADERS, &service_rows));
+    output
+}
+
+fn table(headers: [&str; 5], rows: &[[String; 5]]) -> String {
+    let mut widths = headers.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    let header = headers.map(String::from);
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
@@ -0,0 +1,257 @@
+use crate::secret_manager::{PasswordSpec, SecretCopy, SecretsManagerClientTrait};
+use crate::ssm_manager::SsmClientTrait;
+use aws_sdk_secretsmanager::error::SdkError;
+use aws_sdk_secretsmanager::operation::batch_get_secret_value::BatchGetSecretValueOutput;
+use aws_sdk_secretsmanager::operation::create_secret::{CreateSecretError, CreateSecretOutput};
+use aws_sdk_secretsmanager::operation::describe_secret::{
+    DescribeSecretError, DescribeSecretOutput,
+};
+use aws_sdk_secretsmanager::operation::get_random_password::{
+    GetRandomPasswordError, GetRandomPasswordOutput,
+};
+use aws_sdk_secretsmanager::operation::get_secret_value::{
+    GetSecretValueError, GetSecretValueOutput,
+};
+use aws_sdk_secretsmanager::operation::put_secret_value::{
+    PutSecretValueError, PutSecretValueOutput,
+};
+use aws_sdk_ssm::error::SdkError as SsmSdkError;
+use aws_sdk_ssm::operation::get_parameter::{GetParameterError, GetParameterOutput};
+use aws_sdk_ssm::operation::get_parameters_by_path::{
+    GetParametersByPathError, GetParametersByPathOutput,
+};
+use aws_sdk_ssm::operation::put_parameter::{PutParameterError, PutParameterOutput};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// Responses of the GetSecretValue and GetParameter calls, shared by the
+/// references to the same secret or parameter, such as an ARN declared both
+/// in a variable and in the mapping, so that each is retrieved only once.
+/// Kept across resolutions, the responses are reused until they are older
+/// than the TTL they are looked up with.
+#[derive(Default)]
+pub struct ResponseCache {
+    secrets: Entries<String, GetSecretValueOutput>,
+    parameters: Entries<(String, bool), GetParameterOutput>,
+}
+
+impl ResponseCache {
+    /// Whether the current version of the secret is cached and younger than
+    /// the TTL.
+    pub fn has_fresh_secret(&self, secret_id: &str, ttl: Option<Duration>) -> bool {
+        self.secrets.is_fresh(&secret_id.to_string(), ttl)
+    }
+}
+
+// The responses hold secret values, so only their number is shown.
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("secrets", &self.secrets.len())
+            .field("parameters", &self.parameters.len())
+            .finish()
+    }
+}
+
+type Entry<V> = Arc<OnceCell<(Instant, V)>>;
+
+/// Responses by request. Concurrent requests for the same key wait for the
+/// first one instead of calling AWS again, and failures aren't cached.
+struct Entries<K, V>(Mutex<HashMap<K, Entry<V>>>);
+
+impl<K, V> Default for Entries<K, V> {
+    fn default() -> Self {
+        Entries(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> Entries<K, V> {
+    fn len(&self) -> usize {
+        self.0.lock().expect("response cache lock poisoned").len()
+    }
+
+    fn is_fresh(&self, key: &K, ttl: Option<Duration>) -> bool {
+        let entries = self.0.lock().expect("response cache lock poisoned");
+        entries
+            .get(key)
+            .and_then(|entry| entry.get())
+            .is_some_and(|(fetched, _)| !is_expired(*fetched, ttl))
+    }
+
+    async fn get_or_fetch<F, Fut, E>(&self, key: K, ttl: Option<Duration>, fetch: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let entry = {
+            let mut entries = self.0.lock().expect("response cache lock poisoned");
+            let entry = entries.entry(key).or_default();
+            if entry
+                .get()
+                .is_some_and(|(fetched, _)| is_expired(*fetched, ttl))
+            {
+                *entry = Entry::default();
+            }
+            entry.clone()
+        };
+        let (_, value) = entry
+            .get_or_try_init(|| async { Ok((Instant::now(), fetch().await?)) })
+            .await?;
+        Ok(value.clone())
+    }
+}
+
+fn is_expired(fetched: Instant, ttl: Option<Duration>) -> bool {
+    ttl.is_some_and(|ttl| fetched.elapsed() >= ttl)
+}
+
+/// Client answering the GetSecretValue and GetParameter calls from the
+/// cache, and forwarding every other call to the wrapped client.
+pub struct CachedClient<'a, T: ?Sized> {
+    client: &'a T,
+    cache: &'a ResponseCache,
+    ttl: Option<Duration>,
+}
+
+impl<'a, T: ?Sized> CachedClient<'a, T> {
+    pub fn new(client: &'a T, cache: &'a ResponseCache, ttl: Option<Duration>) -> Self {
+        CachedClient { client, cache, ttl }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: SecretsManagerClientTrait + ?Sized> SecretsManagerClientTrait for CachedClient<'_, T> {
+    async fn get_secret_value(
+        &self,
+        secret_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        self.cache
+            .secrets
+            .get_or_fetch(secret_id.to_string(), self.ttl, || {
+                self.client.get_secret_value(secret_id)
+            })
+            .await
+    }
+
+    async fn get_secret_value_version(
+        &self,
+        secret_id: &str,
+        version_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        let key = format!("{}?versionId={}", secret_id, version_id);
+        self.cache
+            .secrets
+            .get_or_fetch(key, self.ttl, || {
+                self.client.get_secret_value_version(secret_id, version_id)
+            })
+            .await
+    }
+
+    async fn get_secret_value_stage(
+        &self,
+        secret_id: &str,
+        version_stage: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        let key = format!("{}@{}", secret_id, version_stage);
+        self.cache
+            .secrets
+            .get_or_fetch(key, self.ttl, || {
+                self.client.get_secret_value_stage(secret_id, version_stage)
+            })
+            .await
+    }
+
+    async fn describe_secret(
+        &self,
+        secret_id: &str,
+    ) -> Result<DescribeSecretOutput, SdkError<DescribeSecretError>> {
+        self.client.describe_secret(secret_id).await
+    }
+
+    async fn get_random_password(
+        &self,
+        spec: &PasswordSpec,
+    ) -> Result<GetRandomPasswordOutput, SdkError<GetRandomPasswordError>> {
+        self.client.get_random_password(spec).await
+    }
+
+    async fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        self.client.create_secret(name, value).await
+    }
+
+    async fn put_secret_value(
+        &self,
+        secret_id: &str,
+        value: &str,
+    ) -> Result<PutSecretValueOutput, SdkError<PutSecretValueError>> {
+        self.client.put_secret_value(secret_id, value).await
+    }
+
+    async fn create_secret_copy(
+        &self,
+        copy: &SecretCopy,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        self.client.create_secret_copy(copy).await
+    }
+
+    async fn batch_get_secret_value(
+        &self,
+        secret_ids: &[String],
+    ) -> Result<BatchGetSecretValueOutput, Box<dyn Error>> {
+        self.client.batch_get_secret_value(secret_ids).await
+    }
+
+    async fn with_target(
+        &self,
+        region: Option<&str>,
+        role: Option<&str>,
+    ) -> Result<Box<dyn SecretsManagerClientTrait>, Box<dyn Error>> {
+        self.client.with_target(region, role).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: SsmClientTrait + ?Sized> SsmClientTrait for CachedClient<'_, T> {
+    async fn get_parameter(
+        &self,
+        name: &str,
+        with_decryption: bool,
+    ) -> Result<GetParameterOutput, SsmSdkError<GetParameterError>> {
+        self.cache
+            .parameters
+            .get_or_fetch((name.to_string(), with_decryption), self.ttl, || {
+                self.client.get_parameter(name, with_decryption)
+            })
+            .await
+    }
+
+    async fn get_parameters_by_path(
+        &self,
+        path: &str,
+        with_decryption: bool,
+        next_token: Option<String>,
+    ) -> Result<GetParametersByPathOutput, SsmSdkError<GetParametersByPathError>> {
+        self.client
+            .get_parameters_by_path(path, with_decryption, next_token)
+            .await
+    }
+
+    async fn put_parameter(
+        &self,
+        name: &str,
+        value: &str,
+        overwrite: bool,
+    ) -> Result<PutParameterOutput, SsmSdkError<PutParameterError>> {
+        self.client.put_parameter(name, value, overwrite).await
+    }
+}
@@ -0,0 +1,175 @@
+//! A cgroup v2 group with memory and CPU limits for the command, for hosts
+//! where the tool is the only supervision layer, such as bare metal or EC2.
+
+use crate::logging::{info, warn};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Mount point of the unified cgroup v2 hierarchy.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Limits of the group the command is placed in.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CgroupLimits {
+    /// Maximum memory, in bytes with an optional K, M or G suffix.
+    pub memory_max: Option<String>,
+    /// Maximum CPU time, in CPUs.
+    pub cpu_max: Option<f64>,
+}
+
+impl CgroupLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == CgroupLimits::default()
+    }
+}
+
+/// A group created for the command, removed when dropped once the command
+/// has exited.
+pub struct Cgroup {
+    dir: PathBuf,
+    /// Leaf group the current process moved to, with the controllers it
+    /// enabled in the parent group, to undo once the command has exited.
+    supervisor: Option<(PathBuf, Vec<&'static str>)>,
+}
+
+impl Cgroup {
+    /// Creates a group with the limits under the group of the current
+    /// process, which must be delegated to the current user.
+    pub fn create(limits: &CgroupLimits) -> Result<Self, Box<dyn Error>> {
+        let mounts = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+        if crate::files::mount_file_system(&mounts, Path::new(CGROUP_ROOT)) != Some("cgroup2") {
+            return Err(format!(
+                "cgroup limits require the cgroup v2 hierarchy mounted at {}",
+                CGROUP_ROOT
+            )
+            .into());
+        }
+        let membership = std::fs::read_to_string("/proc/self/cgroup")?;
+        let own = own_cgroup(&membership).ok_or("The process is not in a cgroup v2 group")?;
+        Self::create_in(&Path::new(CGROUP_ROOT).join(own), limits)
+    }
+
+    /// Creates the group under the given parent group directory. Processes
+    /// can't stay in a group delegating controllers, so when the parent has
+    /// processes of its own, the current process first moves to a leaf group.
+    pub fn create_in(parent: &Path, limits: &CgroupLimits) -> Result<Self, Box<dyn Error>> {
+        let pid = std::process::id();
+        let controllers = [
+            limits.memory_max.as_ref().map(|_| "+memory"),
+            limits.cpu_max.map(|_| "+cpu"),
+        ];
+        let controllers: Vec<&'static str> = controllers.into_iter().flatten().collect();
+        let subtree_control = parent.join("cgroup.subtree_control");
+        let mut supervisor = None;
+        if std::fs::write(&subtree_control, controllers.join(" ")).is_err() {
+            let leaf = parent.join(format!("resolve-aws-secrets-{}-supervisor", pid));
+            std::fs::create_dir_all(&leaf)?;
+            std::fs::write(leaf.join("cgroup.procs"), pid.to_string())?;
+            std::fs::write(&subtree_control, controllers.join(" ")).map_err(|e| {
+                format!(
+                    "Failed to enable {} in {}, is the group delegated to this user? {}",
+                    controllers.join(" "),
+                    parent.display(),
+                    e
+                )
+            })?;
+            supervisor = Some((leaf, controllers.clone()));
+        }
+
+        let cgroup = Cgroup {
+            dir: parent.join(format!("resolve-aws-secrets-{}", pid)),
+            supervisor,
+        };
+        std::fs::create_dir(&cgroup.dir)
+            .map_err(|e| format!("Failed to create {}: {}", cgroup.dir.display(), e))?;
+        if let Some(memory_max) = &limits.memory_max {
+            cgroup.write("memory.max", memory_max)?;
+        }
+        if let Some(cpus) = limits.cpu_max {
+            cgroup.write("cpu.max", &cpu_max(cpus))?;
+        }
+        info!("Running the command in cgroup {}", cgroup.dir.display());
+        Ok(cgroup)
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.dir.join(file);
+        std::fs::write(&path, value)
+            .map_err(|e| format!("Failed to set {} to {}: {}", path.display(), value, e).into())
+    }
+
+    /// Moves the command process into the group before it executes the
+    /// program.
+    #[cfg(unix)]
+    pub fn apply(&self, command: &mut std::process::Command) -> Result<(), Box<dyn Error>> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::process::CommandExt;
+
+        let procs = std::ffi::CString::new(self.dir.join("cgroup.procs").as_os_str().as_bytes())?;
+        // SAFETY: the closure only calls open, write and close, which are
+        // async-signal-safe, on a string allocated beforehand. Writing 0 to
+        // cgroup.procs moves the writing process.
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                let error = std::io::Error::last_os_error();
+                libc::close(fd);
+                if written != 1 {
+                    return Err(error);
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _command: &mut std::process::Command) -> Result<(), Box<dyn Error>> {
+        Err("cgroup limits are only supported on Linux".into())
+    }
+}
+
+impl Drop for Cgroup {
+    /// Removes the group, then moves the current process back to the parent
+    /// group, which first stops delegating the controllers, and removes the
+    /// leaf group it was in.
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.dir);
+        let Some((leaf, controllers)) = &self.supervisor else {
+            return;
+        };
+        let Some(parent) = leaf.parent() else {
+            return;
+        };
+        let disabled: Vec<String> = controllers
+            .iter()
+            .map(|controller| controller.replacen('+', "-", 1))
+            .collect();
+        let result = std::fs::write(parent.join("cgroup.subtree_control"), disabled.join(" "))
+            .and_then(|_| {
+                std::fs::write(parent.join("cgroup.procs"), std::process::id().to_string())
+            })
+            .and_then(|_| std::fs::remove_dir(leaf));
+        if let Err(e) = result {
+            warn!("Failed to remove cgroup {}: {}", leaf.display(), e);
+        }
+    }
+}
+
+/// Finds the cgroup v2 group of a process in its `/proc/<pid>/cgroup` file.
+pub fn own_cgroup(membership: &str) -> Option<&str> {
+    membership
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.trim_start_matches('/'))
+}
+
+/// Formats a number of CPUs as the quota and period of `cpu.max`.
+pub fn cpu_max(cpus: f64) -> String {
+    const PERIOD: u64 = 100_000;
+    format!("{} {}", (cpus * PERIOD as f64).round() as u64, PERIOD)
+}
@@ -0,0 +1,88 @@
+//! Fault injection with `--chaos`, failing or delaying selected references
+//! so launch policies such as fallbacks and deadlines can be tested without
+//! breaking real secrets.
+
+use crate::cli::parse_duration;
+use crate::environment_processor::Reference;
+use crate::logging::warn;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Faults to inject, given as comma-separated `deny=<pattern>` and
+/// `delay=<duration>[@<pattern>]` directives. Patterns match the names of the
+/// variables or mapping keys declaring the references, with `*` matching any
+/// characters, and a delay without a pattern applies to every reference.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chaos {
+    pub deny: Vec<String>,
+    pub delays: Vec<(Duration, String)>,
+}
+
+impl FromStr for Chaos {
+    type Err = Box<dyn Error>;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Chaos::default();
+        for directive in spec.split(',').filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some(("deny", pattern)) => chaos.deny.push(pattern.to_string()),
+                Some(("delay", delay)) => {
+                    let (duration, pattern) = delay.split_once('@').unwrap_or((delay, "*"));
+                    chaos
+                        .delays
+                        .push((parse_duration(duration)?, pattern.to_string()));
+                }
+                _ => return Err(format!("unknown chaos directive {}", directive).into()),
+            }
+        }
+        Ok(chaos)
+    }
+}
+
+impl Chaos {
+    /// Delays the resolution of the reference, then fails it, as configured.
+    pub async fn inject(&self, reference: &Reference) -> Result<(), Box<dyn Error>> {
+        for (delay, pattern) in &self.delays {
+            if matches(pattern, &reference.variable) {
+                warn!(
+                    "Injecting a {:?} delay into the resolution of {}",
+                    delay, reference.variable
+                );
+                tokio::time::sleep(*delay).await;
+            }
+        }
+        if self
+            .deny
+            .iter()
+            .any(|pattern| matches(pattern, &reference.variable))
+        {
+            warn!(
+                "Injecting a failure into the resolution of {}",
+                reference.variable
+            );
+            return Err("denied by --chaos fault injection".into());
+        }
+        Ok(())
+    }
+}
+
+/// Matches a name against a pattern where `*` matches any characters.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
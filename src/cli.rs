@@ -0,0 +1,970 @@
+use crate::bench::DEFAULT_ITERATIONS;
+use crate::cgroup::CgroupLimits;
+use crate::copy::CopyMetadata;
+use crate::db_credentials::{CredentialFile, CredentialFileKind};
+use crate::environment_processor::ResolveOptions;
+use crate::errors::ErrorFormat;
+use crate::files::PersistentFiles;
+use crate::lockfile::DEFAULT_LOCKFILE;
+use crate::logging::LogFormat;
+use crate::output::OutputFormat;
+use crate::rlimits::Rlimit;
+use crate::rotation_report::DEFAULT_MAX_AGE;
+use crate::watch::{self, OnChange};
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const RESOLVE_OPTIONS: &str =
+    "  --strict                      fail on malformed references instead of skipping them
+  --no-decrypt                  don't decrypt SecureString SSM parameters
+  --fallback-previous           retry with AWSPREVIOUS when AWSCURRENT can't be retrieved
+  --rotation-wait <duration>    wait for in-progress rotations to finish
+  --resolve-deadline <duration> fail the references still pending after this long
+  --on-deadline <policy>        fail, or continue without the references pending at the deadline
+  --missing <policy>            fail, warn and continue without, or set empty the secrets that don't exist or can't be accessed
+  --call-timeout <duration>     give up on an attempt of an AWS call after this long
+  --concurrency <count>         resolve up to this many references at the same time, 10 by default
+  --multiline <policy>          pass, reject or escape values spanning multiple lines
+  --trim <policy>               trim trailing newlines or surrounding whitespace from values
+  --binary <policy>             fail on, base64-encode or write to a file binary secrets that aren't UTF-8
+  --allow-empty                 accept empty values instead of failing
+  --warn-suspicious             warn about values that look like ARNs or placeholders
+  --wait-for-credentials <duration>  retry acquiring credentials for this long at boot
+  --set <profile>               use this profile of the mapping, instead of SECRETS_PROFILE
+  --environment-tag <key>       select the mapping parameter by this ECS task or EC2 instance tag
+  --report-api-calls            print the AWS API calls made and their estimated cost to stderr
+  --chaos <faults>              fail or delay references for resilience testing, e.g. deny=SECRET_DB_*,delay=2s
+  --error-format <format>       report fatal errors as text or as a JSON object
+  --log-format <format>         write the logs as text or as JSON objects, or LOG_FORMAT
+  --keychain-cache <duration>   cache the resolved values in the OS credential store for this long
+  --disk-cache <duration>       cache the retrieved values encrypted on disk for this long
+  --disk-cache-dir <path>       directory of the disk cache, ~/.cache/resolve-aws-secrets by default
+  --disk-cache-identity <path>  age identity encrypting the disk cache, created when missing
+  --test-harness <path>         serve the secrets and parameters of this fixtures file instead of calling AWS
+  --max-attempts <count>        attempts per AWS call before giving up, 3 by default, or AWS_MAX_ATTEMPTS
+  --initial-backoff <duration>  base of the exponential backoff between attempts, 1s by default
+  --max-backoff <duration>      longest wait between attempts, 20s by default
+  --retry-mode <mode>           standard, or adaptive to slow down once throttled, or AWS_RETRY_MODE
+  --endpoint-url <url>          send the AWS calls to this endpoint, such as LocalStack, or AWS_ENDPOINT_URL
+  --assume-role <arn>           assume this role before retrieving anything, or SECRETS_ASSUME_ROLE_ARN
+  --external-id <id>            external id of the assumed role, or SECRETS_ASSUME_ROLE_EXTERNAL_ID
+  --session-name <name>         session name of the assumed role, or SECRETS_ASSUME_ROLE_SESSION_NAME
+  --roles-anywhere-certificate <path>  authenticate with IAM Roles Anywhere using this certificate
+  --roles-anywhere-private-key <path>  private key of the Roles Anywhere certificate
+  --roles-anywhere-trust-anchor <arn>  Roles Anywhere trust anchor
+  --roles-anywhere-profile <arn>       Roles Anywhere profile
+  --roles-anywhere-role <arn>          role to assume with Roles Anywhere";
+
+const RUN_OPTIONS: &str =
+    "  --print-env                   print the masked environment instead of running the program
+  --dry-run                     resolve and report the variables that would be set instead of running the program
+  --output <format>             write the variables as dotenv, json or shell instead of running a program
+  --provenance                  include the source, reference and version of each variable in the JSON output
+  --out-file <path>             write the output to this private file instead of stdout
+  --locked                      retrieve exactly the versions pinned in the lockfile
+  --lockfile <path>             lockfile to use, resolve-aws-secrets.lock by default
+  --previous-on-exit-code <code> rerun once with AWSPREVIOUS secrets on this exit code
+  --pgpass <key>[=<path>]       write the JSON database secret <key> as a .pgpass file
+  --my-cnf <key>[=<path>]       write the JSON database secret <key> as a .my.cnf file
+  --persistent-files <policy>   warn, refuse or allow writing secret files to persistent storage
+  --stdin-secret <key>          write the resolved value of <key> to the stdin of the program
+  --stdin-newline               end the value written to stdin with a newline
+  --fifo <key>                  deliver <key> through a named pipe given by <key>_FIFO instead of the environment
+  --template <path>             render this template, replacing {{KEY}} with the resolved values
+  --render-to <path>            write the rendered template to this private file
+  --rlimit <resource>=<soft>[:<hard>]  set a resource limit of the program, such as nofile=65536
+  --cgroup-memory-max <bytes>   run the program in a cgroup v2 group with this memory limit
+  --cgroup-cpu-max <cpus>       run the program in a cgroup v2 group with this CPU limit
+  --seccomp-profile <path>      apply this Docker seccomp profile to the program
+  --status-file <path>          write the failed phase, exit code and timings as JSON on exit
+  --no-exec                     run the program as a child instead of replacing the tool with it
+  --init                        run the program as a child and reap orphaned processes, the default as PID 1
+  --watch                       resolve the variables again every 5 minutes and restart the program when they change
+  --refresh-interval <duration> resolve the variables again this often in watch mode
+  --on-change <policy>          restart the program, rewrite its files and send it a signal, e.g. signal:SIGHUP, or replace it
+  --ready-check <command>       command telling that the replacement program is ready with --on-change replace
+  --ready-timeout <duration>    how long the replacement program has to pass the readiness check, 60s by default
+  --forbid-argv-secrets         fail on {{SECRET:KEY}} tokens in the arguments instead of substituting them";
+
+const COPY_OPTIONS: &str =
+    "  --region <region>             region to copy the secret to
+  --role <arn>                  role to assume for creating the copy, in another account
+  --name <name>                 name of the copy, the name of the secret by default
+  --kms-key-id <key>            KMS key encrypting the copy, the default key of the account by default
+  --metadata <list>             metadata to copy: description, tags or none, both by default
+  --overwrite                   store the value as a new version when the copy already exists";
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [run] [options] [--] <program> [args...]
+       {program} lint [--file <path> | --stdin | --parameter <name-or-arn>]
+       {program} push-mapping [--parameter <name-or-arn>] [--yes] <path>
+       {program} lock [resolve options] [--lockfile <path>]
+       {program} update [resolve options] [--lockfile <path>]
+       {program} gen-ecs [resolve options]
+       {program} rotation-report [resolve options] [--max-age <duration>]
+       {program} bench [resolve options] [--iterations <count>]
+       {program} edit <secret-id>
+       {program} copy [resolve options] [copy options] <secret-id>
+       {program} get [resolve options] [--key <json-key>] [--ssm] [--raw] [--reveal] <reference>
+
+A program named like a subcommand must follow run or --, as in {program} run lint.
+
+Resolve options:
+{RESOLVE_OPTIONS}
+
+Run options (in addition to the resolve options):
+{RUN_OPTIONS}
+
+Copy options:
+{COPY_OPTIONS}"
+    )
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run(Box<RunArgs>),
+    Lint(LintArgs),
+    Lock(LockArgs),
+    GenEcs(ResolveOptions),
+    RotationReport(RotationReportArgs),
+    Bench(BenchArgs),
+    Edit(EditArgs),
+    Get(GetArgs),
+    Copy(CopyArgs),
+    PushMapping(PushMappingArgs),
+    Help,
+}
+
+impl Command {
+    /// The resolve options of the commands resolving references.
+    fn resolve_options(&self) -> Option<&ResolveOptions> {
+        match self {
+            Command::Run(run_args) => Some(&run_args.resolve),
+            Command::Lock(lock_args) => Some(&lock_args.resolve),
+            Command::GenEcs(options) => Some(options),
+            Command::RotationReport(report_args) => Some(&report_args.resolve),
+            Command::Bench(bench_args) => Some(&bench_args.resolve),
+            Command::Get(get_args) => Some(&get_args.resolve),
+            Command::Copy(copy_args) => Some(&copy_args.resolve),
+            Command::Lint(lint_args) => Some(&lint_args.resolve),
+            Command::PushMapping(push_args) => Some(&push_args.resolve),
+            Command::Edit(edit_args) => Some(&edit_args.resolve),
+            Command::Help => None,
+        }
+    }
+
+    /// How the fatal errors of the command are reported.
+    pub fn error_format(&self) -> ErrorFormat {
+        self.resolve_options()
+            .map_or(ErrorFormat::Text, |options| options.error_format)
+    }
+
+    /// Format of the logs given on the command line, if any.
+    pub fn log_format(&self) -> Option<LogFormat> {
+        self.resolve_options()
+            .and_then(|options| options.log_format)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunArgs {
+    pub resolve: ResolveOptions,
+    /// Print the masked child environment instead of executing the command.
+    pub print_env: bool,
+    /// Report the variables that would be set and their references instead
+    /// of executing the command, to validate the configuration.
+    pub dry_run: bool,
+    /// Write the resolved variables in this format instead of executing a
+    /// command.
+    pub output: Option<OutputFormat>,
+    /// Include the source, reference and version of each variable in the
+    /// JSON output.
+    pub provenance: bool,
+    /// File the output is written to, instead of stdout.
+    pub out_file: Option<PathBuf>,
+    pub lockfile: PathBuf,
+    pub credential_files: Vec<CredentialFile>,
+    /// What to do when secret files would be written to persistent storage.
+    pub persistent_files: PersistentFiles,
+    /// Key of the resolved value to write to the stdin of the command.
+    pub stdin_secret: Option<String>,
+    /// End the value written to stdin with a newline.
+    pub stdin_newline: bool,
+    /// Keys of the resolved values delivered through named pipes instead of
+    /// the environment.
+    pub fifos: Vec<String>,
+    /// Fail on `{{SECRET:KEY}}` tokens in the command arguments, to enforce
+    /// passing secrets through the environment, files or descriptors only.
+    pub forbid_argv_secrets: bool,
+    /// Configuration files rendered from templates before the command runs.
+    pub templates: Vec<ConfigTemplate>,
+    /// Resource limits set for the command.
+    pub rlimits: Vec<Rlimit>,
+    /// Limits of the cgroup v2 group the command is placed in, if any.
+    pub cgroup_limits: CgroupLimits,
+    /// Docker seccomp profile applied to the command.
+    pub seccomp_profile: Option<PathBuf>,
+    /// File the failed phase, exit code and timings are written to on exit.
+    pub status_file: Option<PathBuf>,
+    /// Exit code with which the command signals that it rejected the
+    /// credentials, to rerun it once with the AWSPREVIOUS secret versions.
+    pub previous_on_exit_code: Option<i32>,
+    /// Replace the current process with the command instead of supervising
+    /// it, on Unix, unless an option needs the tool to outlive the command.
+    pub exec: bool,
+    /// Reap the orphaned processes left by the command, as an init process
+    /// does, also enabled when the tool runs as PID 1.
+    pub init: bool,
+    /// Resolve the references again this often, restarting the command when
+    /// the values change.
+    pub refresh_interval: Option<Duration>,
+    /// Whether watch mode restarts, signals or replaces the command.
+    pub on_change: OnChange,
+    /// Command whose success tells that the replacement command is ready.
+    pub ready_check: String,
+    /// How long the replacement command has to pass the readiness check.
+    pub ready_timeout: Duration,
+    pub command: Vec<String>,
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        RunArgs {
+            resolve: ResolveOptions::default(),
+            print_env: false,
+            dry_run: false,
+            output: None,
+            provenance: false,
+            out_file: None,
+            lockfile: DEFAULT_LOCKFILE.into(),
+            credential_files: Vec::new(),
+            persistent_files: PersistentFiles::default(),
+            stdin_secret: None,
+            stdin_newline: false,
+            fifos: Vec::new(),
+            forbid_argv_secrets: false,
+            templates: Vec::new(),
+            rlimits: Vec::new(),
+            cgroup_limits: CgroupLimits::default(),
+            seccomp_profile: None,
+            status_file: None,
+            previous_on_exit_code: None,
+            exec: cfg!(unix),
+            init: false,
+            refresh_interval: None,
+            on_change: OnChange::default(),
+            ready_check: String::new(),
+            ready_timeout: watch::DEFAULT_READY_TIMEOUT,
+            command: Vec::new(),
+        }
+    }
+}
+
+/// A configuration file template whose `{{KEY}}` placeholders are replaced
+/// with the resolved values, for programs that only read files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigTemplate {
+    pub template: PathBuf,
+    /// Path the rendered file is written to.
+    pub render_to: PathBuf,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MappingSource {
+    File(PathBuf),
+    Stdin,
+    Parameter(String),
+}
+
+#[derive(Debug)]
+pub struct LintArgs {
+    pub resolve: ResolveOptions,
+    pub source: MappingSource,
+}
+
+#[derive(Debug)]
+pub struct LockArgs {
+    pub resolve: ResolveOptions,
+    pub lockfile: PathBuf,
+    /// Refresh every reference to its current version instead of keeping the
+    /// versions already pinned.
+    pub update: bool,
+}
+
+#[derive(Debug)]
+pub struct RotationReportArgs {
+    pub resolve: ResolveOptions,
+    /// Age beyond which a secret that wasn't rotated is flagged as stale.
+    pub max_age: Duration,
+}
+
+#[derive(Debug)]
+pub struct BenchArgs {
+    pub resolve: ResolveOptions,
+    /// Warm resolutions of every reference after the cold one.
+    pub iterations: usize,
+}
+
+#[derive(Debug)]
+pub struct EditArgs {
+    pub resolve: ResolveOptions,
+    /// Name or ARN of the JSON secret to edit.
+    pub secret_id: String,
+}
+
+#[derive(Debug)]
+pub struct GetArgs {
+    pub resolve: ResolveOptions,
+    /// Secret ARN or name, `consul://` or `etcd://` key, or SSM parameter
+    /// name with `ssm`.
+    pub reference: String,
+    /// Key to extract from a secret holding a JSON object.
+    pub json_key: Option<String>,
+    /// Resolve the reference as an SSM parameter name.
+    pub ssm: bool,
+    /// Print the value without a trailing newline.
+    pub raw: bool,
+    /// Print the value even when the output is a terminal.
+    pub reveal: bool,
+}
+
+#[derive(Debug)]
+pub struct CopyArgs {
+    pub resolve: ResolveOptions,
+    /// Name or ARN of the secret to copy.
+    pub secret_id: String,
+    /// Name of the copy, instead of the name of the secret.
+    pub name: Option<String>,
+    /// Region to create the copy in.
+    pub region: Option<String>,
+    /// Role to assume for creating the copy.
+    pub role: Option<String>,
+    pub kms_key_id: Option<String>,
+    pub metadata: CopyMetadata,
+    /// Store the value as a new version when the copy already exists.
+    pub overwrite: bool,
+}
+
+#[derive(Debug)]
+pub struct PushMappingArgs {
+    pub resolve: ResolveOptions,
+    /// Local mapping file to publish.
+    pub file: PathBuf,
+    /// SSM parameter the mapping is written to.
+    pub parameter: String,
+    /// Write the mapping without asking for confirmation.
+    pub yes: bool,
+}
+
+/// Parses the command line, without the program name. Anything that isn't a
+/// known subcommand is treated as the wrapped command of an implicit `run`.
+pub fn parse_args(args: &[String]) -> Result<Command, Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("--help") | Some("-h") => Ok(Command::Help),
+        Some("run") => Ok(Command::Run(Box::new(parse_run_args(&args[1..])?))),
+        Some("lint") => Ok(Command::Lint(parse_lint_args(&args[1..])?)),
+        Some("push-mapping") => Ok(Command::PushMapping(parse_push_mapping_args(&args[1..])?)),
+        Some("lock") => Ok(Command::Lock(parse_lock_args(&args[1..], false)?)),
+        Some("update") => Ok(Command::Lock(parse_lock_args(&args[1..], true)?)),
+        Some("gen-ecs") => Ok(Command::GenEcs(parse_gen_ecs_args(&args[1..])?)),
+        Some("rotation-report") => Ok(Command::RotationReport(parse_rotation_report_args(
+            &args[1..],
+        )?)),
+        Some("bench") => Ok(Command::Bench(parse_bench_args(&args[1..])?)),
+        Some("edit") => Ok(Command::Edit(parse_edit_args(&args[1..])?)),
+        Some("get") => Ok(Command::Get(parse_get_args(&args[1..])?)),
+        Some("copy") => Ok(Command::Copy(parse_copy_args(&args[1..])?)),
+        _ => Ok(Command::Run(Box::new(parse_run_args(args)?))),
+    }
+}
+
+/// Builds the run arguments for the Lambda exec wrapper mode, where the
+/// arguments are the runtime entrypoint and are never parsed as options.
+pub fn exec_wrapper_args(args: &[String]) -> Result<RunArgs, Box<dyn Error>> {
+    if args.is_empty() {
+        return Err("No runtime entrypoint was given by the Lambda runtime".into());
+    }
+    Ok(RunArgs {
+        exec: true,
+        command: args.to_vec(),
+        ..RunArgs::default()
+    })
+}
+
+/// Parses the wrapper options that precede the wrapped command. Option parsing
+/// stops at the first argument that isn't an option, or after a literal `--`,
+/// so the arguments of the wrapped command are always passed through untouched.
+pub fn parse_run_args(args: &[String]) -> Result<RunArgs, Box<dyn Error>> {
+    let mut run_args = RunArgs::default();
+    let mut args = args.iter();
+    let mut template = None;
+    let mut on_change = false;
+    let mut ready_timeout = false;
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut run_args.resolve)? {
+            continue;
+        }
+        if template.is_some() && arg != "--render-to" {
+            return Err("--template must be followed by --render-to".into());
+        }
+        match arg.as_str() {
+            "--" => break,
+            "--template" => template = Some(PathBuf::from(option_value(arg, args.next())?)),
+            "--render-to" => run_args.templates.push(ConfigTemplate {
+                template: template
+                    .take()
+                    .ok_or("--render-to must follow --template")?,
+                render_to: option_value(arg, args.next())?.into(),
+            }),
+            "--print-env" => run_args.print_env = true,
+            "--dry-run" => run_args.dry_run = true,
+            "--output" => run_args.output = Some(option_value(arg, args.next())?.parse()?),
+            "--provenance" => run_args.provenance = true,
+            "--out-file" => run_args.out_file = Some(option_value(arg, args.next())?.into()),
+            "--forbid-argv-secrets" => run_args.forbid_argv_secrets = true,
+            "--stdin-secret" => run_args.stdin_secret = Some(option_value(arg, args.next())?),
+            "--rlimit" => run_args
+                .rlimits
+                .push(option_value(arg, args.next())?.parse()?),
+            "--cgroup-memory-max" => {
+                run_args.cgroup_limits.memory_max = Some(option_value(arg, args.next())?);
+            }
+            "--cgroup-cpu-max" => {
+                let cpus = option_value(arg, args.next())?;
+                run_args.cgroup_limits.cpu_max = Some(
+                    cpus.parse()
+                        .ok()
+                        .filter(|cpus: &f64| *cpus > 0.0)
+                        .ok_or_else(|| format!("Invalid number of CPUs for {}: {}", arg, cpus))?,
+                );
+            }
+            "--seccomp-profile" => {
+                run_args.seccomp_profile = Some(option_value(arg, args.next())?.into());
+            }
+            "--status-file" => run_args.status_file = Some(option_value(arg, args.next())?.into()),
+            "--stdin-newline" => run_args.stdin_newline = true,
+            "--no-exec" => run_args.exec = false,
+            "--init" => run_args.init = true,
+            "--watch" => {
+                run_args.refresh_interval = run_args
+                    .refresh_interval
+                    .or(Some(watch::DEFAULT_REFRESH_INTERVAL));
+            }
+            "--on-change" => {
+                run_args.on_change = option_value(arg, args.next())?.parse()?;
+                on_change = true;
+            }
+            "--ready-check" => run_args.ready_check = option_value(arg, args.next())?,
+            "--ready-timeout" => {
+                run_args.ready_timeout = parse_duration(&option_value(arg, args.next())?)?;
+                ready_timeout = true;
+            }
+            "--refresh-interval" => {
+                run_args.refresh_interval = Some(parse_duration(&option_value(arg, args.next())?)?);
+            }
+            "--fifo" => run_args.fifos.push(option_value(arg, args.next())?),
+            "--locked" => run_args.resolve.locked = true,
+            "--lockfile" => run_args.lockfile = option_value(arg, args.next())?.into(),
+            "--previous-on-exit-code" => {
+                let code = option_value(arg, args.next())?;
+                run_args.previous_on_exit_code = Some(
+                    code.parse()
+                        .map_err(|_| format!("Invalid exit code for {}: {}", arg, code))?,
+                );
+            }
+            "--pgpass" => run_args.credential_files.push(CredentialFile::parse(
+                CredentialFileKind::PgPass,
+                &option_value(arg, args.next())?,
+            )),
+            "--my-cnf" => run_args.credential_files.push(CredentialFile::parse(
+                CredentialFileKind::MyCnf,
+                &option_value(arg, args.next())?,
+            )),
+            "--persistent-files" => {
+                run_args.persistent_files = option_value(arg, args.next())?.parse()?;
+            }
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown option: {}", option).into());
+            }
+            _ => {
+                run_args.command.push(arg.clone());
+                break;
+            }
+        }
+    }
+    run_args.command.extend(args.cloned());
+
+    if template.is_some() {
+        return Err("--template must be followed by --render-to".into());
+    }
+    if run_args.output.is_some() && !run_args.command.is_empty() {
+        return Err(format!(
+            "--output writes the variables instead of running a program, {} was given",
+            run_args.command[0]
+        )
+        .into());
+    }
+    if run_args.out_file.is_some() && run_args.output.is_none() {
+        return Err("--out-file requires --output".into());
+    }
+    if run_args.provenance && run_args.output != Some(OutputFormat::Json) {
+        return Err("--provenance requires --output json".into());
+    }
+    if on_change && run_args.refresh_interval.is_none() {
+        return Err("--on-change requires --watch".into());
+    }
+    let replace = run_args.on_change == OnChange::Replace;
+    if replace && run_args.ready_check.trim().is_empty() {
+        return Err("--on-change replace requires --ready-check".into());
+    }
+    if !replace && (!run_args.ready_check.is_empty() || ready_timeout) {
+        return Err("--ready-check and --ready-timeout require --on-change replace".into());
+    }
+    if replace && (run_args.init || !run_args.cgroup_limits.is_empty()) {
+        return Err(
+            "--on-change replace can't be combined with --init or the cgroup options".into(),
+        );
+    }
+    if run_args.refresh_interval.is_some() {
+        if cfg!(not(unix)) {
+            return Err("--watch is only supported on Unix".into());
+        }
+        if run_args.command.is_empty() || run_args.print_env || run_args.dry_run {
+            return Err("--watch requires a program to run".into());
+        }
+        if run_args.refresh_interval == Some(Duration::ZERO) {
+            return Err("--refresh-interval must be positive".into());
+        }
+    }
+    if run_args.command.is_empty()
+        && !run_args.print_env
+        && !run_args.dry_run
+        && run_args.output.is_none()
+    {
+        return Err("No program to execute was given".into());
+    }
+
+    Ok(run_args)
+}
+
+/// Parses the `lint` options. Without an explicit source, the live mapping
+/// parameter configured through the environment is linted.
+pub fn parse_lint_args(args: &[String]) -> Result<LintArgs, Box<dyn Error>> {
+    let mut resolve = ResolveOptions::default();
+    let mut source = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut resolve)? {
+            continue;
+        }
+        source = Some(match arg.as_str() {
+            "--file" => MappingSource::File(option_value(arg, args.next())?.into()),
+            "--stdin" => MappingSource::Stdin,
+            "--parameter" => MappingSource::Parameter(option_value(arg, args.next())?),
+            other => return Err(format!("Unknown lint argument: {}", other).into()),
+        });
+    }
+
+    let source = match source {
+        Some(source) => source,
+        None => MappingSource::Parameter(mapping_parameter().ok_or(
+            "No mapping to lint: use --file, --stdin, --parameter or set SECRETS_PARAMETER_NAME",
+        )?),
+    };
+
+    Ok(LintArgs { resolve, source })
+}
+
+/// Parses the `push-mapping` options and the mapping file to publish.
+pub fn parse_push_mapping_args(args: &[String]) -> Result<PushMappingArgs, Box<dyn Error>> {
+    let mut resolve = ResolveOptions::default();
+    let mut file = None;
+    let mut parameter = None;
+    let mut yes = false;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--parameter" => parameter = Some(option_value(arg, args.next())?),
+            "--yes" => yes = true,
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown push-mapping argument: {}", option).into());
+            }
+            _ if file.is_some() => {
+                return Err(format!("Unexpected push-mapping argument: {}", arg).into());
+            }
+            _ => file = Some(PathBuf::from(arg)),
+        }
+    }
+
+    Ok(PushMappingArgs {
+        resolve,
+        file: file.ok_or("No mapping file to push was given")?,
+        parameter: parameter.or_else(mapping_parameter).ok_or(
+            "No parameter to push the mapping to: use --parameter or set SECRETS_PARAMETER_NAME",
+        )?,
+        yes,
+    })
+}
+
+/// The mapping parameter configured through the environment.
+fn mapping_parameter() -> Option<String> {
+    std::env::var("SECRETS_PARAMETER_NAME")
+        .or_else(|_| std::env::var("SECRETS_PARAMETER_ARN"))
+        .ok()
+}
+
+/// Parses the options shared by `lock` and `update`.
+pub fn parse_lock_args(args: &[String], update: bool) -> Result<LockArgs, Box<dyn Error>> {
+    let mut lock_args = LockArgs {
+        resolve: ResolveOptions::default(),
+        lockfile: DEFAULT_LOCKFILE.into(),
+        update,
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut lock_args.resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--lockfile" => lock_args.lockfile = option_value(arg, args.next())?.into(),
+            other => return Err(format!("Unknown lock argument: {}", other).into()),
+        }
+    }
+
+    Ok(lock_args)
+}
+
+/// Parses the `gen-ecs` options.
+pub fn parse_gen_ecs_args(args: &[String]) -> Result<ResolveOptions, Box<dyn Error>> {
+    let mut options = ResolveOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if !parse_resolve_option(arg, &mut args, &mut options)? {
+            return Err(format!("Unknown gen-ecs argument: {}", arg).into());
+        }
+    }
+
+    Ok(options)
+}
+
+/// Parses the `rotation-report` options.
+pub fn parse_rotation_report_args(args: &[String]) -> Result<RotationReportArgs, Box<dyn Error>> {
+    let mut report_args = RotationReportArgs {
+        resolve: ResolveOptions::default(),
+        max_age: DEFAULT_MAX_AGE,
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut report_args.resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--max-age" => report_args.max_age = parse_duration(&option_value(arg, args.next())?)?,
+            other => return Err(format!("Unknown rotation-report argument: {}", other).into()),
+        }
+    }
+
+    Ok(report_args)
+}
+
+/// Parses the `bench` options.
+pub fn parse_bench_args(args: &[String]) -> Result<BenchArgs, Box<dyn Error>> {
+    let mut bench_args = BenchArgs {
+        resolve: ResolveOptions::default(),
+        iterations: DEFAULT_ITERATIONS,
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut bench_args.resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--iterations" => {
+                let count = option_value(arg, args.next())?;
+                bench_args.iterations = count
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| format!("Invalid iteration count for {}: {}", arg, count))?;
+            }
+            other => return Err(format!("Unknown bench argument: {}", other).into()),
+        }
+    }
+
+    Ok(bench_args)
+}
+
+/// Parses the `edit` arguments.
+pub fn parse_edit_args(args: &[String]) -> Result<EditArgs, Box<dyn Error>> {
+    let mut resolve = ResolveOptions::default();
+    let mut secret_id = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown edit argument: {}", option).into());
+            }
+            _ if secret_id.is_some() => {
+                return Err(format!("Unexpected edit argument: {}", arg).into());
+            }
+            _ => secret_id = Some(arg.clone()),
+        }
+    }
+
+    Ok(EditArgs {
+        resolve,
+        secret_id: secret_id.ok_or("No secret to edit was given")?,
+    })
+}
+
+/// Parses the `get` options and the reference to print.
+pub fn parse_get_args(args: &[String]) -> Result<GetArgs, Box<dyn Error>> {
+    let mut resolve = ResolveOptions::default();
+    let mut reference = None;
+    let mut json_key = None;
+    let (mut ssm, mut raw, mut reveal) = (false, false, false);
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--key" => json_key = Some(option_value(arg, args.next())?),
+            "--ssm" => ssm = true,
+            "--raw" => raw = true,
+            "--reveal" => reveal = true,
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown get argument: {}", option).into());
+            }
+            _ if reference.is_some() => {
+                return Err(format!("Unexpected get argument: {}", arg).into());
+            }
+            _ => reference = Some(arg.clone()),
+        }
+    }
+
+    Ok(GetArgs {
+        resolve,
+        reference: reference.ok_or("No reference to get was given")?,
+        json_key,
+        ssm,
+        raw,
+        reveal,
+    })
+}
+
+/// Parses the `copy` options and the secret to copy.
+pub fn parse_copy_args(args: &[String]) -> Result<CopyArgs, Box<dyn Error>> {
+    let mut copy_args = CopyArgs {
+        resolve: ResolveOptions::default(),
+        secret_id: String::new(),
+        name: None,
+        region: None,
+        role: None,
+        kms_key_id: None,
+        metadata: CopyMetadata::default(),
+        overwrite: false,
+    };
+    let mut secret_id = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if parse_resolve_option(arg, &mut args, &mut copy_args.resolve)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--name" => copy_args.name = Some(option_value(arg, args.next())?),
+            "--region" => copy_args.region = Some(option_value(arg, args.next())?),
+            "--role" => copy_args.role = Some(option_value(arg, args.next())?),
+            "--kms-key-id" => copy_args.kms_key_id = Some(option_value(arg, args.next())?),
+            "--metadata" => copy_args.metadata = option_value(arg, args.next())?.parse()?,
+            "--overwrite" => copy_args.overwrite = true,
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown copy argument: {}", option).into());
+            }
+            _ if secret_id.is_some() => {
+                return Err(format!("Unexpected copy argument: {}", arg).into());
+            }
+            _ => secret_id = Some(arg.clone()),
+        }
+    }
+
+    copy_args.secret_id = secret_id.ok_or("No secret to copy was given")?;
+    if copy_args.name.is_none() && copy_args.region.is_none() && copy_args.role.is_none() {
+        return Err("Give --region, --role or --name to copy the secret elsewhere".into());
+    }
+    Ok(copy_args)
+}
+
+/// Parses an option controlling how references are collected and resolved,
+/// shared by the subcommands that resolve secrets. Returns false for any
+/// other argument.
+fn parse_resolve_option(
+    arg: &str,
+    args: &mut std::slice::Iter<String>,
+    options: &mut ResolveOptions,
+) -> Result<bool, Box<dyn Error>> {
+    match arg {
+        "--strict" => options.strict = true,
+        "--no-decrypt" => options.no_decrypt = true,
+        "--fallback-previous" => options.fallback_previous = true,
+        "--allow-empty" => options.allow_empty = true,
+        "--warn-suspicious" => options.warn_suspicious = true,
+        "--report-api-calls" => options.report_api_calls = true,
+        "--set" => options.profile = Some(option_value(arg, args.next())?),
+        "--environment-tag" => options.environment_tag = Some(option_value(arg, args.next())?),
+        "--rotation-wait" => {
+            options.rotation_wait = Some(parse_duration(&option_value(arg, args.next())?)?);
+        }
+        "--resolve-deadline" => {
+            options.resolve_deadline = Some(parse_duration(&option_value(arg, args.next())?)?);
+        }
+        "--on-deadline" => options.on_deadline = option_value(arg, args.next())?.parse()?,
+        "--missing" => options.missing = option_value(arg, args.next())?.parse()?,
+        "--call-timeout" => {
+            options.call_timeout = Some(parse_duration(&option_value(arg, args.next())?)?);
+        }
+        "--concurrency" => {
+            let count = option_value(arg, args.next())?;
+            options.concurrency = Some(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| format!("Invalid concurrency for {}: {}", arg, count))?,
+            );
+        }
+        "--multiline" => options.multiline = option_value(arg, args.next())?.parse()?,
+        "--trim" => options.trim = option_value(arg, args.next())?.parse()?,
+        "--binary" => options.binary = option_value(arg, args.next())?.parse()?,
+        "--wait-for-credentials" => {
+            options.credentials_wait = Some(parse_duration(&option_value(arg, args.next())?)?);
+        }
+        "--chaos" => options.chaos = option_value(arg, args.next())?.parse()?,
+        "--error-format" => options.error_format = option_value(arg, args.next())?.parse()?,
+        "--log-format" => options.log_format = Some(option_value(arg, args.next())?.parse()?),
+        "--keychain-cache" => {
+            let ttl = parse_duration(&option_value(arg, args.next())?)?;
+            if cfg!(not(feature = "keychain")) {
+                return Err("--keychain-cache requires building with the keychain feature".into());
+            }
+            options.keychain_ttl = Some(ttl);
+        }
+        "--disk-cache" => {
+            let ttl = parse_duration(&option_value(arg, args.next())?)?;
+            if cfg!(not(feature = "disk-cache")) {
+                return Err("--disk-cache requires building with the disk-cache feature".into());
+            }
+            options.disk_cache.ttl = Some(ttl);
+        }
+        "--disk-cache-dir" => options.disk_cache.dir = Some(option_value(arg, args.next())?.into()),
+        "--disk-cache-identity" => {
+            options.disk_cache.identity = Some(option_value(arg, args.next())?.into());
+        }
+        "--test-harness" => {
+            let fixtures = option_value(arg, args.next())?;
+            if cfg!(not(feature = "test-harness")) {
+                return Err(
+                    "--test-harness requires building with the test-harness feature".into(),
+                );
+            }
+            options.test_harness = Some(fixtures.into());
+        }
+        "--max-attempts" => {
+            let count = option_value(arg, args.next())?;
+            options.retry.max_attempts = Some(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| format!("Invalid attempt count for {}: {}", arg, count))?,
+            );
+        }
+        "--initial-backoff" => {
+            options.retry.initial_backoff = Some(parse_duration(&option_value(arg, args.next())?)?);
+        }
+        "--max-backoff" => {
+            options.retry.max_backoff = Some(parse_duration(&option_value(arg, args.next())?)?);
+        }
+        "--retry-mode" => {
+            let mode = option_value(arg, args.next())?;
+            options.retry.mode = Some(
+                mode.parse()
+                    .map_err(|_| format!("Invalid retry mode for {}: {}", arg, mode))?,
+            );
+        }
+        "--endpoint-url" => options.endpoint_url = Some(option_value(arg, args.next())?),
+        "--assume-role" => options.assume_role.role_arn = Some(option_value(arg, args.next())?),
+        "--external-id" => {
+            options.assume_role.external_id = Some(option_value(arg, args.next())?);
+        }
+        "--session-name" => {
+            options.assume_role.session_name = Some(option_value(arg, args.next())?);
+        }
+        "--roles-anywhere-certificate" => {
+            options.roles_anywhere.certificate = Some(option_value(arg, args.next())?.into());
+        }
+        "--roles-anywhere-private-key" => {
+            options.roles_anywhere.private_key = Some(option_value(arg, args.next())?.into());
+        }
+        "--roles-anywhere-trust-anchor" => {
+            options.roles_anywhere.trust_anchor_arn = Some(option_value(arg, args.next())?);
+        }
+        "--roles-anywhere-profile" => {
+            options.roles_anywhere.profile_arn = Some(option_value(arg, args.next())?);
+        }
+        "--roles-anywhere-role" => {
+            options.roles_anywhere.role_arn = Some(option_value(arg, args.next())?);
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn option_value(option: &str, value: Option<&String>) -> Result<String, Box<dyn Error>> {
+    value
+        .cloned()
+        .ok_or_else(|| format!("Option {} requires a value", option).into())
+}
+
+/// Parses durations such as `500ms`, `30s`, `5m`, `1h` or `90d`, with plain
+/// numbers taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, Box<dyn Error>> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+
+    let multiplier = match unit {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("Invalid duration unit in {}", value).into()),
+    };
+    amount
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid duration: {}", value).into())
+}
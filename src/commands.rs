@@ -0,0 +1,631 @@
+use crate::aws_clients::{AssumeRole, LazyClient, LazyConfig};
+use crate::cli::{
+    BenchArgs, CopyArgs, EditArgs, GetArgs, LintArgs, LockArgs, MappingSource, PushMappingArgs,
+    RotationReportArgs, RunArgs,
+};
+use crate::environment_processor::{
+    collect_references, process_environment, ResolveOptions, ResolvedSecret,
+};
+use crate::lockfile::Lockfile;
+use crate::logging::{info, warn};
+use crate::secret_manager::SecretsManagerClientTrait;
+use crate::ssm_manager::{get_ssm_parameter, SsmClientTrait};
+#[cfg(feature = "test-harness")]
+use crate::test_harness;
+use crate::{
+    api_calls, aws_clients, bench, cgroup, copy, db_credentials, ecs, edit, fifo, files, get, lint,
+    output, push_mapping, rlimits, rotation_report, seccomp, signals, status_file, summary,
+    template, tls, watch,
+};
+use aws_config::credential_process::CredentialProcessProvider;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::retry::RetryConfig;
+use aws_config::timeout::TimeoutConfig;
+use aws_config::SdkConfig;
+use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+use aws_sdk_ssm::Client as SsmClient;
+use std::env;
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+/// Settings of the AWS configuration, checked from the resolve options.
+struct AwsSettings {
+    credentials: Option<CredentialProcessProvider>,
+    assume_role: Option<AssumeRole>,
+    endpoint_url: Option<String>,
+    retry_config: Option<RetryConfig>,
+    call_timeout: Option<Duration>,
+    credentials_wait: Option<Duration>,
+}
+
+impl AwsSettings {
+    fn new(options: &ResolveOptions) -> Result<Self, Box<dyn Error>> {
+        Ok(AwsSettings {
+            credentials: options.roles_anywhere.credentials_provider()?,
+            assume_role: options.assume_role.effective()?,
+            endpoint_url: options.endpoint_url.clone(),
+            retry_config: options.retry.retry_config()?,
+            call_timeout: options.call_timeout,
+            credentials_wait: options.credentials_wait,
+        })
+    }
+}
+
+async fn load_aws_config(settings: AwsSettings) -> SdkConfig {
+    info!("Initializing AWS configuration");
+    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+    let mut loader =
+        aws_config::defaults(aws_config::BehaviorVersion::latest()).region(region_provider);
+    if let Some(credentials) = settings.credentials {
+        info!("Authenticating with IAM Roles Anywhere");
+        loader = loader.credentials_provider(credentials);
+    }
+    if let Some(retry_config) = settings.retry_config {
+        loader = loader.retry_config(retry_config);
+    }
+    if let Some(call_timeout) = settings.call_timeout {
+        loader = loader.timeout_config(
+            TimeoutConfig::builder()
+                .operation_attempt_timeout(call_timeout)
+                .build(),
+        );
+    }
+    // AWS_ENDPOINT_URL and the per-service variables are read by the SDK.
+    if let Some(endpoint_url) = settings.endpoint_url {
+        info!("Sending the AWS calls to {}", endpoint_url);
+        loader = loader.endpoint_url(endpoint_url);
+    }
+    let mut config = tls::with_http_client(loader).load().await;
+    if let Some(assume_role) = settings.assume_role {
+        config = assume_role.apply(config).await;
+    }
+    if let Some(timeout) = settings.credentials_wait {
+        aws_clients::wait_for_credentials(&config, timeout).await;
+    }
+    config
+}
+
+/// Starts loading the AWS configuration for the resolve options, after
+/// checking the credential settings.
+pub(crate) fn spawn_aws_config(options: &ResolveOptions) -> Result<LazyConfig, Box<dyn Error>> {
+    #[cfg(feature = "test-harness")]
+    if let Some(path) = &options.test_harness {
+        let endpoint = test_harness::start(test_harness::Fixtures::load(path)?)?;
+        return Ok(LazyConfig::spawn(test_harness::load_config(endpoint)));
+    }
+    Ok(LazyConfig::spawn(load_aws_config(AwsSettings::new(
+        options,
+    )?)))
+}
+
+pub async fn run(run_args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let status_file = run_args.status_file.clone();
+    let mut timings = status_file::Timings::start();
+    let result = run_command(run_args, &mut timings).await;
+    files::remove_temporary_files();
+    if let Some(path) = status_file {
+        if let Err(e) = status_file::write(&path, &result, &timings) {
+            warn!("{}", e);
+        }
+    }
+    std::process::exit(result?)
+}
+
+/// Resolves the secrets and runs the command, returning its exit code.
+async fn run_command(
+    mut run_args: RunArgs,
+    timings: &mut status_file::Timings,
+) -> Result<i32, Box<dyn Error>> {
+    let config = spawn_aws_config(&run_args.resolve)?;
+    let secretsmanager_client = LazyClient::new(&config, SecretsManagerClient::new);
+    let ssm_client = LazyClient::new(&config, SsmClient::new);
+
+    if run_args.resolve.locked {
+        run_args.resolve.pinned_versions = Lockfile::load(&run_args.lockfile)?.versions;
+    }
+
+    let secrets = signals::cancellable(resolve(
+        &secretsmanager_client,
+        &ssm_client,
+        &run_args.resolve,
+    ))
+    .await?;
+    signals::exit_on_signal();
+
+    if run_args.print_env {
+        let inherited: Vec<(String, String)> = env::vars().collect();
+        print!("{}", summary::masked_environment(&inherited, &secrets));
+        return Ok(0);
+    }
+
+    if let Some(format) = run_args.output {
+        output::write(
+            format,
+            &secrets,
+            run_args.provenance,
+            run_args.out_file.as_deref(),
+            run_args.persistent_files,
+        )?;
+        return Ok(0);
+    }
+
+    if run_args.dry_run {
+        if !run_args.command.is_empty() {
+            substitute_arguments(&run_args, &secrets)?;
+        }
+        render_templates(&run_args, &secrets)?;
+        print!("{}", summary::summary_table(&secrets));
+        info!("Dry run, not executing the command");
+        return Ok(0);
+    }
+
+    timings.launched();
+    let mut exit_code = match run_args.refresh_interval {
+        Some(interval) => {
+            watch::supervise(
+                &secretsmanager_client,
+                &ssm_client,
+                &run_args,
+                secrets,
+                interval,
+            )
+            .await?
+        }
+        None => launch(&run_args, &secrets)?,
+    };
+
+    if Some(exit_code) == run_args.previous_on_exit_code {
+        warn!(
+            "Command exited with status code {}, retrying with the AWSPREVIOUS secret versions",
+            exit_code
+        );
+        run_args.resolve.use_previous = true;
+        let secrets = resolve(&secretsmanager_client, &ssm_client, &run_args.resolve).await?;
+        exit_code = launch(&run_args, &secrets)?;
+    }
+
+    Ok(exit_code)
+}
+
+async fn resolve<S, T>(
+    secretsmanager_client: &S,
+    ssm_client: &T,
+    options: &ResolveOptions,
+) -> Result<Vec<ResolvedSecret>, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SsmClientTrait + ?Sized,
+{
+    info!("Processing environment");
+    let secrets = process_environment(secretsmanager_client, ssm_client, options).await?;
+    info!("Processed {} environment variables", secrets.len());
+    let calls = api_calls::counts();
+    info!("AWS API calls: {}", api_calls::summary(&calls));
+    if options.report_api_calls {
+        eprint!("{}", api_calls::report_table(&calls));
+    }
+    if !secrets.is_empty() {
+        info!("Resolved secrets:\n{}", summary::summary_table(&secrets));
+    }
+    Ok(secrets)
+}
+
+/// Runs the command with the resolved secrets, returning its exit code.
+pub(crate) fn launch(
+    run_args: &RunArgs,
+    secrets: &[ResolvedSecret],
+) -> Result<i32, Box<dyn Error>> {
+    let file_variables = write_files(run_args, secrets)?;
+
+    // Create a new environment with both existing and new variables
+    let mut new_env: std::collections::HashMap<String, String> = env::vars().collect();
+    new_env.extend(file_variables);
+    for secret in secrets {
+        if run_args.fifos.contains(&secret.key) || secret.file.is_some() {
+            continue;
+        }
+        info!("Setting environment variable: {}", secret.key);
+        new_env.insert(secret.key.clone(), secret.value.expose().to_string());
+    }
+    let _fifos = if run_args.fifos.is_empty() {
+        None
+    } else {
+        let fifos = fifo::Fifos::create(&run_args.fifos, secrets)?;
+        new_env.extend(fifos.variables.clone());
+        Some(fifos)
+    };
+
+    let command = &run_args.command;
+    let arguments = substitute_arguments(run_args, secrets)?;
+    let mut child = std::process::Command::new(&command[0]);
+    child.args(&arguments).envs(&new_env);
+    rlimits::apply(&mut child, &run_args.rlimits)?;
+    let cgroup = if run_args.cgroup_limits.is_empty() {
+        None
+    } else {
+        let group = cgroup::Cgroup::create(&run_args.cgroup_limits)?;
+        group.apply(&mut child)?;
+        Some(group)
+    };
+    // Installed last, as the profile may deny the calls placing the command
+    // in its cgroup or setting its limits.
+    if let Some(path) = &run_args.seccomp_profile {
+        let arch = seccomp::Arch::current()
+            .ok_or("seccomp profiles are only supported on x86_64 and aarch64")?;
+        seccomp::apply(&mut child, seccomp::load_profile(path, arch)?)?;
+    }
+
+    let stdin_value = match &run_args.stdin_secret {
+        Some(key) => {
+            let secret = secrets
+                .iter()
+                .find(|secret| &secret.key == key)
+                .ok_or_else(|| format!("No resolved secret named {} to feed to stdin", key))?;
+            let mut value = secret.value.expose().to_string();
+            if run_args.stdin_newline {
+                value.push('\n');
+            }
+            Some(value)
+        }
+        None => None,
+    };
+
+    // As PID 1 of a container, the orphaned processes are adopted by the
+    // tool, which must reap them for them not to linger as zombies.
+    let init = run_args.init || std::process::id() == 1;
+    if init {
+        signals::adopt_orphans();
+    }
+
+    // Feeding stdin, serving the pipes, removing the cgroup or the temporary
+    // files, rerunning or restarting the command, reporting its exit and
+    // reaping orphans need the tool to outlive the command.
+    let supervised = init
+        || run_args.refresh_interval.is_some()
+        || files::has_temporary_files()
+        || stdin_value.is_some()
+        || !run_args.fifos.is_empty()
+        || cgroup.is_some()
+        || run_args.previous_on_exit_code.is_some()
+        || run_args.status_file.is_some();
+    if run_args.exec && !supervised {
+        info!("Replacing process with command: {}", command[0]);
+        return Err(exec(child).into());
+    }
+
+    info!("Executing command: {}", command[0]);
+    if stdin_value.is_some() {
+        child.stdin(std::process::Stdio::piped());
+    }
+    let mut process = child.spawn()?;
+    if let Some(value) = stdin_value {
+        let stdin = process.stdin.take().expect("stdin is piped");
+        feed_stdin(stdin, value);
+    }
+    let status = signals::wait_forwarding_signals(&mut process, init)?;
+
+    let exit_code = signals::exit_code(status);
+    info!("Command exited with status code: {}", exit_code);
+    Ok(exit_code)
+}
+
+/// Writes the credential files, the files targeted by `SECRET_FILE_`
+/// variables and the rendered templates, returning the variables set to the
+/// paths of the files.
+pub(crate) fn write_files(
+    run_args: &RunArgs,
+    secrets: &[ResolvedSecret],
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut file_variables = db_credentials::write_credential_files(
+        &run_args.credential_files,
+        secrets,
+        run_args.persistent_files,
+    )?;
+    file_variables.extend(files::write_target_files(
+        secrets,
+        run_args.persistent_files,
+    )?);
+    for (config, rendered) in run_args
+        .templates
+        .iter()
+        .zip(render_templates(run_args, secrets)?)
+    {
+        files::write_secret_to(
+            &config.render_to,
+            rendered.as_bytes(),
+            run_args.persistent_files,
+        )?;
+        info!(
+            "Rendered {} to {}",
+            config.template.display(),
+            config.render_to.display()
+        );
+    }
+    Ok(file_variables)
+}
+
+/// Writes the value to the stdin of the command from another thread, so a
+/// command reading it only after writing its own output can't block the
+/// tool, then closes it.
+fn feed_stdin(mut stdin: std::process::ChildStdin, value: String) {
+    std::thread::spawn(move || {
+        use std::io::Write;
+        if let Err(e) = stdin.write_all(value.as_bytes()) {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                warn!(
+                    "Failed to write the secret to the stdin of the command: {}",
+                    e
+                );
+            }
+        }
+    });
+}
+
+/// Substitutes the `{{SECRET:KEY}}` tokens in the command arguments, for
+/// programs that only accept credentials as arguments.
+pub(crate) fn substitute_arguments(
+    run_args: &RunArgs,
+    secrets: &[ResolvedSecret],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut arguments = Vec::new();
+    for argument in &run_args.command[1..] {
+        let (argument, keys) = template::substitute_secret_tokens(argument, |key| {
+            secrets
+                .iter()
+                .find(|secret| secret.key == key)
+                .map(|secret| secret.value.expose())
+        })?;
+        for key in keys {
+            if run_args.forbid_argv_secrets {
+                return Err(format!(
+                    "{} is substituted into the command arguments, which --forbid-argv-secrets doesn't allow",
+                    key
+                )
+                .into());
+            }
+            warn!(
+                "Substituting {} into the command arguments, where other local users may see it, for example with ps",
+                key
+            );
+        }
+        arguments.push(argument);
+    }
+    Ok(arguments)
+}
+
+/// Renders the configuration file templates with the resolved values, in the
+/// order they were given.
+pub(crate) fn render_templates(
+    run_args: &RunArgs,
+    secrets: &[ResolvedSecret],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut rendered = Vec::new();
+    for config in &run_args.templates {
+        let template = std::fs::read_to_string(&config.template).map_err(|e| {
+            format!(
+                "Failed to read template {}: {}",
+                config.template.display(),
+                e
+            )
+        })?;
+        let contents = template::render_config(&template, |key| {
+            secrets
+                .iter()
+                .find(|secret| secret.key == key)
+                .map(|secret| secret.value.expose())
+        })
+        .map_err(|e| format!("Failed to render {}: {}", config.template.display(), e))?;
+        rendered.push(contents);
+    }
+    Ok(rendered)
+}
+
+/// Replaces the current process with the command, only returning on failure.
+#[cfg(unix)]
+fn exec(mut command: std::process::Command) -> std::io::Error {
+    use std::os::unix::process::CommandExt;
+    command.exec()
+}
+
+#[cfg(not(unix))]
+fn exec(mut command: std::process::Command) -> std::io::Error {
+    match command.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => e,
+    }
+}
+
+pub async fn lock(mut lock_args: LockArgs) -> Result<(), Box<dyn Error>> {
+    let config = spawn_aws_config(&lock_args.resolve)?;
+    let secretsmanager_client = LazyClient::new(&config, SecretsManagerClient::new);
+    let ssm_client = LazyClient::new(&config, SsmClient::new);
+
+    if !lock_args.update && lock_args.lockfile.exists() {
+        lock_args.resolve.pinned_versions = Lockfile::load(&lock_args.lockfile)?.versions;
+    }
+
+    let secrets = signals::cancellable(process_environment(
+        &secretsmanager_client,
+        &ssm_client,
+        &lock_args.resolve,
+    ))
+    .await?;
+    let lockfile = Lockfile::from_resolved(&secrets)?;
+    lockfile.save(&lock_args.lockfile)?;
+    info!(
+        "Locked {} secret versions in {}",
+        lockfile.versions.len(),
+        lock_args.lockfile.display()
+    );
+    Ok(())
+}
+
+pub async fn gen_ecs(options: ResolveOptions) -> Result<(), Box<dyn Error>> {
+    let config = spawn_aws_config(&options)?;
+    let ssm_client = LazyClient::new(&config, SsmClient::new);
+    let references = collect_references(&ssm_client, &options).await?;
+    let fragments = ecs::task_definition_fragments(&references);
+    println!("{}", serde_json::to_string_pretty(&fragments)?);
+    Ok(())
+}
+
+pub async fn rotation_report(report_args: RotationReportArgs) -> Result<(), Box<dyn Error>> {
+    let config = spawn_aws_config(&report_args.resolve)?;
+    let secretsmanager_client = LazyClient::new(&config, SecretsManagerClient::new);
+    let ssm_client = LazyClient::new(&config, SsmClient::new);
+    let references = collect_references(&ssm_client, &report_args.resolve).await?;
+    let statuses = rotation_report::rotation_report(
+        &secretsmanager_client,
+        &references,
+        report_args.max_age,
+        std::time::SystemTime::now(),
+    )
+    .await?;
+    print!("{}", rotation_report::report_table(&statuses));
+
+    let stale = statuses.iter().filter(|status| status.stale).count();
+    if stale == 0 {
+        return Ok(());
+    }
+    eprintln!(
+        "{} secret(s) were not rotated within {:?}",
+        stale, report_args.max_age
+    );
+    std::process::exit(1)
+}
+
+pub async fn bench(bench_args: BenchArgs) -> Result<(), Box<dyn Error>> {
+    let config = spawn_aws_config(&bench_args.resolve)?;
+    let secretsmanager_client = LazyClient::new(&config, SecretsManagerClient::new);
+    let ssm_client = LazyClient::new(&config, SsmClient::new);
+    let references = collect_references(&ssm_client, &bench_args.resolve).await?;
+    let timings = signals::cancellable(bench::bench(
+        &secretsmanager_client,
+        &ssm_client,
+        &references,
+        &bench_args.resolve,
+        bench_args.iterations,
+    ))
+    .await?;
+    print!("{}", bench::bench_table(&timings));
+    Ok(())
+}
+
+pub async fn edit(edit_args: EditArgs) -> Result<(), Box<dyn Error>> {
+    let config = spawn_aws_config(&edit_args.resolve)?;
+    let client = LazyClient::new(&config, SecretsManagerClient::new);
+    match edit::edit_secret(&client, &edit_args.secret_id, edit::edit_in_editor).await? {
+        Some(version_id) => println!("Saved {} as version {}", edit_args.secret_id, version_id),
+        None => println!("{} was not changed", edit_args.secret_id),
+    }
+    Ok(())
+}
+
+pub async fn get(get_args: GetArgs) -> Result<(), Box<dyn Error>> {
+    use std::io::{IsTerminal, Write};
+
+    // Printing a secret to a terminal leaves it in the scrollback and in
+    // screen recordings, so it has to be asked for explicitly.
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() && !get_args.reveal {
+        return Err(
+            "Refusing to print the secret to a terminal, use --reveal or redirect the output"
+                .into(),
+        );
+    }
+
+    let config = spawn_aws_config(&get_args.resolve)?;
+    let secretsmanager_client = LazyClient::new(&config, SecretsManagerClient::new);
+    let ssm_client = LazyClient::new(&config, SsmClient::new);
+    let value = signals::cancellable(get::get_value(
+        &secretsmanager_client,
+        &ssm_client,
+        &get_args,
+    ))
+    .await?;
+    stdout.write_all(value.as_bytes())?;
+    if !get_args.raw {
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+pub async fn copy(copy_args: CopyArgs) -> Result<(), Box<dyn Error>> {
+    let config = spawn_aws_config(&copy_args.resolve)?;
+    let source = LazyClient::new(&config, SecretsManagerClient::new);
+    let target = source
+        .with_target(copy_args.region.as_deref(), copy_args.role.as_deref())
+        .await?;
+    let arn = copy::copy_secret(&source, target.as_ref(), &copy_args).await?;
+    println!("Copied {} to {}", copy_args.secret_id, arn);
+    Ok(())
+}
+
+pub async fn lint(lint_args: LintArgs) -> Result<(), Box<dyn Error>> {
+    let document = match &lint_args.source {
+        MappingSource::File(path) => std::fs::read_to_string(path)?,
+        MappingSource::Stdin => {
+            let mut document = String::new();
+            std::io::stdin().read_to_string(&mut document)?;
+            document
+        }
+        MappingSource::Parameter(name) => {
+            let config = spawn_aws_config(&lint_args.resolve)?;
+            get_ssm_parameter(&LazyClient::new(&config, SsmClient::new), name, true).await?
+        }
+    };
+
+    let errors = lint::lint_mapping(&document);
+    if errors.is_empty() {
+        println!("Mapping is valid");
+        return Ok(());
+    }
+
+    for error in &errors {
+        println!("{}", error);
+    }
+    eprintln!("Mapping has {} error(s)", errors.len());
+    std::process::exit(1)
+}
+
+pub async fn push_mapping(push_args: PushMappingArgs) -> Result<(), Box<dyn Error>> {
+    let document = std::fs::read_to_string(&push_args.file)
+        .map_err(|e| format!("Failed to read {}: {}", push_args.file.display(), e))?;
+    let config = spawn_aws_config(&push_args.resolve)?;
+    let client = LazyClient::new(&config, SsmClient::new);
+    let parameter = &push_args.parameter;
+
+    let written = push_mapping::push_mapping(&client, parameter, &document, |diff| {
+        for line in diff {
+            println!("{}", line);
+        }
+        confirm_push(parameter, push_args.yes)
+    })
+    .await?;
+    if written {
+        println!("Wrote the mapping to {}", parameter);
+    } else {
+        println!("The mapping in {} was not changed", parameter);
+    }
+    Ok(())
+}
+
+/// Asks on the terminal whether to write the mapping, unless `--yes` was
+/// given.
+fn confirm_push(parameter: &str, yes: bool) -> Result<bool, Box<dyn Error>> {
+    use std::io::{IsTerminal, Write};
+
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err("Use --yes to write the mapping without a terminal to confirm it".into());
+    }
+    eprint!("Write the mapping to {}? [y/N] ", parameter);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
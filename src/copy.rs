@@ -0,0 +1,122 @@
+use crate::cli::CopyArgs;
+use crate::logging::info;
+use crate::redact::SecretString;
+use crate::secret_manager::{SecretCopy, SecretsManagerClientTrait};
+use aws_sdk_secretsmanager::operation::create_secret::CreateSecretError;
+use std::error::Error;
+
+/// Metadata of the secret copied along with its value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CopyMetadata {
+    pub description: bool,
+    pub tags: bool,
+}
+
+impl Default for CopyMetadata {
+    fn default() -> Self {
+        CopyMetadata {
+            description: true,
+            tags: true,
+        }
+    }
+}
+
+impl std::str::FromStr for CopyMetadata {
+    type Err = String;
+
+    /// Parses a comma-separated list such as `description,tags`, or `none`.
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let mut metadata = CopyMetadata {
+            description: false,
+            tags: false,
+        };
+        if list == "none" {
+            return Ok(metadata);
+        }
+        for name in list.split(',') {
+            match name {
+                "description" => metadata.description = true,
+                "tags" => metadata.tags = true,
+                other => return Err(format!("unknown metadata {}", other)),
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// Copies the current value of a secret, and the selected metadata, to the
+/// target client, which may be for another region or account. Returns the
+/// ARN of the copy.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(source, target)))]
+pub async fn copy_secret<S, T>(
+    source: &S,
+    target: &T,
+    copy_args: &CopyArgs,
+) -> Result<String, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SecretsManagerClientTrait + ?Sized,
+{
+    info!("Copying secret: {}", copy_args.secret_id);
+    let description = source.describe_secret(&copy_args.secret_id).await?;
+    let secret = source.get_secret_value(&copy_args.secret_id).await?;
+
+    let name = copy_args
+        .name
+        .clone()
+        .or_else(|| description.name().map(str::to_string))
+        .ok_or_else(|| format!("No name was returned for {}", copy_args.secret_id))?;
+    let metadata = copy_args.metadata;
+    let copy = SecretCopy {
+        value: secret.secret_string().map(SecretString::from),
+        binary: secret.secret_binary().map(|blob| blob.as_ref().to_vec()),
+        description: description
+            .description()
+            .filter(|_| metadata.description)
+            .map(str::to_string),
+        // Tags with the aws: prefix are reserved and can't be set.
+        tags: description
+            .tags()
+            .iter()
+            .filter(|_| metadata.tags)
+            .filter_map(|tag| Some((tag.key()?.to_string(), tag.value()?.to_string())))
+            .filter(|(key, _)| !key.starts_with("aws:"))
+            .collect(),
+        kms_key_id: copy_args.kms_key_id.clone(),
+        name,
+    };
+    if copy.value.is_none() && copy.binary.is_none() {
+        return Err(format!("{} has no value to copy", copy_args.secret_id).into());
+    }
+
+    let arn = match target.create_secret_copy(&copy).await {
+        Ok(response) => response.arn().map(str::to_string),
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(CreateSecretError::is_resource_exists_exception) =>
+        {
+            if !copy_args.overwrite {
+                return Err(format!(
+                    "{} already exists in the target, use --overwrite to store the value as a new version",
+                    copy.name
+                )
+                .into());
+            }
+            let value = copy
+                .value
+                .as_ref()
+                .map(SecretString::expose)
+                .ok_or_else(|| {
+                    format!(
+                        "{} holds a SecretBinary, which can't be stored in an existing secret",
+                        copy_args.secret_id
+                    )
+                })?;
+            info!("Storing the value as a new version of {}", copy.name);
+            let response = target.put_secret_value(&copy.name, value).await?;
+            response.arn().map(str::to_string)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(arn.ok_or_else(|| format!("No ARN was returned for {}", copy.name))?)
+}
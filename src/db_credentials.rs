@@ -0,0 +1,294 @@
+use crate::environment_processor::ResolvedSecret;
+use crate::files::{
+    check_storage, create_private_file, is_temporary_file, register_temporary_file, temporary_path,
+    write_private_file, PersistentFiles,
+};
+use crate::logging::info;
+use crate::redact::SecretString;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// An environment variable defined for the wrapped program.
+type Variable = (String, String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CredentialFileKind {
+    PgPass,
+    MyCnf,
+}
+
+impl CredentialFileKind {
+    fn default_file_name(self) -> &'static str {
+        match self {
+            CredentialFileKind::PgPass => ".pgpass",
+            CredentialFileKind::MyCnf => ".my.cnf",
+        }
+    }
+}
+
+/// A database client credential file rendered from a resolved JSON secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CredentialFile {
+    pub kind: CredentialFileKind,
+    /// Target key of the resolved secret holding the database credentials.
+    pub key: String,
+    /// Defaults to the file name the client looks for in the home directory.
+    pub path: Option<PathBuf>,
+}
+
+impl CredentialFile {
+    /// Parses a `KEY[=path]` option value.
+    pub fn parse(kind: CredentialFileKind, value: &str) -> Self {
+        let (key, path) = match value.split_once('=') {
+            Some((key, path)) => (key, Some(PathBuf::from(path))),
+            None => (value, None),
+        };
+        CredentialFile {
+            kind,
+            key: key.to_string(),
+            path,
+        }
+    }
+
+    /// Returns where the file is written, along with the variable pointing
+    /// the client to it, if any. As the PostgreSQL clients read the file
+    /// named by `PGPASSFILE`, a `.pgpass` without a path is written to the
+    /// private temporary directory of the run rather than over the one in
+    /// the home directory.
+    fn target_path(&self) -> Result<(PathBuf, Option<Variable>), Box<dyn Error>> {
+        if let Some(path) = &self.path {
+            return Ok((path.clone(), None));
+        }
+        if self.kind == CredentialFileKind::PgPass {
+            let path = temporary_path(self.kind.default_file_name())?;
+            let variable = ("PGPASSFILE".to_string(), path.display().to_string());
+            return Ok((path, Some(variable)));
+        }
+        let home = PathBuf::from(std::env::var_os("HOME").ok_or("HOME is not set")?);
+        Ok((home.join(self.kind.default_file_name()), None))
+    }
+}
+
+/// Database connection details, as stored in RDS-style JSON secrets.
+#[derive(Debug, PartialEq)]
+pub struct DbCredentials {
+    pub username: String,
+    pub password: SecretString,
+    pub host: Option<String>,
+    pub port: Option<String>,
+    pub dbname: Option<String>,
+}
+
+impl DbCredentials {
+    pub fn from_json(secret: &str) -> Result<Self, Box<dyn Error>> {
+        let value: Value =
+            serde_json::from_str(secret).map_err(|_| "database secret is not valid JSON")?;
+        let field = |name: &str| match &value[name] {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+
+        Ok(DbCredentials {
+            username: field("username").ok_or("database secret has no username")?,
+            password: field("password")
+                .ok_or("database secret has no password")?
+                .into(),
+            host: field("host"),
+            port: field("port"),
+            dbname: field("dbname"),
+        })
+    }
+}
+
+/// Renders a `.pgpass` line, escaping `:` and `\` as libpq expects.
+pub fn render_pgpass(credentials: &DbCredentials) -> String {
+    let escape = |field: &str| field.replace('\\', "\\\\").replace(':', "\\:");
+    let wildcard = |field: &Option<String>| field.as_deref().map(escape).unwrap_or("*".into());
+
+    format!(
+        "{}:{}:{}:{}:{}\n",
+        wildcard(&credentials.host),
+        wildcard(&credentials.port),
+        wildcard(&credentials.dbname),
+        escape(&credentials.username),
+        escape(credentials.password.expose()),
+    )
+}
+
+/// Renders the `[client]` section of a MySQL option file, quoting the values.
+pub fn render_my_cnf(credentials: &DbCredentials) -> String {
+    let quote = |field: &str| format!("\"{}\"", field.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut contents = String::from("[client]\n");
+    contents.push_str(&format!("user={}\n", quote(&credentials.username)));
+    contents.push_str(&format!(
+        "password={}\n",
+        quote(credentials.password.expose())
+    ));
+    if let Some(host) = &credentials.host {
+        contents.push_str(&format!("host={}\n", quote(host)));
+    }
+    if let Some(port) = &credentials.port {
+        contents.push_str(&format!("port={}\n", port));
+    }
+    if let Some(dbname) = &credentials.dbname {
+        contents.push_str(&format!("database={}\n", quote(dbname)));
+    }
+    contents
+}
+
+/// Engine of the connection URL built from a JSON database secret.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrlScheme {
+    Postgres,
+    Mysql,
+    Redis,
+    Amqp,
+}
+
+impl UrlScheme {
+    fn default_port(self) -> u16 {
+        match self {
+            UrlScheme::Postgres => 5432,
+            UrlScheme::Mysql => 3306,
+            UrlScheme::Redis => 6379,
+            UrlScheme::Amqp => 5672,
+        }
+    }
+}
+
+impl std::str::FromStr for UrlScheme {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "postgres" => Ok(UrlScheme::Postgres),
+            "mysql" => Ok(UrlScheme::Mysql),
+            "redis" => Ok(UrlScheme::Redis),
+            "amqp" => Ok(UrlScheme::Amqp),
+            other => Err(format!("unknown connection URL scheme {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for UrlScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlScheme::Postgres => write!(f, "postgres"),
+            UrlScheme::Mysql => write!(f, "mysql"),
+            UrlScheme::Redis => write!(f, "redis"),
+            UrlScheme::Amqp => write!(f, "amqp"),
+        }
+    }
+}
+
+/// Renders a connection URL, percent-encoding the credentials and database
+/// name so that special characters in passwords can't break the URL. The
+/// database name is the virtual host for AMQP and the database number for
+/// Redis.
+pub fn render_url(
+    scheme: UrlScheme,
+    credentials: &DbCredentials,
+) -> Result<String, Box<dyn Error>> {
+    let host = credentials
+        .host
+        .as_deref()
+        .ok_or("database secret has no host")?;
+    let port = match &credentials.port {
+        Some(port) => port.clone(),
+        None => scheme.default_port().to_string(),
+    };
+
+    let mut url = format!(
+        "{}://{}:{}@{}:{}",
+        scheme,
+        percent_encode(&credentials.username),
+        percent_encode(credentials.password.expose()),
+        host,
+        port
+    );
+    if let Some(dbname) = &credentials.dbname {
+        url.push('/');
+        url.push_str(&percent_encode(dbname));
+    }
+    Ok(url)
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Writes the credential files, removing the ones already written if any of
+/// them fails so that no partial set is left behind. Returns the variables
+/// pointing the clients to the files written to another location than the
+/// default one. The files are removed with the temporary files of the run.
+pub fn write_credential_files(
+    files: &[CredentialFile],
+    secrets: &[ResolvedSecret],
+    policy: PersistentFiles,
+) -> Result<Vec<Variable>, Box<dyn Error>> {
+    let mut written = Vec::new();
+    let result = write_each(files, secrets, policy, &mut written);
+    if result.is_err() {
+        for path in written {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    result
+}
+
+fn write_each(
+    files: &[CredentialFile],
+    secrets: &[ResolvedSecret],
+    policy: PersistentFiles,
+    written: &mut Vec<PathBuf>,
+) -> Result<Vec<Variable>, Box<dyn Error>> {
+    let mut variables = Vec::new();
+    for file in files {
+        let secret = secrets
+            .iter()
+            .find(|secret| secret.key == file.key)
+            .ok_or_else(|| format!("No resolved secret named {}", file.key))?;
+        let credentials = DbCredentials::from_json(secret.value.expose())
+            .map_err(|e| format!("Cannot use {} as database credentials: {}", file.key, e))?;
+
+        let contents = match file.kind {
+            CredentialFileKind::PgPass => render_pgpass(&credentials),
+            CredentialFileKind::MyCnf => render_my_cnf(&credentials),
+        };
+        let (path, variable) = file.target_path()?;
+        check_storage(&path, policy)?;
+        // A file in the home directory is only written when missing, or
+        // when written by this run and rewritten with new values.
+        if file.path.is_some() || is_temporary_file(&path) {
+            write_private_file(&path, contents.as_bytes())?;
+        } else if path.symlink_metadata().is_ok() {
+            return Err(format!(
+                "{} already exists, give --my-cnf {}=<path> to write the credentials elsewhere and pass the path to the client with --defaults-extra-file",
+                path.display(),
+                file.key
+            )
+            .into());
+        } else {
+            create_private_file(&path, contents.as_bytes())?;
+        }
+        register_temporary_file(&path);
+        written.push(path.clone());
+        info!("Wrote credentials from {} to {}", file.key, path.display());
+        variables.extend(variable);
+    }
+    Ok(variables)
+}
@@ -0,0 +1,167 @@
+use crate::environment_processor::{Provider, Reference};
+
+/// Configuration variables read by the tool itself, used to spot near-miss
+/// spellings that would otherwise be silently ignored.
+const KNOWN_VARIABLES: &[&str] = &[
+    "SECRETS_PARAMETER_ARN",
+    "SECRETS_PARAMETER_NAME",
+    "SECRETS_PARAMETER_PATH",
+    "SECRETS_PROFILE",
+    "SECRETS_ASSUME_ROLE_ARN",
+    "SECRETS_ASSUME_ROLE_EXTERNAL_ID",
+    "SECRETS_ASSUME_ROLE_SESSION_NAME",
+];
+
+/// Configuration variables also read with an environment suffix, such as
+/// `SECRETS_PARAMETER_NAME_STAGING`.
+const SUFFIXED_VARIABLES: &[&str] = &["SECRETS_PARAMETER_ARN", "SECRETS_PARAMETER_NAME"];
+
+fn is_known_variable(key: &str) -> bool {
+    KNOWN_VARIABLES.contains(&key)
+        || SUFFIXED_VARIABLES.iter().any(|variable| {
+            key.strip_prefix(variable)
+                .and_then(|rest| rest.strip_prefix('_'))
+                .is_some_and(|suffix| !suffix.is_empty())
+        })
+}
+
+const SECRET_PREFIX: &str = "SECRET";
+
+/// Values commonly left behind by templates and broken rotation pipelines.
+const PLACEHOLDERS: &[&str] = &[
+    "changeme",
+    "change_me",
+    "change-me",
+    "replaceme",
+    "placeholder",
+    "todo",
+    "tbd",
+    "dummy",
+    "xxx",
+];
+
+/// Returns warnings for variables that look like they were meant to configure
+/// the tool but don't, such as `SECERT_FOO`, `SECRETS_PARAMETER_ARM` or a
+/// `SECRET_` ARN with a misspelled service segment.
+pub fn near_miss_warnings(vars: &[(String, String)]) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for (key, value) in vars {
+        if is_known_variable(key) {
+            continue;
+        }
+
+        if key.starts_with("SECRETS_") {
+            if let Some(known) = KNOWN_VARIABLES
+                .iter()
+                .find(|known| edit_distance(key, known) <= 2)
+            {
+                warnings.push(format!(
+                    "{} is not a recognized variable, did you mean {}?",
+                    key, known
+                ));
+            }
+            continue;
+        }
+
+        if let Some(name) = key.strip_prefix("SECRET_") {
+            if let Some(warning) = check_secret_arn(key, value) {
+                warnings.push(warning);
+            } else if name.is_empty() {
+                warnings.push(format!("{} has no name after the prefix", key));
+            }
+            continue;
+        }
+
+        let prefix = key.split('_').next().unwrap_or_default();
+        if key.contains('_')
+            && value.starts_with("arn:")
+            && edit_distance(prefix, SECRET_PREFIX) <= 2
+        {
+            warnings.push(format!(
+                "{} contains an ARN but is not prefixed with SECRET_, so it will not be resolved",
+                key
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Returns warnings for a resolved value that looks wrong: shorter than the
+/// minimum length declared for it or, when `heuristics` is enabled, equal to
+/// its own reference, another ARN or a placeholder. The value itself is never
+/// part of the warnings.
+pub fn suspicious_value_warnings(
+    reference: &Reference,
+    value: &str,
+    heuristics: bool,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let variable = &reference.variable;
+
+    if let Some(min_length) = reference.options.min_length {
+        if value.chars().count() < min_length {
+            warnings.push(format!(
+                "{} is shorter than its minimum length of {}",
+                variable, min_length
+            ));
+        }
+    }
+
+    if heuristics && reference.provider != Provider::Literal {
+        if value == reference.reference {
+            warnings.push(format!("{} resolved to its own reference", variable));
+        } else if value.starts_with("arn:aws") {
+            warnings.push(format!(
+                "{} resolved to an ARN instead of a secret value",
+                variable
+            ));
+        }
+        if PLACEHOLDERS.contains(&value.trim().to_lowercase().as_str()) {
+            warnings.push(format!("{} resolved to a placeholder value", variable));
+        }
+    }
+
+    warnings
+}
+
+fn check_secret_arn(key: &str, value: &str) -> Option<String> {
+    if !value.starts_with("arn:") {
+        return None;
+    }
+
+    let segments: Vec<&str> = value.splitn(7, ':').collect();
+    if segments.len() < 7 {
+        return Some(format!(
+            "{} looks like an ARN but has too few segments to be a Secrets Manager ARN",
+            key
+        ));
+    }
+
+    let service = segments[2];
+    if service != "secretsmanager" {
+        return Some(format!(
+            "{} references the {} service, but SECRET_ variables must contain a secretsmanager ARN",
+            key, service
+        ));
+    }
+
+    None
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
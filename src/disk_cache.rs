@@ -0,0 +1,215 @@
+//! Encrypted on-disk cache of the retrieved values, so programs started
+//! often, such as cron jobs and CLI tools, don't call AWS every time they
+//! start. Each value is stored in a file of its own, encrypted with age to
+//! the key of an identity file, which is created on first use.
+
+use crate::environment_processor::{DiskCacheOptions, Reference, ResolveOptions};
+use crate::files::write_private_file;
+use crate::logging::{info, warn};
+use crate::secret_manager::SecretValue;
+use age::secrecy::ExposeSecret;
+use age::x25519::Identity;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Serializes loading or creating the identity, so concurrent resolutions
+/// of the first run don't create different keys.
+static IDENTITY_LOCK: Mutex<()> = Mutex::new(());
+
+/// Directory of the cache, under `XDG_CACHE_HOME` or `~/.cache` by default.
+fn cache_dir(options: &DiskCacheOptions) -> Option<PathBuf> {
+    options
+        .dir
+        .clone()
+        .or_else(|| user_dir("XDG_CACHE_HOME", ".cache"))
+}
+
+/// Identity file encrypting the cache, under `XDG_CONFIG_HOME` or
+/// `~/.config` by default, away from the encrypted values.
+fn identity_path(options: &DiskCacheOptions) -> Option<PathBuf> {
+    options.identity.clone().or_else(|| {
+        user_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("cache-identity.txt"))
+    })
+}
+
+fn user_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    let base = match std::env::var_os(variable).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(fallback),
+    };
+    Some(base.join("resolve-aws-secrets"))
+}
+
+/// The key of the value, from the ARN or name and the version retrieved.
+/// Values are cached per declaring variable, as the entry options shape the
+/// value.
+fn cache_key(reference: &Reference, options: &ResolveOptions) -> String {
+    let version = match options.pinned_versions.get(&reference.reference) {
+        Some(version_id) => version_id.as_str(),
+        None if options.use_previous => "AWSPREVIOUS",
+        None => "AWSCURRENT",
+    };
+    format!("{}={}@{}", reference.variable, reference.reference, version)
+}
+
+/// Name of the file caching the key. The key itself is stored encrypted
+/// with the value, so the names don't reveal which secrets are used.
+fn file_name(key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    format!("{:016x}.age", hasher.finish())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Reads the identity from the file, ignoring comments as age does.
+fn read_identity(path: &Path) -> Result<Identity, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or("no identity found")?
+        .parse::<Identity>()
+        .map_err(|e| e.into())
+}
+
+/// Loads the identity, creating a private file with a new one when it
+/// doesn't exist yet and `create` is set.
+fn load_identity(path: &Path, create: bool) -> Result<Option<Identity>, Box<dyn Error>> {
+    let _guard = IDENTITY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if path.exists() {
+        return read_identity(path).map(Some);
+    }
+    if !create {
+        return Ok(None);
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        create_private_dir(dir)?;
+    }
+    let identity = Identity::generate();
+    let contents = format!(
+        "# public key: {}\n{}\n",
+        identity.to_public(),
+        identity.to_string().expose_secret()
+    );
+    write_private_file(path, contents.as_bytes())?;
+    info!("Created the disk cache identity {}", path.display());
+    Ok(Some(identity))
+}
+
+/// Creates the directory, readable only by the current user on Unix.
+fn create_private_dir(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder
+        .create(dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e).into())
+}
+
+fn read_entry(
+    options: &DiskCacheOptions,
+    key: &str,
+) -> Result<Option<SecretValue>, Box<dyn Error>> {
+    let Some(path) = cache_dir(options).map(|dir| dir.join(file_name(key))) else {
+        return Ok(None);
+    };
+    let ciphertext = match std::fs::read(&path) {
+        Ok(ciphertext) => ciphertext,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(identity) = identity_path(options)
+        .map(|path| load_identity(&path, false))
+        .transpose()?
+        .flatten()
+    else {
+        return Ok(None);
+    };
+    let cached: Value = serde_json::from_slice(&age::decrypt(&identity, &ciphertext)?)?;
+    if cached["key"].as_str() != Some(key) || cached["expires"].as_u64().unwrap_or(0) <= now() {
+        return Ok(None);
+    }
+    Ok(cached["value"].as_str().map(|value| SecretValue {
+        value: value.into(),
+        version_id: cached["version_id"].as_str().map(str::to_string),
+        stored_arn: None,
+        binary: cached["binary"].as_bool().unwrap_or(false),
+    }))
+}
+
+fn write_entry(
+    options: &DiskCacheOptions,
+    key: &str,
+    secret: &SecretValue,
+    ttl: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let dir = cache_dir(options).ok_or("no cache directory, use --disk-cache-dir")?;
+    let path = identity_path(options).ok_or("no identity file, use --disk-cache-identity")?;
+    let identity = load_identity(&path, true)?.expect("the identity is created when missing");
+    let cached = json!({
+        "key": key,
+        "value": secret.value.expose(),
+        "version_id": secret.version_id,
+        "binary": secret.binary,
+        "expires": now() + ttl.as_secs(),
+    });
+    let ciphertext = age::encrypt(&identity.to_public(), cached.to_string().as_bytes())?;
+    create_private_dir(&dir)?;
+    write_private_file(&dir.join(file_name(key)), &ciphertext)
+}
+
+/// Returns the cached value of the reference, unless it expired. Failures to
+/// read or decrypt the cache are logged and treated as cache misses.
+pub async fn lookup(reference: &Reference, options: &ResolveOptions) -> Option<SecretValue> {
+    let key = cache_key(reference, options);
+    let variable = reference.variable.clone();
+    let disk_cache = options.disk_cache.clone();
+    tokio::task::spawn_blocking(move || match read_entry(&disk_cache, &key) {
+        Ok(Some(secret)) => {
+            info!("Using the value of {} cached on disk", variable);
+            Some(secret)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Failed to read {} from the disk cache: {}", variable, e);
+            None
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Caches the value of the reference on disk for the given time.
+pub async fn store(
+    reference: &Reference,
+    options: &ResolveOptions,
+    secret: &SecretValue,
+    ttl: Duration,
+) {
+    let key = cache_key(reference, options);
+    let variable = reference.variable.clone();
+    let disk_cache = options.disk_cache.clone();
+    let secret = secret.clone();
+    let _ = tokio::task::spawn_blocking(move || {
+        if let Err(e) = write_entry(&disk_cache, &key, &secret, ttl) {
+            warn!("Failed to cache {} on disk: {}", variable, e);
+        }
+    })
+    .await;
+}
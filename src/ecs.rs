@@ -0,0 +1,109 @@
+use crate::environment_processor::{Provider, Reference};
+use crate::logging::warn;
+use serde_json::{json, Value};
+
+/// Converts the collected references into the `secrets` and `environment`
+/// fragments of an ECS task definition container, for comparing with or
+/// migrating to the native ECS secret injection. Literal values from the
+/// mapping become plain environment variables, and JSON keys and versions use
+/// the ECS `arn:...:json-key:version-stage:version-id` syntax. Merged secret lists, expanded JSON secrets,
+/// parameter paths, templates, generated passwords, Consul and etcd keys,
+/// assumed roles and transforms have no ECS equivalent and are skipped with a
+/// warning.
+pub fn task_definition_fragments(references: &[Reference]) -> Value {
+    let mut secrets = Vec::new();
+    let mut environment = Vec::new();
+    for reference in references {
+        if reference.provider == Provider::MergedSecrets {
+            warn!(
+                "Skipping {} as ECS can't merge the secrets {}",
+                reference.key, reference.reference
+            );
+            continue;
+        }
+        if reference.provider == Provider::SsmPath {
+            warn!(
+                "Skipping {} as ECS can't list the parameters under {}",
+                reference.variable, reference.reference
+            );
+            continue;
+        }
+        if reference.provider == Provider::JsonObject {
+            warn!(
+                "Skipping {} as ECS can't expand the JSON secret {} into variables",
+                reference.variable, reference.reference
+            );
+            continue;
+        }
+        if reference.provider == Provider::Template {
+            warn!(
+                "Skipping {} as ECS can't render the template {}",
+                reference.key, reference.reference
+            );
+            continue;
+        }
+        if let Provider::KeyValue(store) = reference.provider {
+            warn!(
+                "Skipping {} as ECS can't read {} keys",
+                reference.key, store
+            );
+            continue;
+        }
+        if reference.provider == Provider::GeneratedPassword {
+            warn!("Skipping {} as ECS can't generate passwords", reference.key);
+            continue;
+        }
+        if reference.options.file.is_some() {
+            warn!(
+                "Skipping {} as ECS can't write the secret {} to a file",
+                reference.variable, reference.reference
+            );
+            continue;
+        }
+        if reference.options.role.is_some()
+            || reference.options.transform.is_some()
+            || reference.options.url.is_some()
+        {
+            warn!(
+                "Skipping {} as ECS can't assume a role or transform the secret {}",
+                reference.key, reference.reference
+            );
+            continue;
+        }
+        if reference.options.default.is_some() {
+            warn!(
+                "ECS fails the task when the secret of {} can't be retrieved, its default value is ignored",
+                reference.key
+            );
+        }
+        if reference.provider == Provider::Literal {
+            environment.push(json!({
+                "name": reference.key,
+                "value": reference.reference,
+            }));
+        } else {
+            let options = &reference.options;
+            let value_from = match (&options.json_key, options.version()) {
+                (None, None) => reference.reference.clone(),
+                _ => format!(
+                    "{}:{}:{}:{}",
+                    reference.reference,
+                    options.json_key.as_deref().unwrap_or_default(),
+                    options.version_stage.as_deref().unwrap_or_default(),
+                    options.version_id.as_deref().unwrap_or_default()
+                ),
+            };
+            secrets.push(json!({
+                "name": reference.key,
+                "valueFrom": value_from,
+            }));
+        }
+    }
+    secrets.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    environment.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+    json!({
+        "secrets": secrets,
+        "environment": environment,
+    })
+}
@@ -0,0 +1,110 @@
+use crate::files::{create_private_dir, memory_backed_dir, write_private_file};
+use crate::logging::{info, warn};
+use crate::secret_manager::SecretsManagerClientTrait;
+use serde_json::Value;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Editor used when neither `VISUAL` nor `EDITOR` is set.
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Fetches a JSON secret, passes it pretty-printed to `edit` and stores the
+/// edited document as a new version of the secret. Returns the id of the new
+/// version, or None when the document wasn't changed.
+///
+/// The secret is fetched again before writing, so that a version created in
+/// the meantime, for example by a rotation, isn't silently overwritten.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client, edit)))]
+pub async fn edit_secret<T, F>(
+    client: &T,
+    secret_id: &str,
+    edit: F,
+) -> Result<Option<String>, Box<dyn Error>>
+where
+    T: SecretsManagerClientTrait + ?Sized,
+    F: FnOnce(&str) -> Result<String, Box<dyn Error>>,
+{
+    info!("Retrieving secret to edit: {}", secret_id);
+    let response = client.get_secret_value(secret_id).await?;
+    let original = response
+        .secret_string()
+        .ok_or_else(|| format!("{} has no SecretString to edit", secret_id))?;
+    let original: Value = serde_json::from_str(original)
+        .map_err(|e| format!("{} is not a JSON secret: {}", secret_id, e))?;
+    let version_id = response.version_id().map(str::to_string);
+
+    let edited = edit(&serde_json::to_string_pretty(&original)?)?;
+    let edited: Value = serde_json::from_str(&edited).map_err(|e| {
+        format!(
+            "The edited secret is not valid JSON, nothing was saved: {}",
+            e
+        )
+    })?;
+    if edited == original {
+        info!("{} was not changed", secret_id);
+        return Ok(None);
+    }
+
+    let current = client.get_secret_value(secret_id).await?;
+    if current.version_id() != version_id.as_deref() {
+        return Err(format!(
+            "{} was changed by someone else while editing, nothing was saved",
+            secret_id
+        )
+        .into());
+    }
+
+    let response = client
+        .put_secret_value(secret_id, &serde_json::to_string(&edited)?)
+        .await?;
+    Ok(Some(
+        response
+            .version_id()
+            .ok_or_else(|| format!("No version id was returned for {}", secret_id))?
+            .to_string(),
+    ))
+}
+
+/// Opens the document in the editor given by `VISUAL` or `EDITOR` and returns
+/// the edited document. The document is written to a file in a new private
+/// directory, in a memory-backed directory when one is available, which is
+/// removed with the backup files of the editor once it exits.
+pub fn edit_in_editor(document: &str) -> Result<String, Box<dyn Error>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or("The editor command is empty")?;
+
+    let parent = memory_backed_dir().unwrap_or_else(|| {
+        warn!("No memory-backed directory is available, the secret is edited on disk");
+        std::env::temp_dir()
+    });
+    let dir = TempDir(create_private_dir(&parent, "resolve-aws-secrets-edit")?);
+    let file = dir.0.join("secret.json");
+    write_private_file(&file, document.as_bytes())?;
+
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&file)
+        .status()
+        .map_err(|e| format!("Failed to start the editor {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!(
+            "The editor exited with {}, nothing was saved",
+            status.code().unwrap_or(1)
+        )
+        .into());
+    }
+    std::fs::read_to_string(&file)
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e).into())
+}
+
+/// Temporary directory removed when dropped, including on errors.
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
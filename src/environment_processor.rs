@@ -1,70 +1,1949 @@
-use crate::secret_manager::{get_secret, SecretsManagerClientTrait};
-use crate::ssm_manager::{get_ssm_parameter, SsmClientTrait};
-use serde_json::Value;
+use crate::aws_clients::{AssumeRole, RetryOptions};
+use crate::cache::{CachedClient, ResponseCache};
+use crate::chaos::Chaos;
+use crate::db_credentials::{render_url, DbCredentials, UrlScheme};
+use crate::diagnostics::{near_miss_warnings, suspicious_value_warnings};
+use crate::errors::{
+    error_message, is_missing_or_denied, ErrorFormat, ResolveError, ResolveErrors,
+};
+use crate::files;
+use crate::kv_store::{get_kv_value, parse_kv_reference, KvStore};
+use crate::logging::{info, warn, LogFormat};
+use crate::redact::SecretString;
+use crate::roles_anywhere::RolesAnywhere;
+use crate::secret_manager::{
+    generate_password, get_secret, rotation_in_progress, store_secret, BatchedClient, BinaryPolicy,
+    PasswordSpec, SecretValue, SecretsManagerClientTrait, VersionSelector, AWSPREVIOUS,
+};
+use crate::ssm_manager::{
+    get_parameters_by_path, get_ssm_parameter, is_parameter_not_found, is_public_parameter,
+    SsmClientTrait,
+};
+use crate::tags;
+use crate::template;
+use futures::StreamExt;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use tracing::{info, instrument, warn};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-#[instrument(skip(secretsmanager_client, ssm_client))]
+#[derive(Debug, Default, Clone)]
+pub struct ResolveOptions {
+    /// Fail on malformed references instead of skipping them with a warning.
+    pub strict: bool,
+    /// Retrieve SecureString SSM parameters without decrypting them.
+    pub no_decrypt: bool,
+    /// Secret versions to retrieve instead of the current ones, per reference.
+    pub pinned_versions: BTreeMap<String, String>,
+    /// Fail for references that have no pinned version.
+    pub locked: bool,
+    /// Retry with the AWSPREVIOUS version when the current one can't be retrieved.
+    pub fallback_previous: bool,
+    /// Retrieve the AWSPREVIOUS version of every secret.
+    pub use_previous: bool,
+    /// Wait up to this long for in-progress rotations to finish before
+    /// retrieving a secret.
+    pub rotation_wait: Option<Duration>,
+    /// Give up on the references still pending once this much time has
+    /// passed since the resolution started.
+    pub resolve_deadline: Option<Duration>,
+    /// What to do with the references still pending at the deadline.
+    pub on_deadline: DeadlinePolicy,
+    /// What to do with the secrets and parameters that don't exist or can't
+    /// be accessed.
+    pub missing: MissingPolicy,
+    /// Give up on an attempt of an AWS call once it takes this long.
+    pub call_timeout: Option<Duration>,
+    /// How to handle values spanning multiple lines.
+    pub multiline: MultilinePolicy,
+    /// Whitespace to trim from the values.
+    pub trim: TrimPolicy,
+    /// How to deliver binary secrets that aren't valid UTF-8.
+    pub binary: BinaryPolicy,
+    /// Accept empty values instead of failing.
+    pub allow_empty: bool,
+    /// Warn about values that look wrong, such as placeholders.
+    pub warn_suspicious: bool,
+    /// Authenticate with IAM Roles Anywhere instead of the default
+    /// credential provider chain.
+    pub roles_anywhere: RolesAnywhere,
+    /// Role to assume with STS before retrieving anything.
+    pub assume_role: AssumeRole,
+    /// Endpoint receiving the AWS calls instead of the AWS one, such as
+    /// LocalStack.
+    pub endpoint_url: Option<String>,
+    /// Retry settings of the AWS calls.
+    pub retry: RetryOptions,
+    /// Cache the resolved values in the OS credential store for this long.
+    pub keychain_ttl: Option<Duration>,
+    /// Cache the retrieved values encrypted on disk.
+    pub disk_cache: DiskCacheOptions,
+    /// Retry acquiring credentials for up to this long before the first call.
+    pub credentials_wait: Option<Duration>,
+    /// How fatal errors are reported on stderr.
+    pub error_format: ErrorFormat,
+    /// Format of the logs, instead of `LOG_FORMAT`.
+    pub log_format: Option<LogFormat>,
+    /// Faults to inject into the resolution, for resilience testing.
+    pub chaos: Chaos,
+    /// Tag of the ECS task or EC2 instance selecting the mapping parameter.
+    pub environment_tag: Option<String>,
+    /// Profile of the mapping to use, instead of `SECRETS_PROFILE`.
+    pub profile: Option<String>,
+    /// Print the AWS API calls made and their estimated cost to stderr.
+    pub report_api_calls: bool,
+    /// References resolved at the same time, `DEFAULT_CONCURRENCY` by default.
+    pub concurrency: Option<usize>,
+    /// Fixtures served to the clients by the in-process test harness,
+    /// instead of calling AWS.
+    pub test_harness: Option<PathBuf>,
+    /// Variables the references are collected from, instead of the process
+    /// environment.
+    pub variables: Option<Variables>,
+    /// Keep the retrieved values for this long, to reuse them in the next
+    /// resolutions with these options. Without it, the values are only
+    /// shared by the references of one resolution.
+    pub cache_ttl: Option<Duration>,
+    /// Values kept across resolutions, shared by the clones of the options.
+    pub cache: Arc<ResponseCache>,
+}
+
+/// Settings of the encrypted on-disk cache of the retrieved values.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiskCacheOptions {
+    /// Keep the values on disk for this long, the cache is disabled without it.
+    pub ttl: Option<Duration>,
+    /// Directory of the cache, instead of the user cache directory.
+    pub dir: Option<PathBuf>,
+    /// age identity file encrypting the cache, created when missing, instead
+    /// of the one in the user configuration directory.
+    pub identity: Option<PathBuf>,
+}
+
+impl ResolveOptions {
+    /// The variables the references are collected from.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        match &self.variables {
+            Some(variables) => variables.0.clone(),
+            None => std::env::vars().collect(),
+        }
+    }
+
+    /// The value of a variable the references are collected from.
+    fn variable(&self, name: &str) -> Option<String> {
+        match &self.variables {
+            Some(variables) => variables
+                .0
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone()),
+            None => std::env::var(name).ok(),
+        }
+    }
+}
+
+/// Variables given to the resolution instead of the process environment.
+/// Only their names are shown in debug output, as some may hold values.
+#[derive(Clone, Default, PartialEq)]
+pub struct Variables(pub Vec<(String, String)>);
+
+impl fmt::Debug for Variables {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(key, _)| key))
+            .finish()
+    }
+}
+
+const ROTATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What to do with the references still pending when the resolution
+/// deadline expires.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DeadlinePolicy {
+    /// Fail the resolution, listing the pending references.
+    #[default]
+    Fail,
+    /// Continue without the pending references, with a warning.
+    Continue,
+}
+
+impl std::str::FromStr for DeadlinePolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "fail" => Ok(DeadlinePolicy::Fail),
+            "continue" => Ok(DeadlinePolicy::Continue),
+            other => Err(format!("unknown deadline policy {}", other)),
+        }
+    }
+}
+
+/// What to do with the secrets and parameters that don't exist or can't be
+/// accessed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MissingPolicy {
+    /// Fail the resolution.
+    #[default]
+    Fail,
+    /// Continue without the variable, with a warning.
+    Warn,
+    /// Set the variable to an empty value, with a warning.
+    Empty,
+}
+
+impl std::str::FromStr for MissingPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "fail" => Ok(MissingPolicy::Fail),
+            "warn" => Ok(MissingPolicy::Warn),
+            "empty" => Ok(MissingPolicy::Empty),
+            other => Err(format!("unknown missing policy {}", other)),
+        }
+    }
+}
+
+/// References resolved at the same time when no limit is given.
+pub const DEFAULT_CONCURRENCY: usize = 10;
+
+/// How often progress is logged while references are still being resolved.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where a reference was declared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A `SECRET_` environment variable.
+    Environment,
+    /// An entry of the mapping stored in the named SSM parameter.
+    SsmMapping(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Environment => write!(f, "env"),
+            Source::SsmMapping(parameter) => write!(f, "ssm-mapping({})", parameter),
+        }
+    }
+}
+
+/// The service a reference is resolved from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Provider {
+    /// A Secrets Manager secret, declared with `SECRET_` or in the mapping.
+    SecretsManager,
+    /// An SSM parameter, declared with `SSM_PARAM_`.
+    SsmParameter,
+    /// A literal value given in the mapping, used as-is.
+    Literal,
+    /// Secrets Manager secrets holding JSON objects, given as an array in
+    /// the mapping and merged with later secrets winning.
+    MergedSecrets,
+    /// A Secrets Manager secret holding a JSON object, declared with
+    /// `SECRET_JSON_`, exported as one variable per key.
+    JsonObject,
+    /// The SSM parameters under a path, declared with
+    /// `SECRETS_PARAMETER_PATH`, exported as one variable per parameter.
+    SsmPath,
+    /// A template rendered from the other resolved values.
+    Template,
+    /// A password generated with GetRandomPassword, declared with
+    /// `GENERATE_SECRET_`.
+    GeneratedPassword,
+    /// A key of a store outside AWS, referenced as `consul://path` or
+    /// `etcd://key`.
+    KeyValue(KvStore),
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provider::SecretsManager => write!(f, "secretsmanager"),
+            Provider::SsmParameter => write!(f, "ssm"),
+            Provider::Literal => write!(f, "literal"),
+            Provider::MergedSecrets => write!(f, "secretsmanager-merge"),
+            Provider::JsonObject => write!(f, "secretsmanager-json"),
+            Provider::SsmPath => write!(f, "ssm-path"),
+            Provider::Template => write!(f, "template"),
+            Provider::GeneratedPassword => write!(f, "generated"),
+            Provider::KeyValue(store) => write!(f, "{}", store),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Resolved,
+    /// Set to an empty value, as the secret doesn't exist or can't be
+    /// accessed.
+    Missing,
+    /// Set to the default value of the reference, as the secret doesn't
+    /// exist or can't be accessed.
+    Default,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Resolved => write!(f, "resolved"),
+            Status::Missing => write!(f, "missing"),
+            Status::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// A secret reference declared for a target variable, not yet resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub key: String,
+    /// Name of the variable or mapping key that declared the reference.
+    pub variable: String,
+    pub source: Source,
+    pub provider: Provider,
+    pub reference: String,
+    pub options: EntryOptions,
+}
+
+/// Options given per secret with the object form of a mapping entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntryOptions {
+    /// Key to extract from a secret holding a JSON object.
+    pub json_key: Option<String>,
+    /// Region to retrieve the secret from instead of the default one.
+    pub region: Option<String>,
+    /// Role to assume for retrieving the secret.
+    pub role: Option<String>,
+    pub transform: Option<Transform>,
+    /// Build a connection URL of this scheme from a JSON database secret.
+    pub url: Option<UrlScheme>,
+    /// Kind of value the secret must hold.
+    pub validate: Option<ValueKind>,
+    /// Regular expression the value must match.
+    pub pattern: Option<String>,
+    /// How to handle a value spanning multiple lines, instead of the global
+    /// policy.
+    pub multiline: Option<MultilinePolicy>,
+    /// Whitespace to trim from the value, instead of the global setting.
+    pub trim: Option<TrimPolicy>,
+    /// Length below which the value is reported as suspicious.
+    pub min_length: Option<usize>,
+    /// Version of the secret to retrieve instead of the current one.
+    pub version_id: Option<String>,
+    /// Staging label of the version to retrieve instead of AWSCURRENT.
+    pub version_stage: Option<String>,
+    /// File the value is written to, with the variable set to its path.
+    pub file: Option<PathBuf>,
+    /// What to do when the secret doesn't exist or can't be accessed,
+    /// instead of the global policy.
+    pub missing: Option<MissingPolicy>,
+    /// Value used when the secret doesn't exist or can't be accessed.
+    pub default: Option<SecretString>,
+    /// How long the retrieved value is reused across resolutions, such as
+    /// those of watch mode, before it is retrieved again.
+    pub refresh: Option<Duration>,
+    /// Secrets merged by an entry given as an array, in order.
+    pub merge: Vec<String>,
+}
+
+impl EntryOptions {
+    /// Whether the secret must be retrieved with a client for another region
+    /// or role.
+    pub fn targets_other_client(&self) -> bool {
+        self.region.is_some() || self.role.is_some()
+    }
+
+    /// The version selected by the entry, if any.
+    pub fn version(&self) -> Option<VersionSelector<'_>> {
+        match (&self.version_id, &self.version_stage) {
+            (Some(version_id), _) => Some(VersionSelector::Id(version_id)),
+            (None, Some(stage)) => Some(VersionSelector::Stage(stage)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Transformation applied to a retrieved value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    Base64Decode,
+}
+
+impl std::str::FromStr for Transform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "base64decode" => Ok(Transform::Base64Decode),
+            other => Err(format!("unknown transform {}", other)),
+        }
+    }
+}
+
+/// Whitespace trimmed from values, as trailing newlines are a frequent
+/// artifact of storing secrets from files.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TrimPolicy {
+    /// Keep the value unchanged.
+    #[default]
+    None,
+    /// Trim trailing newlines.
+    Newlines,
+    /// Trim all surrounding whitespace.
+    Whitespace,
+}
+
+impl std::str::FromStr for TrimPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(TrimPolicy::None),
+            "newlines" => Ok(TrimPolicy::Newlines),
+            "whitespace" => Ok(TrimPolicy::Whitespace),
+            other => Err(format!("unknown trim policy {}", other)),
+        }
+    }
+}
+
+/// How values spanning multiple lines, such as PEM blobs, are handled, as
+/// several consumers break on newlines in environment variables.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MultilinePolicy {
+    /// Pass the value through unchanged.
+    #[default]
+    Pass,
+    /// Fail the resolution.
+    Reject,
+    /// Replace the newlines with a literal `\n`.
+    Escape,
+}
+
+impl std::str::FromStr for MultilinePolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pass" => Ok(MultilinePolicy::Pass),
+            "reject" => Ok(MultilinePolicy::Reject),
+            "escape" => Ok(MultilinePolicy::Escape),
+            other => Err(format!("unknown multiline policy {}", other)),
+        }
+    }
+}
+
+/// Kind of value a secret is validated against, to catch mistakes such as
+/// an ARN pasted as the secret value before the application starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Url,
+    Int,
+    Base64,
+    NonEmpty,
+}
+
+impl ValueKind {
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            ValueKind::Url => value.split_once("://").is_some_and(|(scheme, rest)| {
+                !scheme.is_empty()
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                    && !rest.is_empty()
+            }),
+            ValueKind::Int => value.parse::<i64>().is_ok(),
+            ValueKind::Base64 => {
+                !value.is_empty() && aws_smithy_types::base64::decode(value).is_ok()
+            }
+            ValueKind::NonEmpty => !value.trim().is_empty(),
+        }
+    }
+}
+
+impl std::str::FromStr for ValueKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "url" => Ok(ValueKind::Url),
+            "int" => Ok(ValueKind::Int),
+            "base64" => Ok(ValueKind::Base64),
+            "non-empty" => Ok(ValueKind::NonEmpty),
+            other => Err(format!("unknown validation {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueKind::Url => write!(f, "url"),
+            ValueKind::Int => write!(f, "int"),
+            ValueKind::Base64 => write!(f, "base64"),
+            ValueKind::NonEmpty => write!(f, "non-empty"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedSecret {
+    pub key: String,
+    pub value: SecretString,
+    pub source: Source,
+    pub provider: Provider,
+    pub reference: String,
+    pub version_id: Option<String>,
+    pub status: Status,
+    /// File the value is written to before the command runs, whose path is
+    /// set in the variable instead of the value.
+    pub file: Option<PathBuf>,
+    /// The value is the base64 encoding of a binary secret, decoded when
+    /// written to its file.
+    pub binary: bool,
+    /// How often the value is retrieved again in watch mode, when the
+    /// reference has a refresh period of its own.
+    pub refresh: Option<Duration>,
+    /// Secrets merged into the value, in the order of their version ids.
+    pub merged: Vec<String>,
+}
+
+impl ResolvedSecret {
+    fn new(reference: Reference, secret: SecretValue) -> Self {
+        ResolvedSecret {
+            key: reference.key,
+            value: secret.value,
+            source: reference.source,
+            provider: reference.provider,
+            reference: reference.reference,
+            version_id: secret.version_id,
+            status: Status::Resolved,
+            file: reference.options.file,
+            binary: secret.binary,
+            refresh: reference.options.refresh,
+            merged: reference.options.merge,
+        }
+    }
+}
+
+/// Retrieves a secret honoring the version pinned for it, if any, and the
+/// AWSPREVIOUS fallback settings.
+async fn resolve_secret<S: SecretsManagerClientTrait + ?Sized>(
+    client: &S,
+    reference: &str,
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>> {
+    if let Some(version_id) = options.pinned_versions.get(reference) {
+        return get_secret(
+            client,
+            reference,
+            VersionSelector::Id(version_id),
+            options.binary,
+        )
+        .await;
+    }
+    if options.locked {
+        return Err(format!("{} is not pinned in the lockfile", reference).into());
+    }
+    if options.use_previous {
+        return get_secret(
+            client,
+            reference,
+            VersionSelector::Stage(AWSPREVIOUS),
+            options.binary,
+        )
+        .await;
+    }
+
+    match get_secret(client, reference, VersionSelector::Current, options.binary).await {
+        Err(e) if options.fallback_previous => {
+            warn!(
+                "Failed to retrieve the current version of {}, falling back to {}: {}",
+                reference, AWSPREVIOUS, e
+            );
+            get_secret(
+                client,
+                reference,
+                VersionSelector::Stage(AWSPREVIOUS),
+                options.binary,
+            )
+            .await
+            .map_err(|_| e)
+        }
+        result => result,
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(secretsmanager_client, ssm_client))
+)]
 pub async fn process_environment<S, T>(
     secretsmanager_client: &S,
     ssm_client: &T,
-) -> Result<Vec<(String, String)>, Box<dyn Error>>
+    options: &ResolveOptions,
+) -> Result<Vec<ResolvedSecret>, Box<dyn Error>>
 where
     S: SecretsManagerClientTrait + ?Sized,
     T: SsmClientTrait + ?Sized,
 {
+    let deadline = options
+        .resolve_deadline
+        .map(|timeout| (timeout, tokio::time::Instant::now() + timeout));
+
+    // References to the same secret or parameter share one retrieval.
+    let resolution_cache;
+    let cache = match options.cache_ttl {
+        Some(_) => options.cache.as_ref(),
+        None => {
+            resolution_cache = ResponseCache::default();
+            &resolution_cache
+        }
+    };
+    let uncached_ssm_client = ssm_client;
+    let ssm_client = &CachedClient::new(ssm_client, cache, options.cache_ttl);
+
+    let (references, mut errors) = match deadline {
+        Some((timeout, instant)) => {
+            tokio::time::timeout_at(instant, collect_valid_references(ssm_client, options))
+                .await
+                .map_err(|_| {
+                    format!(
+                        "resolution deadline of {:?} expired while reading the mapping",
+                        timeout
+                    )
+                })??
+        }
+        None => collect_valid_references(ssm_client, options).await?,
+    };
+    let (templates, references): (Vec<_>, Vec<_>) = references
+        .into_iter()
+        .partition(|reference| reference.provider == Provider::Template);
+    let batch = batch_secret_ids(&references, options);
+    // The batches are bound by the deadline too, leaving every reference
+    // pending when it expires.
+    let prefetch = BatchedClient::prefetch(secretsmanager_client, &batch);
+    let batched_client = match deadline {
+        Some((timeout, instant)) => tokio::time::timeout_at(instant, prefetch)
+            .await
+            .unwrap_or_else(|_| {
+                warn!(
+                    "The resolution deadline of {:?} expired while retrieving the secrets in batches",
+                    timeout
+                );
+                BatchedClient::new(secretsmanager_client)
+            }),
+        None => prefetch.await,
+    };
+    let batched_client = &batched_client;
+    let secretsmanager_client = &CachedClient::new(batched_client, cache, options.cache_ttl);
     let mut results = Vec::new();
+    let total = references.len();
+    let started = tokio::time::Instant::now();
+    let mut progress = tokio::time::interval_at(started + PROGRESS_INTERVAL, PROGRESS_INTERVAL);
+    progress.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // Every reference is attempted, so that all failures are reported at
+    // once. Up to the concurrency limit are resolved at the same time, and
+    // the results are kept in the order of the references.
+    let pending: Vec<(String, String)> = references
+        .iter()
+        .map(|reference| (reference.variable.clone(), reference.reference.clone()))
+        .collect();
+    // When each reference being resolved was started, for naming the one
+    // waited for the longest in the progress.
+    let in_flight = &Mutex::new(HashMap::new());
+    let references = references.into_iter().enumerate();
+    let resolutions = futures::stream::iter(references.map(|(index, reference)| async move {
+        in_flight
+            .lock()
+            .unwrap()
+            .insert(index, tokio::time::Instant::now());
+        let result = {
+            // References with a refresh period of their own are answered
+            // from the cache kept across resolutions until it elapses.
+            let resolution = async {
+                match reference.options.refresh {
+                    Some(refresh) => {
+                        let secretsmanager_client =
+                            CachedClient::new(batched_client, &options.cache, Some(refresh));
+                        let ssm_client =
+                            CachedClient::new(uncached_ssm_client, &options.cache, Some(refresh));
+                        resolve_reference(&secretsmanager_client, &ssm_client, &reference, options)
+                            .await
+                    }
+                    None => {
+                        resolve_reference(secretsmanager_client, ssm_client, &reference, options)
+                            .await
+                    }
+                }
+            };
+            // References not started before the deadline, because it expired
+            // during the batches, aren't attempted.
+            match deadline {
+                Some((timeout, instant)) if tokio::time::Instant::now() >= instant => Err(timeout),
+                Some((timeout, instant)) => tokio::time::timeout_at(instant, resolution)
+                    .await
+                    .map_err(|_| timeout),
+                None => Ok(resolution.await),
+            }
+        };
+        in_flight.lock().unwrap().remove(&index);
+        (reference, result)
+    }))
+    .buffered(options.concurrency.unwrap_or(DEFAULT_CONCURRENCY));
+    tokio::pin!(resolutions);
 
-    for (key, value) in std::env::vars() {
-        if key.starts_with("SECRET_") && value.starts_with("arn:") {
-            let secret_value = get_secret(secretsmanager_client, &value).await?;
-            results.push((key.trim_start_matches("SECRET_").to_string(), secret_value));
+    let mut done = 0;
+    loop {
+        let (reference, result) = tokio::select! {
+            resolution = resolutions.next() => match resolution {
+                Some(resolution) => resolution,
+                None => break,
+            },
+            _ = progress.tick() => {
+                let oldest = in_flight
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .min_by_key(|(_, started)| **started)
+                    .map(|(&index, started)| (index, started.elapsed()));
+                if let Some((index, waiting)) = oldest {
+                    let (variable, reference) = &pending[index];
+                    info!(
+                        "Resolved {} of {} references, {} pending, waiting {:?} for {} ({})",
+                        done,
+                        total,
+                        total - done,
+                        waiting,
+                        variable,
+                        reference
+                    );
+                }
+                continue;
+            }
+        };
+        done += 1;
+        let result = match result {
+            Ok(result) => result,
+            Err(timeout) if options.on_deadline == DeadlinePolicy::Continue => {
+                warn!(
+                    "Continuing without {} ({}), still pending when the resolution deadline of {:?} expired",
+                    reference.variable, reference.reference, timeout
+                );
+                continue;
+            }
+            Err(timeout) => Err(format!(
+                "still pending when the resolution deadline of {:?} expired",
+                timeout
+            )
+            .into()),
+        };
+        match result {
+            Ok(secret_value)
+                if matches!(reference.provider, Provider::JsonObject | Provider::SsmPath) =>
+            {
+                match expand_object(&reference, secret_value, options) {
+                    Ok(expanded) => results.extend(expanded),
+                    Err(cause) => errors.push(ResolveError {
+                        variable: reference.variable,
+                        reference: reference.reference,
+                        cause,
+                    }),
+                }
+            }
+            Ok(mut secret_value) => {
+                // A generated value written back to Secrets Manager is
+                // injected along with the ARN of the secret holding it.
+                let stored = secret_value.stored_arn.take().map(|arn| {
+                    let arn_reference = Reference {
+                        key: format!("{}_ARN", reference.key),
+                        provider: Provider::Literal,
+                        reference: arn.clone(),
+                        options: EntryOptions::default(),
+                        ..reference.clone()
+                    };
+                    let arn_value = SecretValue {
+                        value: arn.into(),
+                        version_id: None,
+                        stored_arn: None,
+                        binary: false,
+                    };
+                    ResolvedSecret::new(arn_reference, arn_value)
+                });
+                // Binary secrets delivered in files without a target file of
+                // their own are written to a temporary one.
+                let file = match secret_value.binary && reference.options.file.is_none() {
+                    true => match files::temporary_path(&reference.key) {
+                        Ok(path) => Some(path),
+                        Err(cause) => {
+                            errors.push(ResolveError {
+                                variable: reference.variable,
+                                reference: reference.reference,
+                                cause,
+                            });
+                            continue;
+                        }
+                    },
+                    false => None,
+                };
+                let mut secret = ResolvedSecret::new(reference, secret_value);
+                secret.file = secret.file.or(file);
+                results.push(secret);
+                results.extend(stored);
+            }
+            Err(cause)
+                if reference.options.default.is_some() && is_missing_or_denied(cause.as_ref()) =>
+            {
+                warn!(
+                    "Setting {} to its default value ({}): {}",
+                    reference.key,
+                    reference.reference,
+                    error_message(cause.as_ref())
+                );
+                let default = SecretValue {
+                    value: reference.options.default.clone().unwrap_or_default(),
+                    version_id: None,
+                    stored_arn: None,
+                    binary: false,
+                };
+                let mut secret = ResolvedSecret::new(reference, default);
+                secret.status = Status::Default;
+                results.push(secret);
+            }
+            Err(cause) => {
+                let missing = reference.options.missing.unwrap_or(options.missing);
+                if missing == MissingPolicy::Fail || !is_missing_or_denied(cause.as_ref()) {
+                    errors.push(ResolveError {
+                        variable: reference.variable,
+                        reference: reference.reference,
+                        cause,
+                    });
+                    continue;
+                }
+                let expanded =
+                    matches!(reference.provider, Provider::JsonObject | Provider::SsmPath);
+                if missing == MissingPolicy::Warn || expanded {
+                    warn!(
+                        "Continuing without {} ({}): {}",
+                        reference.variable,
+                        reference.reference,
+                        error_message(cause.as_ref())
+                    );
+                    continue;
+                }
+                warn!(
+                    "Setting {} to an empty value ({}): {}",
+                    reference.key,
+                    reference.reference,
+                    error_message(cause.as_ref())
+                );
+                let empty = SecretValue {
+                    value: SecretString::default(),
+                    version_id: None,
+                    stored_arn: None,
+                    binary: false,
+                };
+                let mut secret = ResolvedSecret::new(reference, empty);
+                secret.status = Status::Missing;
+                results.push(secret);
+            }
         }
     }
 
-    if let Ok(ssm_arn) = std::env::var("SECRETS_PARAMETER_ARN") {
-        let ssm_secrets =
-            process_ssm_parameter(ssm_client, secretsmanager_client, &ssm_arn).await?;
-        results.extend(ssm_secrets);
+    // Templates are rendered once every other reference is resolved, and may
+    // refer to the templates declared before them.
+    for reference in templates {
+        let rendered = template::render(&reference.reference, |name| {
+            results
+                .iter()
+                .find(|secret| secret.key == name)
+                .map(|secret| secret.value.expose())
+        });
+        let rendered = rendered
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|value| apply_value_policies(&reference, options, value));
+        match rendered {
+            Ok(value) => {
+                let value = SecretValue {
+                    value: value.into(),
+                    version_id: None,
+                    stored_arn: None,
+                    binary: false,
+                };
+                results.push(ResolvedSecret::new(reference, value));
+            }
+            Err(cause) => errors.push(ResolveError {
+                variable: reference.variable,
+                reference: reference.reference,
+                cause,
+            }),
+        }
     }
 
-    if let Ok(ssm_name) = std::env::var("SECRETS_PARAMETER_NAME") {
-        let ssm_secrets =
-            process_ssm_parameter(ssm_client, secretsmanager_client, &ssm_name).await?;
-        results.extend(ssm_secrets);
+    aggregate(results, errors)
+}
+
+/// Returns the value, or fails with every error at once.
+fn aggregate<T>(value: T, mut errors: Vec<ResolveError>) -> Result<T, Box<dyn Error>> {
+    match errors.len() {
+        0 => Ok(value),
+        1 => Err(errors.remove(0).into()),
+        _ => Err(ResolveErrors(errors).into()),
     }
+}
 
-    Ok(results)
+/// The secrets that can be prefetched with BatchGetSecretValue, which only
+/// retrieves the current versions with the default client, among those not
+/// cached yet. Nothing is batched for a single secret, which is retrieved
+/// just as well alone.
+fn batch_secret_ids(references: &[Reference], options: &ResolveOptions) -> Vec<String> {
+    if options.locked
+        || options.use_previous
+        || options.rotation_wait.is_some()
+        || options.keychain_ttl.is_some()
+        || options.disk_cache.ttl.is_some()
+    {
+        return Vec::new();
+    }
+    let mut secret_ids: Vec<String> = Vec::new();
+    for reference in references {
+        let batched = matches!(
+            reference.provider,
+            Provider::SecretsManager | Provider::JsonObject
+        ) && !reference.options.targets_other_client()
+            && reference.options.version().is_none()
+            && !options.pinned_versions.contains_key(&reference.reference)
+            && !secret_ids.contains(&reference.reference)
+            && !reference
+                .options
+                .refresh
+                .or(options.cache_ttl)
+                .is_some_and(|ttl| {
+                    options
+                        .cache
+                        .has_fresh_secret(&reference.reference, Some(ttl))
+                });
+        if batched {
+            secret_ids.push(reference.reference.clone());
+        }
+    }
+    if secret_ids.len() < 2 {
+        secret_ids.clear();
+    }
+    secret_ids
 }
 
-#[instrument(skip(ssm_client, secretsmanager_client))]
-async fn process_ssm_parameter<
+/// Splits the JSON object a `SECRET_JSON_` or `SECRETS_PARAMETER_PATH`
+/// reference resolved to into one resolved value per key, and applies the
+/// value policies to each. The keys of a JSON secret are prefixed with the
+/// name of the reference, and parameters are named after the last segment of
+/// their path. Names that aren't valid variable names are skipped, or fail
+/// the resolution in strict mode.
+fn expand_object(
+    reference: &Reference,
+    secret: SecretValue,
+    options: &ResolveOptions,
+) -> Result<Vec<ResolvedSecret>, Box<dyn Error>> {
+    let Ok(Value::Object(object)) = serde_json::from_str(secret.value.expose()) else {
+        return Err("The secret does not contain a JSON object".into());
+    };
+
+    let mut expanded: Vec<ResolvedSecret> = Vec::new();
+    for (key, value) in object {
+        let key_reference = match reference.provider {
+            Provider::SsmPath => Reference {
+                key: key.rsplit('/').next().unwrap_or_default().to_string(),
+                provider: Provider::SsmParameter,
+                reference: key.clone(),
+                ..reference.clone()
+            },
+            _ => Reference {
+                key: match reference.key.as_str() {
+                    "" => key.clone(),
+                    prefix => format!("{}_{}", prefix, key),
+                },
+                provider: Provider::SecretsManager,
+                options: EntryOptions {
+                    json_key: Some(key.clone()),
+                    ..reference.options.clone()
+                },
+                ..reference.clone()
+            },
+        };
+        let name = &key_reference.key;
+        if !is_variable_name(name) {
+            if options.strict {
+                return Err(format!("{} is not a valid variable name", name).into());
+            }
+            warn!(
+                "Skipping {} of {} as {} is not a valid variable name",
+                key, reference.variable, name
+            );
+            continue;
+        }
+        if let Some(other) = expanded.iter().find(|secret| &secret.key == name) {
+            return Err(format!("{} and {} both define {}", other.reference, key, name).into());
+        }
+
+        let value = match value {
+            Value::String(value) => value,
+            Value::Null => String::new(),
+            other => other.to_string(),
+        };
+        let value = apply_value_policies(&key_reference, options, value)
+            .map_err(|e| format!("{}: {}", name, e))?;
+        let value = SecretValue {
+            value: value.into(),
+            version_id: secret.version_id.clone(),
+            stored_arn: None,
+            binary: false,
+        };
+        expanded.push(ResolvedSecret::new(key_reference, value));
+    }
+    Ok(expanded)
+}
+
+/// Whether the name can be used as an environment variable by shells.
+pub(crate) fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Resolves one reference, applying the value policies.
+pub async fn resolve_reference<S, T>(
+    secretsmanager_client: &S,
+    ssm_client: &T,
+    reference: &Reference,
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>>
+where
     S: SecretsManagerClientTrait + ?Sized,
     T: SsmClientTrait + ?Sized,
->(
+{
+    options.chaos.inject(reference).await?;
+    let mut secret =
+        fetch_cached_reference(secretsmanager_client, ssm_client, reference, options).await?;
+    // The policies apply to each value of an expanded object instead.
+    if !matches!(reference.provider, Provider::JsonObject | Provider::SsmPath) {
+        secret.value = apply_value_policies(reference, options, secret.value.into_inner())?.into();
+    }
+    Ok(secret)
+}
+
+/// Fetches the reference, going through the OS credential store or disk
+/// cache when enabled. Generated passwords are never cached.
+async fn fetch_cached_reference<S, T>(
+    secretsmanager_client: &S,
     ssm_client: &T,
+    reference: &Reference,
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SsmClientTrait + ?Sized,
+{
+    #[cfg(feature = "keychain")]
+    if let Some(ttl) = options.keychain_ttl {
+        if !matches!(
+            reference.provider,
+            Provider::GeneratedPassword | Provider::Literal
+        ) {
+            if let Some(secret) = crate::keychain::lookup(reference).await {
+                return Ok(secret);
+            }
+            let secret =
+                fetch_reference(secretsmanager_client, ssm_client, reference, options).await?;
+            crate::keychain::store(reference, &secret, ttl).await;
+            return Ok(secret);
+        }
+    }
+    #[cfg(feature = "disk-cache")]
+    if let Some(ttl) = options.disk_cache.ttl {
+        if !matches!(
+            reference.provider,
+            Provider::GeneratedPassword | Provider::Literal
+        ) {
+            if let Some(secret) = crate::disk_cache::lookup(reference, options).await {
+                return Ok(secret);
+            }
+            let secret =
+                fetch_reference(secretsmanager_client, ssm_client, reference, options).await?;
+            crate::disk_cache::store(reference, options, &secret, ttl).await;
+            return Ok(secret);
+        }
+    }
+    fetch_reference(secretsmanager_client, ssm_client, reference, options).await
+}
+
+/// Applies the policies controlling the shape of every resolved value.
+fn apply_value_policies(
+    reference: &Reference,
+    options: &ResolveOptions,
+    value: String,
+) -> Result<String, Box<dyn Error>> {
+    let value = match reference.options.trim.unwrap_or(options.trim) {
+        TrimPolicy::None => value,
+        TrimPolicy::Newlines => value.trim_end_matches(['\r', '\n']).to_string(),
+        TrimPolicy::Whitespace => value.trim().to_string(),
+    };
+
+    // An empty value is almost always a misconfiguration, unless it was
+    // given literally.
+    if value.is_empty() && !options.allow_empty && reference.provider != Provider::Literal {
+        return Err("The value is empty, use --allow-empty if this is intended".into());
+    }
+
+    for warning in suspicious_value_warnings(reference, &value, options.warn_suspicious) {
+        warn!("{}", warning);
+    }
+
+    let multiline = reference.options.multiline.unwrap_or(options.multiline);
+    if !value.contains('\n') {
+        return Ok(value);
+    }
+    match multiline {
+        MultilinePolicy::Pass => Ok(value),
+        MultilinePolicy::Reject => Err("The value spans multiple lines".into()),
+        MultilinePolicy::Escape => Ok(value.replace("\r\n", "\n").replace('\n', "\\n")),
+    }
+}
+
+async fn fetch_reference<S, T>(
     secretsmanager_client: &S,
-    arn: &str,
-) -> Result<Vec<(String, String)>, Box<dyn Error>> {
-    info!("Processing SSM parameter: {}", arn);
-    let parameter_value = get_ssm_parameter(ssm_client, arn).await?;
-    let json_value: Value = serde_json::from_str(&parameter_value)?;
+    ssm_client: &T,
+    reference: &Reference,
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SsmClientTrait + ?Sized,
+{
+    match reference.provider {
+        Provider::SecretsManager if reference.options.targets_other_client() => {
+            let client = secretsmanager_client
+                .with_target(
+                    reference.options.region.as_deref(),
+                    reference.options.role.as_deref(),
+                )
+                .await?;
+            resolve_secret_entry(client.as_ref(), reference, options).await
+        }
+        Provider::SecretsManager | Provider::JsonObject => {
+            resolve_secret_entry(secretsmanager_client, reference, options).await
+        }
+        Provider::MergedSecrets => {
+            resolve_merged_secrets(secretsmanager_client, &reference.options.merge, options).await
+        }
+        Provider::SsmParameter => {
+            resolve_parameter(ssm_client, &reference.reference, options).await
+        }
+        Provider::SsmPath => {
+            let parameters =
+                get_parameters_by_path(ssm_client, &reference.reference, !options.no_decrypt)
+                    .await?;
+            if parameters.is_empty() {
+                return Err(
+                    format!("No parameters were found under {}", reference.reference).into(),
+                );
+            }
+            // Carried as a JSON object of the parameter names and values,
+            // which is expanded into one variable per parameter.
+            Ok(SecretValue {
+                value: Value::Object(
+                    parameters
+                        .into_iter()
+                        .map(|(name, value)| (name, Value::String(value)))
+                        .collect(),
+                )
+                .to_string()
+                .into(),
+                version_id: None,
+                stored_arn: None,
+                binary: false,
+            })
+        }
+        Provider::Literal => Ok(SecretValue {
+            value: reference.reference.as_str().into(),
+            version_id: None,
+            stored_arn: None,
+            binary: false,
+        }),
+        Provider::Template => {
+            Err("Templates are rendered after the other references are resolved".into())
+        }
+        Provider::KeyValue(store) => {
+            let (_, key) =
+                parse_kv_reference(&reference.reference).ok_or("Invalid key-value reference")?;
+            Ok(SecretValue {
+                value: get_kv_value(store, key).await?.into(),
+                version_id: None,
+                stored_arn: None,
+                binary: false,
+            })
+        }
+        Provider::GeneratedPassword => {
+            let spec: PasswordSpec = reference.reference.parse()?;
+            let value = generate_password(secretsmanager_client, &spec).await?;
+            let (stored_arn, version_id) = match &spec.store {
+                Some(name) => {
+                    let (arn, version_id) =
+                        store_secret(secretsmanager_client, name, &value).await?;
+                    (Some(arn), version_id)
+                }
+                None => (None, None),
+            };
+            Ok(SecretValue {
+                value: value.into(),
+                version_id,
+                stored_arn,
+                binary: false,
+            })
+        }
+    }
+}
+
+/// Retrieves a Secrets Manager secret and applies the entry options to it.
+async fn resolve_secret_entry<S: SecretsManagerClientTrait + ?Sized>(
+    client: &S,
+    reference: &Reference,
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>> {
+    let mut secret = match reference.options.version() {
+        // An explicitly selected version is retrieved as is, so that it can
+        // be tested, for example during a rotation.
+        Some(version) => get_secret(client, &reference.reference, version, options.binary).await?,
+        None => {
+            if let Some(timeout) = options.rotation_wait {
+                wait_for_rotation(client, &reference.reference, timeout).await?;
+            }
+            resolve_secret(client, &reference.reference, options).await?
+        }
+    };
+    secret.value = apply_entry_options(&reference.options, secret.value.into_inner())?.into();
+    Ok(secret)
+}
+
+/// Extracts the requested key from a JSON secret, then applies the
+/// transformation and builds the connection URL, if any, and finally
+/// validates the value.
+fn apply_entry_options(entry: &EntryOptions, value: String) -> Result<String, Box<dyn Error>> {
+    let value = match &entry.json_key {
+        Some(key) => {
+            let object: Value = serde_json::from_str(&value)
+                .map_err(|_| format!("Cannot extract {} as the secret is not JSON", key))?;
+            match object.get(key) {
+                Some(Value::String(value)) => value.clone(),
+                Some(Value::Null) | None => {
+                    return Err(format!("Key {} was not found in the secret", key).into())
+                }
+                Some(other) => other.to_string(),
+            }
+        }
+        None => value,
+    };
+
+    let value = match entry.transform {
+        Some(Transform::Base64Decode) => {
+            let decoded = aws_smithy_types::base64::decode(value.trim())
+                .map_err(|e| format!("Cannot base64-decode the secret: {}", e))?;
+            String::from_utf8(decoded)
+                .map_err(|_| "The base64-decoded secret is not valid UTF-8")?
+        }
+        None => value,
+    };
+
+    let value = match entry.url {
+        Some(scheme) => {
+            let credentials = DbCredentials::from_json(&value)
+                .map_err(|e| format!("Cannot build a {} URL: {}", scheme, e))?;
+            render_url(scheme, &credentials)?
+        }
+        None => value,
+    };
+
+    // The value itself is never part of the error, as it's a secret.
+    if let Some(kind) = entry.validate {
+        if !kind.accepts(&value) {
+            return Err(format!("The value is not a valid {}", kind).into());
+        }
+    }
+    if let Some(pattern) = &entry.pattern {
+        if !Regex::new(pattern)?.is_match(&value) {
+            return Err(format!("The value does not match the pattern {}", pattern).into());
+        }
+    }
+    Ok(value)
+}
+
+/// Retrieves each of the secrets, which must hold JSON objects, and merges
+/// their keys, later secrets overriding earlier ones.
+async fn resolve_merged_secrets<S: SecretsManagerClientTrait + ?Sized>(
+    client: &S,
+    references: &[String],
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>> {
+    let mut merged = serde_json::Map::new();
+    let mut version_ids = Vec::new();
+
+    for reference in references {
+        if let Some(timeout) = options.rotation_wait {
+            wait_for_rotation(client, reference, timeout).await?;
+        }
+        let secret = resolve_secret(client, reference, options).await?;
+        match serde_json::from_str(secret.value.expose()) {
+            Ok(Value::Object(object)) => merged.extend(object),
+            _ => return Err(format!("{} does not contain a JSON object", reference).into()),
+        }
+        version_ids.push(secret.version_id);
+    }
+
+    let version_ids: Option<Vec<String>> = version_ids.into_iter().collect();
+    Ok(SecretValue {
+        value: Value::Object(merged).to_string().into(),
+        version_id: version_ids.map(|ids| ids.join(",")),
+        stored_arn: None,
+        binary: false,
+    })
+}
+
+/// Retrieves an SSM parameter. Public parameters are plain strings, so they
+/// are never decrypted, and they only exist in the regions where AWS
+/// publishes them, which is pointed out when they can't be found.
+async fn resolve_parameter<T: SsmClientTrait + ?Sized>(
+    client: &T,
+    name: &str,
+    options: &ResolveOptions,
+) -> Result<SecretValue, Box<dyn Error>> {
+    let public = is_public_parameter(name);
+    let with_decryption = !options.no_decrypt && !public;
+
+    match get_ssm_parameter(client, name, with_decryption).await {
+        Ok(value) => Ok(SecretValue {
+            value: value.into(),
+            version_id: None,
+            stored_arn: None,
+            binary: false,
+        }),
+        Err(e) if public && is_parameter_not_found(e.as_ref()) => Err(format!(
+            "Public parameter {} was not found, it may not be published in this region",
+            name
+        )
+        .into()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Waits until no rotation of the secret is in progress, giving up with a
+/// warning once the timeout expires.
+async fn wait_for_rotation<S: SecretsManagerClientTrait + ?Sized>(
+    client: &S,
+    reference: &str,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    while rotation_in_progress(client, reference).await? {
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            warn!(
+                "Rotation of {} is still in progress after {:?}, continuing with the current version",
+                reference, timeout
+            );
+            return Ok(());
+        }
+        info!(
+            "Rotation of {} is in progress, waiting for it to finish",
+            reference
+        );
+        tokio::time::sleep(ROTATION_POLL_INTERVAL.min(deadline - now)).await;
+    }
+
+    Ok(())
+}
+
+/// Collects the references declared through `SECRET_` variables and the SSM
+/// parameter mapping, without retrieving any secret.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(ssm_client)))]
+pub async fn collect_references<T>(
+    ssm_client: &T,
+    options: &ResolveOptions,
+) -> Result<Vec<Reference>, Box<dyn Error>>
+where
+    T: SsmClientTrait + ?Sized,
+{
+    let (references, errors) = collect_valid_references(ssm_client, options).await?;
+    aggregate(references, errors)
+}
+
+/// Collects the references, along with the failures of the variables and
+/// mapping entries that can't be used, so that they are all reported
+/// together with the failures of the resolution.
+async fn collect_valid_references<T>(
+    ssm_client: &T,
+    options: &ResolveOptions,
+) -> Result<(Vec<Reference>, Vec<ResolveError>), Box<dyn Error>>
+where
+    T: SsmClientTrait + ?Sized,
+{
+    // The values that aren't references are left out of the errors, as they
+    // may be secrets set by mistake.
+    let invalid = |variable: String, cause: &str| ResolveError {
+        variable,
+        reference: String::new(),
+        cause: cause.into(),
+    };
     let mut results = Vec::new();
+    let mut errors = Vec::new();
+    let vars = options.environment();
 
-    if let Value::Object(obj) = json_value {
-        for (key, value) in obj {
-            if let Value::String(arn) = value {
-                let stripped_key = key.strip_prefix("SECRET_").unwrap_or(&key);
-                info!("Processing secret {} from SSM parameter", stripped_key);
-                let secret_value = get_secret(secretsmanager_client, &arn).await?;
-                results.push((stripped_key.to_string(), secret_value));
+    for warning in near_miss_warnings(&vars) {
+        warn!("{}", warning);
+    }
+
+    for (key, value) in vars {
+        if let Some(name) = key.strip_prefix("SSM_PARAM_") {
+            if !value.is_empty() {
+                results.push(Reference {
+                    key: name.to_string(),
+                    variable: key.clone(),
+                    source: Source::Environment,
+                    provider: Provider::SsmParameter,
+                    reference: value,
+                    options: EntryOptions::default(),
+                });
+            } else if options.strict {
+                errors.push(invalid(key, "does not contain an SSM parameter name"));
+            } else {
+                warn!(
+                    "Skipping {} as it does not contain an SSM parameter name",
+                    key
+                );
+            }
+            continue;
+        }
+        if let Some(name) = key.strip_prefix("GENERATE_SECRET_") {
+            if let Err(e) = value.parse::<PasswordSpec>() {
+                let cause = format!("not a valid password specification: {}", e);
+                errors.push(invalid(key, &cause));
+                continue;
+            }
+            results.push(Reference {
+                key: name.to_string(),
+                variable: key.clone(),
+                source: Source::Environment,
+                provider: Provider::GeneratedPassword,
+                reference: value,
+                options: EntryOptions::default(),
+            });
+            continue;
+        }
+        if let Some(prefix) = key.strip_prefix("SECRET_JSON_") {
+            if value.starts_with("arn:") {
+                let (_, reference, entry_options) = string_reference(value);
+                results.push(Reference {
+                    key: prefix.to_string(),
+                    variable: key.clone(),
+                    source: Source::Environment,
+                    provider: Provider::JsonObject,
+                    reference,
+                    options: entry_options,
+                });
+            } else if options.strict {
+                errors.push(invalid(key, "does not contain a Secrets Manager ARN"));
+            } else {
+                warn!("Skipping {} as its value is not a Secrets Manager ARN", key);
+            }
+            continue;
+        }
+        if let Some(name) = key.strip_prefix("SECRET_FILE_") {
+            match value.rsplit_once("->") {
+                Some((reference, path))
+                    if !path.is_empty()
+                        && (reference.starts_with("arn:")
+                            || parse_kv_reference(reference).is_some()) =>
+                {
+                    let (provider, reference, mut entry_options) =
+                        string_reference(reference.to_string());
+                    entry_options.file = Some(path.into());
+                    results.push(Reference {
+                        key: name.to_string(),
+                        variable: key.clone(),
+                        source: Source::Environment,
+                        provider,
+                        reference,
+                        options: entry_options,
+                    });
+                }
+                _ if options.strict => errors.push(invalid(key, "does not contain a Secrets Manager ARN and a file, as in arn:...->/run/secrets/file",
+                )),
+                _ => warn!(
+                    "Skipping {} as its value is not a Secrets Manager ARN and a file",
+                    key
+                ),
+            }
+            continue;
+        }
+        if let Some(name) = key.strip_prefix("OPTIONAL_SECRET_") {
+            if value.starts_with("arn:") || parse_kv_reference(&value).is_some() {
+                let (provider, reference, mut entry_options) = string_reference(value);
+                // Optional secrets don't fail the resolution, whatever the
+                // global policy.
+                entry_options.missing = Some(match options.missing {
+                    MissingPolicy::Fail => MissingPolicy::Warn,
+                    policy => policy,
+                });
+                results.push(Reference {
+                    key: name.to_string(),
+                    variable: key.clone(),
+                    source: Source::Environment,
+                    provider,
+                    reference,
+                    options: entry_options,
+                });
+            } else if options.strict {
+                errors.push(invalid(key, "does not contain a Secrets Manager ARN"));
             } else {
-                warn!("Unexpected value type for key {} in SSM parameter", key);
+                warn!("Skipping {} as its value is not a Secrets Manager ARN", key);
+            }
+            continue;
+        }
+        if !key.starts_with("SECRET_") {
+            continue;
+        }
+        if value.starts_with("arn:") || parse_kv_reference(&value).is_some() {
+            let (provider, reference, entry_options) = string_reference(value);
+            results.push(Reference {
+                key: key.trim_start_matches("SECRET_").to_string(),
+                variable: key.clone(),
+                source: Source::Environment,
+                provider,
+                reference,
+                options: entry_options,
+            });
+        } else if options.strict {
+            errors.push(invalid(key, "does not contain a Secrets Manager ARN"));
+        } else {
+            warn!("Skipping {} as its value is not a Secrets Manager ARN", key);
+        }
+    }
+
+    if let Some(path) = options.variable("SECRETS_PARAMETER_PATH") {
+        results.push(Reference {
+            key: String::new(),
+            variable: "SECRETS_PARAMETER_PATH".to_string(),
+            source: Source::Environment,
+            provider: Provider::SsmPath,
+            reference: path,
+            options: EntryOptions::default(),
+        });
+    }
+
+    for variable in mapping_variables(options).await? {
+        if let Some(parameter) = options.variable(&variable) {
+            match process_ssm_parameter(ssm_client, &parameter, options).await {
+                Ok((ssm_secrets, entry_errors)) => {
+                    results.extend(ssm_secrets);
+                    errors.extend(entry_errors);
+                }
+                Err(cause) => errors.push(ResolveError {
+                    variable,
+                    reference: parameter,
+                    cause,
+                }),
             }
         }
+    }
+
+    let (references, placeholder_errors) = expand_placeholders(results, options);
+    errors.extend(placeholder_errors);
+    Ok((references, errors))
+}
+
+/// Expands the `{NAME}` placeholders of the references from the environment,
+/// such as `arn:...:secret:myapp/{STAGE}/db`, so one task definition works
+/// across stages. Templates and literal values are left as-is, as templates
+/// use placeholders for the resolved values.
+fn expand_placeholders(
+    references: Vec<Reference>,
+    options: &ResolveOptions,
+) -> (Vec<Reference>, Vec<ResolveError>) {
+    let environment: HashMap<String, String> = options.environment().into_iter().collect();
+    let (expanded, errors): (Vec<_>, Vec<_>) = references
+        .into_iter()
+        .map(|mut reference| {
+            if matches!(reference.provider, Provider::Template | Provider::Literal) {
+                return Ok(reference);
+            }
+            match expand_reference(&mut reference, &environment) {
+                Ok(()) => Ok(reference),
+                Err(cause) => Err(ResolveError {
+                    cause,
+                    variable: reference.variable,
+                    reference: reference.reference,
+                }),
+            }
+        })
+        .partition(Result::is_ok);
+    (
+        expanded.into_iter().filter_map(Result::ok).collect(),
+        errors.into_iter().filter_map(Result::err).collect(),
+    )
+}
+
+/// Expands the placeholders of the secret id and of the key, version,
+/// region and role split out of the reference or given in the mapping. The
+/// default value is taken literally. As `@STAGE`, `#key` and `|default` are
+/// split out of a Secrets Manager reference before the expansion, the
+/// expanded id is split again, so a placeholder can expand to them too.
+fn expand_reference(
+    reference: &mut Reference,
+    environment: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let expand = |text: &mut String| -> Result<(), Box<dyn Error>> {
+        if !text.contains('{') {
+            return Ok(());
+        }
+        let missing = std::cell::RefCell::new(None);
+        let expanded = template::render(text, |name| {
+            let value = environment.get(name).map(String::as_str);
+            if value.is_none() {
+                missing.borrow_mut().get_or_insert_with(|| name.to_string());
+            }
+            value
+        });
+        *text = expanded.map_err(|e| match missing.into_inner() {
+            Some(name) => format!("{} is not set in the environment", name),
+            None => e,
+        })?;
+        Ok(())
+    };
+
+    let placeholders = reference.reference.contains('{');
+    expand(&mut reference.reference)?;
+    let options = &mut reference.options;
+    for component in [
+        &mut options.json_key,
+        &mut options.version_id,
+        &mut options.version_stage,
+        &mut options.region,
+        &mut options.role,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        expand(component)?;
+    }
+
+    if placeholders && reference.provider == Provider::SecretsManager {
+        let (_, secret_id, split) = string_reference(std::mem::take(&mut reference.reference));
+        reference.reference = secret_id;
+        options.json_key = split.json_key.or(options.json_key.take());
+        options.version_id = split.version_id.or(options.version_id.take());
+        options.version_stage = split.version_stage.or(options.version_stage.take());
+        options.default = split.default.or(options.default.take());
+    }
+    Ok(())
+}
+
+/// The variables giving the mapping parameter. With an environment tag, the
+/// variables suffixed with the environment, such as
+/// `SECRETS_PARAMETER_NAME_STAGING`, are used when any is set.
+async fn mapping_variables(options: &ResolveOptions) -> Result<Vec<String>, Box<dyn Error>> {
+    let variables = ["SECRETS_PARAMETER_ARN", "SECRETS_PARAMETER_NAME"].map(String::from);
+    let Some(tag) = &options.environment_tag else {
+        return Ok(variables.to_vec());
+    };
+    let suffix = tags::variable_suffix(&tags::tag_value(tag).await?);
+    let selected = variables
+        .clone()
+        .map(|variable| format!("{}_{}", variable, suffix));
+    if selected
+        .iter()
+        .any(|variable| options.variable(variable).is_some())
+    {
+        return Ok(selected.to_vec());
+    }
+    warn!(
+        "Neither {} nor {} is set, using the default mapping",
+        selected[0], selected[1]
+    );
+    Ok(variables.to_vec())
+}
+
+/// Collects the references of the mapping parameter, along with the failures
+/// of its invalid entries in strict mode.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(ssm_client)))]
+async fn process_ssm_parameter<T: SsmClientTrait + ?Sized>(
+    ssm_client: &T,
+    parameter: &str,
+    options: &ResolveOptions,
+) -> Result<(Vec<Reference>, Vec<ResolveError>), Box<dyn Error>> {
+    info!("Processing SSM parameter: {}", parameter);
+    let parameter_value = get_ssm_parameter(ssm_client, parameter, !options.no_decrypt).await?;
+    let json_value: Value = serde_json::from_str(&parameter_value)?;
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+
+    if let Value::Object(obj) = json_value {
+        let profile = options
+            .profile
+            .clone()
+            .or_else(|| options.variable("SECRETS_PROFILE"));
+        for (key, value) in select_profile(obj, profile.as_deref())? {
+            let (provider, reference, entry_options) = match mapping_entry(value) {
+                Ok(entry) => entry,
+                Err(reason) if options.strict => {
+                    errors.push(ResolveError {
+                        variable: format!("{} in {}", key, parameter),
+                        reference: String::new(),
+                        cause: format!(
+                            "Invalid value for key {} in SSM parameter: {}",
+                            key, reason
+                        )
+                        .into(),
+                    });
+                    continue;
+                }
+                Err(reason) => {
+                    warn!("Invalid value for key {} in SSM parameter: {}", key, reason);
+                    continue;
+                }
+            };
+            let stripped_key = key.strip_prefix("SECRET_").unwrap_or(&key);
+            info!("Processing secret {} from SSM parameter", stripped_key);
+            results.push(Reference {
+                key: stripped_key.to_string(),
+                variable: format!("{} in {}", key, parameter),
+                source: Source::SsmMapping(parameter.to_string()),
+                provider,
+                reference,
+                options: entry_options,
+            });
+        }
+    } else if options.strict {
+        return Err(format!("SSM parameter {} value is not an object", parameter).into());
     } else {
         warn!("SSM parameter value is not an object");
     }
 
-    Ok(results)
+    Ok((results, errors))
+}
+
+/// Key of the named sets of entries of a mapping.
+const PROFILES_KEY: &str = "$profiles";
+
+/// Merges the entries of the selected profile into the shared entries of
+/// the mapping, failing when the mapping defines profiles and none or an
+/// unknown one is selected.
+fn select_profile(
+    mut mapping: Map<String, Value>,
+    profile: Option<&str>,
+) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let Some(profiles) = mapping.remove(PROFILES_KEY) else {
+        if let Some(profile) = profile {
+            warn!(
+                "Ignoring profile {} as the mapping defines no profiles",
+                profile
+            );
+        }
+        return Ok(mapping);
+    };
+    let Value::Object(mut profiles) = profiles else {
+        return Err(format!("{} is not an object", PROFILES_KEY).into());
+    };
+    let names: Vec<&String> = profiles.keys().collect();
+    let Some(profile) = profile else {
+        return Err(format!(
+            "The mapping defines the profiles {:?}, select one with --set or SECRETS_PROFILE",
+            names
+        )
+        .into());
+    };
+    match profiles.remove(profile) {
+        Some(Value::Object(entries)) => {
+            info!("Using the {} profile of the mapping", profile);
+            mapping.extend(entries);
+            Ok(mapping)
+        }
+        Some(_) => Err(format!("Profile {} is not an object", profile).into()),
+        None => Err(format!(
+            "Unknown profile {}, the mapping defines {:?}",
+            profile,
+            profiles.keys().collect::<Vec<_>>()
+        )
+        .into()),
+    }
+}
+
+/// Interprets the value of a mapping entry. Numbers and booleans, such as
+/// ports and feature flags, are passed through as their string
+/// representation, as are the literal values given as `{"value": ...}` for
+/// non-secret configuration. Templates are given as `{"template": ...}`.
+/// Secrets needing options are given as
+/// `{"arn": ..., "key": ..., "region": ..., "role": ..., "transform": ...,
+/// "url": ..., "validate": ..., "pattern": ..., "multiline": ..., "trim": ...,
+/// "missing": ..., "default": ..., "min_length": ..., "refresh": ...}`.
+fn mapping_entry(value: Value) -> Result<(Provider, String, EntryOptions), String> {
+    let literal = |literal| Ok((Provider::Literal, literal, EntryOptions::default()));
+    match value {
+        Value::String(reference) => Ok(string_reference(reference)),
+        Value::Number(number) => literal(number.to_string()),
+        Value::Bool(flag) => literal(flag.to_string()),
+        Value::Array(items) if is_reference_list(&items) => {
+            let merge: Vec<String> = items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect();
+            Ok((
+                Provider::MergedSecrets,
+                merge.join(", "),
+                EntryOptions {
+                    merge,
+                    ..Default::default()
+                },
+            ))
+        }
+        Value::Object(entry) if entry.contains_key("template") => {
+            match (entry.len(), &entry["template"]) {
+                (1, Value::String(template)) => Ok((
+                    Provider::Template,
+                    template.clone(),
+                    EntryOptions::default(),
+                )),
+                (1, _) => Err("template must be a string".to_string()),
+                _ => Err("templates take no options".to_string()),
+            }
+        }
+        Value::Object(entry) if entry.contains_key("value") => {
+            if entry.len() > 1 {
+                return Err("literal values take no options".to_string());
+            }
+            match &entry["value"] {
+                Value::String(value) => literal(value.clone()),
+                Value::Number(number) => literal(number.to_string()),
+                Value::Bool(flag) => literal(flag.to_string()),
+                _ => Err("unexpected literal value type".to_string()),
+            }
+        }
+        Value::Object(entry) => {
+            let mut arn = None;
+            let mut options = EntryOptions::default();
+            for (name, value) in entry {
+                if name == "min_length" {
+                    let length = value.as_u64().ok_or("option min_length must be a number")?;
+                    options.min_length = Some(length as usize);
+                    continue;
+                }
+                let Value::String(value) = value else {
+                    return Err(format!("option {} must be a string", name));
+                };
+                match name.as_str() {
+                    "arn" => arn = Some(value),
+                    "key" => options.json_key = Some(value),
+                    "region" => options.region = Some(value),
+                    "role" => options.role = Some(value),
+                    "version_id" => options.version_id = Some(value),
+                    "version_stage" => options.version_stage = Some(value),
+                    "transform" => options.transform = Some(value.parse()?),
+                    "url" => options.url = Some(value.parse()?),
+                    "validate" => options.validate = Some(value.parse()?),
+                    "multiline" => options.multiline = Some(value.parse()?),
+                    "trim" => options.trim = Some(value.parse()?),
+                    "missing" => options.missing = Some(value.parse()?),
+                    "default" => options.default = Some(value.into()),
+                    "refresh" => {
+                        let refresh = crate::cli::parse_duration(&value)
+                            .map_err(|e| format!("invalid refresh: {}", e))?;
+                        options.refresh = Some(refresh);
+                    }
+                    "pattern" => {
+                        Regex::new(&value).map_err(|e| format!("invalid pattern: {}", e))?;
+                        options.pattern = Some(value);
+                    }
+                    other => return Err(format!("unknown option {}", other)),
+                }
+            }
+            let arn = arn.ok_or("missing arn")?;
+            if options.version_id.is_some() && options.version_stage.is_some() {
+                return Err("version_id and version_stage can't be combined".to_string());
+            }
+            Ok((Provider::SecretsManager, arn, options))
+        }
+        _ => Err("unexpected value type".to_string()),
+    }
+}
+
+/// The provider and options of a reference given as a plain string. A `#key`
+/// suffix on a Secrets Manager reference, as in `arn:...:secret:mydb#password`,
+/// extracts that key of a JSON secret, since `#` can't appear in secret names.
+/// Before the key, `@STAGE` or `?versionId=ID` selects a version of the
+/// secret, as in `arn:...:secret:mydb@AWSPENDING#password`. Everything after
+/// the first `|`, which can't appear in secret names either, is the default
+/// value used when the secret doesn't exist or can't be accessed, as in
+/// `arn:...:secret:flag|off`.
+pub fn string_reference(reference: String) -> (Provider, String, EntryOptions) {
+    let provider = string_reference_provider(&reference);
+    if provider != Provider::SecretsManager {
+        return (provider, reference, EntryOptions::default());
+    }
+    let mut options = EntryOptions::default();
+    let mut secret_id = reference.as_str();
+    if let Some((id, default)) = secret_id.split_once('|') {
+        options.default = Some(default.into());
+        secret_id = id;
+    }
+    if let Some((id, key)) = secret_id.split_once('#') {
+        if !key.is_empty() {
+            options.json_key = Some(key.to_string());
+            secret_id = id;
+        }
+    }
+    if let Some((id, version_id)) = secret_id.split_once("?versionId=") {
+        options.version_id = Some(version_id.to_string());
+        secret_id = id;
+    } else if let Some((id, stage)) = secret_id.rsplit_once('@') {
+        if is_stage_label(stage) {
+            options.version_stage = Some(stage.to_string());
+            secret_id = id;
+        }
+    }
+    (provider, secret_id.to_string(), options)
+}
+
+/// Whether the text after an `@` reads as a staging label rather than as part
+/// of a secret name, which may contain `@` too: the AWS labels, or custom
+/// labels in uppercase such as `CANARY`.
+fn is_stage_label(label: &str) -> bool {
+    !label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// The provider of a reference given as a plain string: a Consul or etcd key
+/// for `consul://` and `etcd://` references, Secrets Manager otherwise.
+fn string_reference_provider(reference: &str) -> Provider {
+    match parse_kv_reference(reference) {
+        Some((store, _)) => Provider::KeyValue(store),
+        None => Provider::SecretsManager,
+    }
+}
+
+/// Whether an array from the mapping is a non-empty list of secret references.
+fn is_reference_list(items: &[Value]) -> bool {
+    !items.is_empty()
+        && items
+            .iter()
+            .all(|item| item.as_str().is_some_and(|arn| !arn.is_empty()))
 }
@@ -0,0 +1,249 @@
+use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_secretsmanager::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_secretsmanager::operation::create_secret::CreateSecretError;
+use aws_sdk_secretsmanager::operation::describe_secret::DescribeSecretError;
+use aws_sdk_secretsmanager::operation::get_random_password::GetRandomPasswordError;
+use aws_sdk_secretsmanager::operation::get_secret_value::GetSecretValueError;
+use aws_sdk_secretsmanager::operation::put_secret_value::PutSecretValueError;
+use aws_sdk_secretsmanager::operation::RequestId;
+use aws_sdk_ssm::operation::get_parameter::GetParameterError;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt;
+
+/// How fatal errors are reported on stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorFormat {
+    /// A human-readable message.
+    #[default]
+    Text,
+    /// A single JSON object, for orchestrators surfacing the failure reason.
+    Json,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            other => Err(format!("unknown error format {}", other)),
+        }
+    }
+}
+
+/// A failure to resolve one reference, naming the variable that declared it.
+/// The reference is empty when the variable doesn't hold a valid one.
+#[derive(Debug)]
+pub struct ResolveError {
+    pub variable: String,
+    pub reference: String,
+    pub cause: Box<dyn Error>,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to resolve {}", self.variable)?;
+        if !self.reference.is_empty() {
+            write!(f, " ({})", self.reference)?;
+        }
+        write!(f, ": {}", error_message(self.cause.as_ref()))
+    }
+}
+
+impl Error for ResolveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.cause.as_ref())
+    }
+}
+
+/// The failures of every reference that couldn't be resolved, reported
+/// together so they can all be fixed at once.
+#[derive(Debug)]
+pub struct ResolveErrors(pub Vec<ResolveError>);
+
+impl fmt::Display for ResolveErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to resolve {} references:", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ResolveErrors {}
+
+/// Resolution was interrupted by a termination signal.
+#[derive(Debug)]
+pub struct Cancelled {
+    pub signal: &'static str,
+}
+
+impl Cancelled {
+    /// The conventional shell exit code for a process ended by the signal.
+    pub fn exit_code(&self) -> i32 {
+        match self.signal {
+            "SIGTERM" => 143,
+            _ => 130,
+        }
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted by {}", self.signal)
+    }
+}
+
+impl Error for Cancelled {}
+
+/// The exit code of the tool for a fatal error.
+pub fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    error
+        .downcast_ref::<Cancelled>()
+        .map_or(1, Cancelled::exit_code)
+}
+
+/// Describes an error for operators: AWS service errors are shown with their
+/// error code and message, instead of the bare "service error" of the SDK,
+/// and other errors with their whole chain of causes.
+pub fn error_message(error: &(dyn Error + 'static)) -> String {
+    if let Some(message) = service_error_message(error) {
+        return message;
+    }
+
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            message.push_str(": ");
+            message.push_str(&cause_message);
+        }
+        source = cause.source();
+    }
+    message
+}
+
+/// Describes an error as a JSON object with its class, the variable and
+/// reference that failed to resolve, and the AWS error code and request id
+/// when the failure comes from an AWS call.
+pub fn error_json(error: &(dyn Error + 'static)) -> Value {
+    if let Some(cancelled) = error.downcast_ref::<Cancelled>() {
+        return json!({
+            "class": "cancelled",
+            "signal": cancelled.signal,
+            "message": cancelled.to_string(),
+        });
+    }
+    if let Some(error) = error.downcast_ref::<ResolveError>() {
+        return resolve_error_json(error);
+    }
+    if let Some(errors) = error.downcast_ref::<ResolveErrors>() {
+        return json!({
+            "class": "resolution",
+            "message": errors.to_string(),
+            "errors": errors.0.iter().map(resolve_error_json).collect::<Vec<_>>(),
+        });
+    }
+    json!({
+        "class": "error",
+        "message": error_message(error),
+    })
+}
+
+fn resolve_error_json(error: &ResolveError) -> Value {
+    let (code, request_id) = service_error_metadata(error.cause.as_ref()).unwrap_or_default();
+    json!({
+        "class": "resolution",
+        "key": error.variable,
+        "reference": error.reference,
+        "code": code,
+        "request_id": request_id,
+        "message": error_message(error.cause.as_ref()),
+    })
+}
+
+/// The AWS error code and request id of a failed AWS call.
+fn service_error_metadata(
+    error: &(dyn Error + 'static),
+) -> Option<(Option<String>, Option<String>)> {
+    fn metadata<E: ProvideErrorMetadata + Error + 'static>(
+        error: &(dyn Error + 'static),
+    ) -> Option<(Option<String>, Option<String>)> {
+        let error = error.downcast_ref::<SdkError<E>>()?;
+        Some((
+            error.code().map(str::to_string),
+            error.request_id().map(str::to_string),
+        ))
+    }
+
+    metadata::<GetSecretValueError>(error)
+        .or_else(|| metadata::<DescribeSecretError>(error))
+        .or_else(|| metadata::<GetRandomPasswordError>(error))
+        .or_else(|| metadata::<CreateSecretError>(error))
+        .or_else(|| metadata::<PutSecretValueError>(error))
+        .or_else(|| metadata::<GetParameterError>(error))
+}
+
+/// AWS error codes of the secrets and parameters that don't exist or can't
+/// be accessed with the credentials.
+const MISSING_OR_DENIED_CODES: &[&str] = &[
+    "ResourceNotFoundException",
+    "ParameterNotFound",
+    "ParameterVersionNotFound",
+    "AccessDeniedException",
+];
+
+/// Whether the error reports a secret or parameter that doesn't exist or
+/// can't be accessed, as opposed to a transient or configuration failure.
+pub fn is_missing_or_denied(error: &(dyn Error + 'static)) -> bool {
+    service_error_metadata(error)
+        .and_then(|(code, _)| code)
+        .is_some_and(|code| MISSING_OR_DENIED_CODES.contains(&code.as_str()))
+}
+
+/// AWS error codes of the calls made with credentials that expired or are
+/// no longer valid.
+const CREDENTIALS_CODES: &[&str] = &[
+    "ExpiredTokenException",
+    "UnrecognizedClientException",
+    "InvalidClientTokenId",
+];
+
+/// Whether the error, or any of the resolution failures it gathers, comes
+/// from credentials that couldn't be acquired or refreshed, or that AWS
+/// rejected as expired.
+pub fn is_credentials_error(error: &(dyn Error + 'static)) -> bool {
+    if let Some(errors) = error.downcast_ref::<ResolveErrors>() {
+        return errors
+            .0
+            .iter()
+            .any(|error| is_credentials_error(error.cause.as_ref()));
+    }
+    if let Some(error) = error.downcast_ref::<ResolveError>() {
+        return is_credentials_error(error.cause.as_ref());
+    }
+    let rejected = service_error_metadata(error)
+        .and_then(|(code, _)| code)
+        .is_some_and(|code| CREDENTIALS_CODES.contains(&code.as_str()));
+    rejected
+        || std::iter::successors(Some(error), |&error| error.source())
+            .any(|cause| cause.is::<CredentialsError>())
+}
+
+fn service_error_message(error: &(dyn Error + 'static)) -> Option<String> {
+    fn message<E: Error + 'static>(error: &(dyn Error + 'static)) -> Option<String> {
+        let error = error.downcast_ref::<SdkError<E>>()?;
+        Some(error.as_service_error()?.to_string())
+    }
+
+    message::<GetSecretValueError>(error)
+        .or_else(|| message::<DescribeSecretError>(error))
+        .or_else(|| message::<GetRandomPasswordError>(error))
+        .or_else(|| message::<CreateSecretError>(error))
+        .or_else(|| message::<PutSecretValueError>(error))
+        .or_else(|| message::<GetParameterError>(error))
+}
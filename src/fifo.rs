@@ -0,0 +1,130 @@
+//! Delivery of individual secrets through named pipes, which keeps them out
+//! of both the environment and persistent storage.
+
+use crate::environment_processor::ResolvedSecret;
+#[cfg(unix)]
+use crate::files::{create_private_dir, memory_backed_dir};
+#[cfg(unix)]
+use crate::logging::{info, warn};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+#[cfg(unix)]
+use std::time::Duration;
+
+/// How often a pipe is opened again while the command hasn't opened it.
+#[cfg(unix)]
+const OPEN_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Named pipes streaming secrets to the command, removed when dropped, once
+/// the threads writing to them stopped.
+pub struct Fifos {
+    dir: PathBuf,
+    /// Variables pointing the command to the pipes, named after the keys
+    /// with a `_FIFO` suffix.
+    pub variables: Vec<(String, String)>,
+    /// Tells the writers to give up on the pipes the command never opened.
+    stopped: Arc<AtomicBool>,
+    writers: Vec<JoinHandle<()>>,
+}
+
+impl Fifos {
+    /// Creates a named pipe per key in a directory private to the current
+    /// user and streams each value to the first reader of its pipe.
+    #[cfg(unix)]
+    pub fn create(keys: &[String], secrets: &[ResolvedSecret]) -> Result<Self, Box<dyn Error>> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let parent = memory_backed_dir().unwrap_or_else(std::env::temp_dir);
+        let mut fifos = Fifos {
+            dir: create_private_dir(&parent, "resolve-aws-secrets-fifo")?,
+            variables: Vec::new(),
+            stopped: Arc::new(AtomicBool::new(false)),
+            writers: Vec::new(),
+        };
+
+        for key in keys {
+            let secret = secrets
+                .iter()
+                .find(|secret| &secret.key == key)
+                .ok_or_else(|| format!("No resolved secret named {} to deliver", key))?;
+            let path = fifos.dir.join(key);
+            let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+            // SAFETY: c_path is a valid NUL-terminated string.
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                let e = std::io::Error::last_os_error();
+                return Err(format!("Failed to create {}: {}", path.display(), e).into());
+            }
+            info!("Delivering {} through {}", key, path.display());
+            let writer = stream(
+                path.clone(),
+                secret.value.expose().to_string(),
+                fifos.stopped.clone(),
+            );
+            fifos.writers.push(writer);
+            let variable = (format!("{}_FIFO", key), path.display().to_string());
+            fifos.variables.push(variable);
+        }
+        Ok(fifos)
+    }
+
+    #[cfg(not(unix))]
+    pub fn create(_keys: &[String], _secrets: &[ResolvedSecret]) -> Result<Self, Box<dyn Error>> {
+        Err("Named pipe delivery is only supported on Unix".into())
+    }
+}
+
+impl Drop for Fifos {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for writer in self.writers.drain(..) {
+            let _ = writer.join();
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Writes the value to the pipe from another thread once the command opens
+/// it for reading. The pipe is opened without blocking, and again until the
+/// command opens it, so that the thread stops when the pipes are dropped.
+#[cfg(unix)]
+fn stream(path: PathBuf, value: String, stopped: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        let mut pipe = loop {
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            let opened = std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&path);
+            match opened {
+                Ok(pipe) => break pipe,
+                // Opening a pipe without a reader fails with ENXIO.
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                    std::thread::sleep(OPEN_RETRY_INTERVAL)
+                }
+                Err(e) => {
+                    warn!("Failed to open {}: {}", path.display(), e);
+                    return;
+                }
+            }
+        };
+        // SAFETY: the descriptor is open for the lifetime of pipe.
+        unsafe {
+            let flags = libc::fcntl(pipe.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(pipe.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+        if let Err(e) = pipe.write_all(value.as_bytes()) {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                warn!("Failed to write to {}: {}", path.display(), e);
+            }
+        }
+    })
+}
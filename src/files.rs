@@ -0,0 +1,310 @@
+use crate::environment_processor::ResolvedSecret;
+use crate::logging::{info, warn};
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Private directory holding the temporary files of the run, created on
+/// first use.
+static TEMPORARY_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Files of the run written outside of the temporary directory, removed
+/// along with it.
+static TEMPORARY_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Writes a file readable only by the current user (mode 0600 on Unix),
+/// replacing any existing content. Symbolic links aren't followed, so a link
+/// planted at the path can't redirect the contents.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    write_private(options, path, contents)
+}
+
+/// Writes a new file readable only by the current user, failing when the
+/// path already exists so that no existing file is overwritten.
+pub fn create_private_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    write_private(options, path, contents)
+}
+
+fn write_private(
+    mut options: std::fs::OpenOptions,
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    // The mode only applies to newly created files, so tighten existing ones too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(contents)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Creates a new directory readable only by the current user (mode 0700 on
+/// Unix) in the parent directory, with a random name starting with the
+/// prefix. Creating it fails rather than reuse an existing directory or
+/// link, so other users can't plant files in it.
+pub fn create_private_dir(parent: &Path, prefix: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    let mut attempts = 0;
+    loop {
+        let suffix = RandomState::new().build_hasher().finish();
+        let dir = parent.join(format!("{}-{:016x}", prefix, suffix));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 8 => {
+                attempts += 1
+            }
+            Err(e) => return Err(format!("Failed to create {}: {}", dir.display(), e).into()),
+        }
+    }
+}
+
+/// Path of a temporary file of the run named after the secret, in a
+/// private directory, memory-backed when one is available. The directory is
+/// created on first use and removed with [`remove_temporary_files`].
+pub fn temporary_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let mut temporary_dir = TEMPORARY_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = match &*temporary_dir {
+        Some(dir) => dir.clone(),
+        None => {
+            let parent = memory_backed_dir().unwrap_or_else(|| {
+                warn!(
+                    "No memory-backed directory is available, {} and the other temporary files are written on disk",
+                    name
+                );
+                std::env::temp_dir()
+            });
+            let prefix = format!("resolve-aws-secrets-{}", std::process::id());
+            temporary_dir
+                .insert(create_private_dir(&parent, &prefix)?)
+                .clone()
+        }
+    };
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    Ok(dir.join(name.trim_start_matches('.')))
+}
+
+/// Registers a file written outside of the temporary directory to be
+/// removed with the temporary files.
+pub fn register_temporary_file(path: &Path) {
+    let mut files = TEMPORARY_FILES.lock().unwrap_or_else(|e| e.into_inner());
+    if !files.iter().any(|file| file == path) {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// Whether the file was registered with [`register_temporary_file`], and
+/// so was written by this run.
+pub fn is_temporary_file(path: &Path) -> bool {
+    TEMPORARY_FILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|file| file == path)
+}
+
+/// Whether temporary files may have been written, which must then be
+/// removed once the command exits.
+pub fn has_temporary_files() -> bool {
+    TEMPORARY_DIR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some()
+        || !TEMPORARY_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_empty()
+}
+
+/// Removes the temporary files of the run, on exit or when the resolution
+/// is cancelled or fails.
+pub fn remove_temporary_files() {
+    let dir = TEMPORARY_DIR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    if let Some(dir) = dir {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+    }
+    let files = std::mem::take(&mut *TEMPORARY_FILES.lock().unwrap_or_else(|e| e.into_inner()));
+    for file in files {
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!("Failed to remove {}: {}", file.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes a file holding secrets to the path, applying the policy, creating
+/// its directory if needed and making it private.
+pub fn write_secret_to(
+    path: &Path,
+    contents: &[u8],
+    policy: PersistentFiles,
+) -> Result<(), Box<dyn Error>> {
+    check_storage(path, policy)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    write_private_file(path, contents)
+}
+
+/// Writes the values of the secrets targeting files, declared with
+/// `SECRET_FILE_` or delivered with `--binary file`, to their private files,
+/// creating their directories, and returns the variables set to the paths
+/// of the files.
+pub fn write_target_files(
+    secrets: &[ResolvedSecret],
+    policy: PersistentFiles,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut variables = Vec::new();
+    for secret in secrets {
+        let Some(path) = &secret.file else {
+            continue;
+        };
+        let contents = match secret.binary {
+            true => aws_smithy_types::base64::decode(secret.value.expose())?,
+            false => secret.value.expose().as_bytes().to_vec(),
+        };
+        write_secret_to(path, &contents, policy)?;
+        info!("Wrote {} to {}", secret.key, path.display());
+        variables.push((secret.key.clone(), path.to_string_lossy().into_owned()));
+    }
+    Ok(variables)
+}
+
+/// What to do when a secret file would be written to persistent storage,
+/// where it may outlive the process or end up in disk snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PersistentFiles {
+    /// Prefer a memory-backed location, and warn when none is available.
+    #[default]
+    Warn,
+    /// Prefer a memory-backed location, and fail when none is available.
+    Refuse,
+    /// Write the files where requested without checking the storage.
+    Allow,
+}
+
+impl std::str::FromStr for PersistentFiles {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "warn" => Ok(PersistentFiles::Warn),
+            "refuse" => Ok(PersistentFiles::Refuse),
+            "allow" => Ok(PersistentFiles::Allow),
+            other => Err(format!("unknown persistent files policy {}", other)),
+        }
+    }
+}
+
+/// File systems keeping their contents in memory only.
+const MEMORY_FILE_SYSTEMS: &[&str] = &["tmpfs", "ramfs"];
+
+/// Returns whether the file system holding the path keeps its contents in
+/// memory only, or None when it can't be determined.
+pub fn is_memory_backed(path: &Path) -> Option<bool> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    let path = std::fs::canonicalize(path).ok()?;
+    let file_system = mount_file_system(&mounts, &path)?;
+    Some(MEMORY_FILE_SYSTEMS.contains(&file_system))
+}
+
+/// Finds the type of the file system holding an absolute path in the
+/// mount table, as listed in `/proc/self/mounts`.
+pub fn mount_file_system<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let file_system = fields.next()?;
+            path.starts_with(&mount_point)
+                .then_some((mount_point.len(), file_system))
+        })
+        .max_by_key(|(length, _)| *length)
+        .map(|(_, file_system)| file_system)
+}
+
+/// Returns the first memory-backed directory among the per-user runtime
+/// directory and `/dev/shm`.
+pub fn memory_backed_dir() -> Option<PathBuf> {
+    let candidates = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(std::iter::once(PathBuf::from("/dev/shm")));
+    candidates
+        .into_iter()
+        .find(|dir| dir.is_dir() && is_memory_backed(dir) == Some(true))
+}
+
+/// Applies the policy to a secret file about to be written to the path. The
+/// storage of directories not created yet is the one of their nearest
+/// existing ancestor, where they will be created. When the storage can't be
+/// determined, the file is refused as if it were persistent.
+pub fn check_storage(path: &Path, policy: PersistentFiles) -> Result<(), Box<dyn Error>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if policy == PersistentFiles::Allow {
+        return Ok(());
+    }
+    let existing = dir.ancestors().find(|dir| dir.exists()).unwrap_or(dir);
+    let message = match is_memory_backed(existing) {
+        Some(true) => return Ok(()),
+        Some(false) => format!("{} is on persistent storage", path.display()),
+        None if policy == PersistentFiles::Warn => return Ok(()),
+        None => format!(
+            "Can't tell whether {} is on persistent storage",
+            path.display()
+        ),
+    };
+    if policy == PersistentFiles::Refuse {
+        return Err(format!(
+            "{}, use --persistent-files allow to write it anyway",
+            message
+        )
+        .into());
+    }
+    warn!("{}, secrets written there may outlive the process", message);
+    Ok(())
+}
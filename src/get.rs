@@ -0,0 +1,71 @@
+use crate::cli::GetArgs;
+use crate::environment_processor::{
+    resolve_reference, string_reference, EntryOptions, Provider, Reference, Source,
+};
+use crate::errors::ResolveError;
+use crate::secret_manager::SecretsManagerClientTrait;
+use crate::ssm_manager::SsmClientTrait;
+use std::error::Error;
+
+/// Builds the reference given on the command line, as it would be declared
+/// with `SECRET_` or `SSM_PARAM_`.
+pub fn reference(get_args: &GetArgs) -> Reference {
+    let (variable, (provider, reference, mut options)) = if get_args.ssm {
+        let reference = get_args.reference.clone();
+        let options = EntryOptions::default();
+        (
+            "SSM_PARAM_VALUE",
+            (Provider::SsmParameter, reference, options),
+        )
+    } else {
+        ("SECRET_VALUE", string_reference(get_args.reference.clone()))
+    };
+    if get_args.json_key.is_some() {
+        options.json_key = get_args.json_key.clone();
+    }
+    Reference {
+        key: "VALUE".to_string(),
+        variable: variable.to_string(),
+        source: Source::Environment,
+        provider,
+        reference,
+        options,
+    }
+}
+
+/// Resolves the single reference of the `get` subcommand, with the same
+/// options and policies as the references of the environment.
+pub async fn get_value<S, T>(
+    secretsmanager_client: &S,
+    ssm_client: &T,
+    get_args: &GetArgs,
+) -> Result<String, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SsmClientTrait + ?Sized,
+{
+    let reference = reference(get_args);
+    if reference.options.json_key.is_some() && reference.provider != Provider::SecretsManager {
+        return Err("--key is only supported for Secrets Manager secrets".into());
+    }
+    let secret = resolve_reference(
+        secretsmanager_client,
+        ssm_client,
+        &reference,
+        &get_args.resolve,
+    )
+    .await
+    .map_err(|cause| ResolveError {
+        variable: reference.variable.clone(),
+        reference: reference.reference.clone(),
+        cause,
+    })?;
+    if secret.binary {
+        return Err(format!(
+            "{} is a binary secret, use --binary base64 to print it",
+            reference.reference
+        )
+        .into());
+    }
+    Ok(secret.value.into_inner())
+}
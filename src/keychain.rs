@@ -0,0 +1,92 @@
+//! Caching of resolved values in the OS credential store (macOS Keychain,
+//! Secret Service on Linux, Windows Credential Manager), so repeated local
+//! runs on developer machines don't need to call AWS, or assume an MFA
+//! protected role, every time.
+
+use crate::environment_processor::Reference;
+use crate::logging::{info, warn};
+use crate::secret_manager::SecretValue;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Service name the values are stored under in the credential store.
+const SERVICE: &str = "resolve-aws-secrets";
+
+/// The credential store entry caching a reference. Values are cached per
+/// declaring variable, as the entry options shape the value.
+fn entry(reference: &Reference) -> Option<keyring::Entry> {
+    let user = format!("{}={}", reference.variable, reference.reference);
+    match keyring::Entry::new(SERVICE, &user) {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            warn!(
+                "Cannot use the credential store for {}: {}",
+                reference.variable, e
+            );
+            None
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Returns the cached value of the reference, unless it expired. Failures to
+/// read the credential store are logged and treated as cache misses.
+pub async fn lookup(reference: &Reference) -> Option<SecretValue> {
+    let reference = reference.clone();
+    tokio::task::spawn_blocking(move || {
+        let cached = match entry(&reference)?.get_password() {
+            Ok(cached) => cached,
+            Err(keyring::Error::NoEntry) => return None,
+            Err(e) => {
+                warn!(
+                    "Failed to read {} from the credential store: {}",
+                    reference.variable, e
+                );
+                return None;
+            }
+        };
+        let cached: Value = serde_json::from_str(&cached).ok()?;
+        if cached["expires"].as_u64()? <= now() {
+            return None;
+        }
+        info!("Using the cached value of {}", reference.variable);
+        Some(SecretValue {
+            value: cached["value"].as_str()?.into(),
+            version_id: cached["version_id"].as_str().map(str::to_string),
+            stored_arn: None,
+            binary: cached["binary"].as_bool().unwrap_or(false),
+        })
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Caches the value of the reference for the given time.
+pub async fn store(reference: &Reference, secret: &SecretValue, ttl: Duration) {
+    let reference = reference.clone();
+    let cached = json!({
+        "value": secret.value.expose(),
+        "version_id": secret.version_id,
+        "binary": secret.binary,
+        "expires": now() + ttl.as_secs(),
+    })
+    .to_string();
+    let _ = tokio::task::spawn_blocking(move || {
+        let Some(entry) = entry(&reference) else {
+            return;
+        };
+        if let Err(e) = entry.set_password(&cached) {
+            warn!(
+                "Failed to cache {} in the credential store: {}",
+                reference.variable, e
+            );
+        }
+    })
+    .await;
+}
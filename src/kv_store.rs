@@ -0,0 +1,143 @@
+//! Key-value stores outside AWS, for hybrid environments keeping some of
+//! their configuration in Consul or etcd. Both are reached over the plain
+//! HTTP API of a local agent or gateway, configured with the same variables
+//! as their command line tools.
+
+use crate::logging::info;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt;
+
+const CONSUL_DEFAULT_ADDRESS: &str = "127.0.0.1:8500";
+const ETCD_DEFAULT_ENDPOINT: &str = "127.0.0.1:2379";
+
+/// A key-value store a reference can point to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KvStore {
+    /// Consul KV, referenced as `consul://path`.
+    Consul,
+    /// etcd, referenced as `etcd://key`.
+    Etcd,
+}
+
+impl fmt::Display for KvStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvStore::Consul => write!(f, "consul"),
+            KvStore::Etcd => write!(f, "etcd"),
+        }
+    }
+}
+
+/// Splits a `consul://path` or `etcd://key` reference into its store and key.
+pub fn parse_kv_reference(reference: &str) -> Option<(KvStore, &str)> {
+    if let Some(path) = reference.strip_prefix("consul://") {
+        return Some((KvStore::Consul, path));
+    }
+    if let Some(key) = reference.strip_prefix("etcd://") {
+        return Some((KvStore::Etcd, key));
+    }
+    None
+}
+
+/// Retrieves the value of a key, failing when it doesn't exist.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub async fn get_kv_value(store: KvStore, key: &str) -> Result<String, Box<dyn Error>> {
+    info!("Retrieving {} key: {}", store, key);
+    let value = match store {
+        KvStore::Consul => get_consul_value(key).await?,
+        KvStore::Etcd => get_etcd_value(key).await?,
+    };
+    value.ok_or_else(|| format!("Key {} was not found in {}", key, store).into())
+}
+
+/// Reads a key with the Consul KV API of the agent at `CONSUL_HTTP_ADDR`,
+/// authenticating with `CONSUL_HTTP_TOKEN` if it's set.
+async fn get_consul_value(path: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let address = endpoint(
+        std::env::var("CONSUL_HTTP_ADDR").ok(),
+        CONSUL_DEFAULT_ADDRESS,
+    )?;
+    let mut request = Request::get(format!(
+        "{}/v1/kv/{}?raw",
+        address,
+        path.trim_start_matches('/')
+    ));
+    if let Ok(token) = std::env::var("CONSUL_HTTP_TOKEN") {
+        request = request.header("X-Consul-Token", token);
+    }
+
+    let Some(body) = send(request.body(Body::empty())?).await? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        String::from_utf8(body).map_err(|_| "The Consul value is not valid UTF-8")?,
+    ))
+}
+
+/// Reads a key with the JSON gateway of the etcd v3 API at the first of the
+/// `ETCDCTL_ENDPOINTS`.
+async fn get_etcd_value(key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let endpoints = std::env::var("ETCDCTL_ENDPOINTS").ok();
+    let first = endpoints
+        .as_deref()
+        .and_then(|endpoints| endpoints.split(',').next())
+        .map(str::to_string);
+    let address = endpoint(first, ETCD_DEFAULT_ENDPOINT)?;
+    let query = json!({ "key": aws_smithy_types::base64::encode(key) });
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/v3/kv/range", address))
+        .header("Content-Type", "application/json")
+        .body(Body::from(query.to_string()))?;
+
+    let Some(body) = send(request).await? else {
+        return Ok(None);
+    };
+    let response: Value = serde_json::from_slice(&body)?;
+    let Some(encoded) = response["kvs"][0]["value"].as_str() else {
+        return Ok(None);
+    };
+    let decoded = aws_smithy_types::base64::decode(encoded)
+        .map_err(|e| format!("Invalid etcd response: {}", e))?;
+    Ok(Some(
+        String::from_utf8(decoded).map_err(|_| "The etcd value is not valid UTF-8")?,
+    ))
+}
+
+/// Normalizes an address given with or without its scheme. Only plain HTTP
+/// is supported, as both stores are expected to be reached through a local
+/// agent or gateway.
+fn endpoint(address: Option<String>, default: &str) -> Result<String, Box<dyn Error>> {
+    let address = address
+        .filter(|address| !address.is_empty())
+        .unwrap_or_else(|| default.to_string());
+    let address = address.trim_end_matches('/');
+    if address.starts_with("https://") {
+        return Err(format!("HTTPS endpoints are not supported: {}", address).into());
+    }
+    if address.starts_with("http://") {
+        Ok(address.to_string())
+    } else {
+        Ok(format!("http://{}", address))
+    }
+}
+
+/// Sends the request, returning the body of successful responses and None
+/// for missing keys.
+pub async fn send(request: Request<Body>) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let response = Client::new().request(request).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    match status {
+        StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => Ok(Some(body.to_vec())),
+        status => Err(format!(
+            "Request failed with status {}: {}",
+            status,
+            String::from_utf8_lossy(&body).trim()
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,65 @@
+use crate::logging::warn;
+use std::error::Error;
+use std::path::Path;
+
+/// Returns true when the tool was started by the Lambda runtime through the
+/// `AWS_LAMBDA_EXEC_WRAPPER` environment variable, in which case the arguments
+/// are the original runtime entrypoint and its arguments.
+pub fn is_exec_wrapper(program: &str) -> bool {
+    if std::env::var_os("AWS_LAMBDA_RUNTIME_API").is_none() {
+        return false;
+    }
+
+    match std::env::var("AWS_LAMBDA_EXEC_WRAPPER") {
+        Ok(wrapper) => same_program(&wrapper, program),
+        Err(_) => false,
+    }
+}
+
+fn same_program(wrapper: &str, program: &str) -> bool {
+    if wrapper == program {
+        return true;
+    }
+    match (
+        std::fs::canonicalize(wrapper),
+        std::fs::canonicalize(program),
+    ) {
+        (Ok(wrapper), Ok(program)) => wrapper == program,
+        _ => Path::new(wrapper).file_name() == Path::new(program).file_name(),
+    }
+}
+
+/// Returns true when the function is being initialized for a SnapStart
+/// snapshot. The values resolved at this point are captured in the snapshot
+/// and served by every environment restored from it, while the wrapper has
+/// already replaced itself with the runtime by the time of the restore.
+///
+/// Re-resolving the secrets on restore isn't supported: the Runtime API only
+/// notifies the runtime process itself of the restore, and the environment
+/// it was started with can't be changed from outside of it, so the function
+/// must fetch the credentials that need to be fresh in its restore hook.
+pub fn is_snapstart_init() -> bool {
+    std::env::var("AWS_LAMBDA_INITIALIZATION_TYPE").as_deref() == Ok("snap-start")
+}
+
+/// Refuses to resolve the secrets for a SnapStart snapshot, where they would
+/// be served by restored environments long after, unless
+/// `SECRETS_ALLOW_SNAPSTART` is `true`, in which case it only warns.
+pub fn check_snapstart() -> Result<(), Box<dyn Error>> {
+    if !is_snapstart_init() {
+        return Ok(());
+    }
+    if std::env::var("SECRETS_ALLOW_SNAPSTART").as_deref() == Ok("true") {
+        warn!(
+            "SnapStart is enabled: the resolved secrets are captured in the snapshot and \
+             not refreshed on restore"
+        );
+        return Ok(());
+    }
+    Err(
+        "SnapStart is enabled: the resolved secrets would be captured in the snapshot and \
+         not refreshed on restore, resolve them in the function instead, or set \
+         SECRETS_ALLOW_SNAPSTART=true to accept it"
+            .into(),
+    )
+}
@@ -0,0 +1,57 @@
+//! Resolves the AWS Secrets Manager and SSM Parameter Store references of
+//! the environment into their values. The `resolve-aws-secrets` program is a
+//! thin command line interface over this crate, and [`Resolver`] embeds the
+//! same resolution in other programs. The other modules implement the
+//! commands of the program and aren't a stable interface.
+
+pub mod api_calls;
+pub mod aws_clients;
+pub mod bench;
+pub mod cache;
+pub mod cgroup;
+pub mod chaos;
+pub mod cli;
+pub mod commands;
+pub mod copy;
+pub mod db_credentials;
+pub mod diagnostics;
+#[cfg(feature = "disk-cache")]
+pub mod disk_cache;
+pub mod ecs;
+pub mod edit;
+pub mod environment_processor;
+pub mod errors;
+pub mod fifo;
+pub mod files;
+pub mod get;
+#[cfg(feature = "keychain")]
+pub mod keychain;
+pub mod kv_store;
+pub mod lambda;
+pub mod lint;
+pub mod lockfile;
+pub mod logging;
+pub mod output;
+pub mod push_mapping;
+pub mod redact;
+pub mod resolver;
+pub mod rlimits;
+pub mod roles_anywhere;
+pub mod rotation_report;
+pub mod seccomp;
+pub mod secret_manager;
+pub mod signals;
+pub mod ssm_manager;
+pub mod status_file;
+pub mod summary;
+pub mod tags;
+pub mod template;
+#[cfg(feature = "test-harness")]
+pub mod test_harness;
+pub mod tls;
+pub mod watch;
+
+pub use resolver::{Resolver, ResolverBuilder};
+
+#[cfg(test)]
+pub mod tests;
@@ -0,0 +1,36 @@
+use jsonschema::JSONSchema;
+use serde_json::Value;
+
+/// JSON Schema describing the SSM parameter mapping, also published as
+/// `schema/mapping.schema.json`.
+pub const MAPPING_SCHEMA: &str = include_str!("../schema/mapping.schema.json");
+
+/// Validates a mapping document against the embedded schema, returning one
+/// error per offending key. An empty result means the mapping is valid.
+pub fn lint_mapping(document: &str) -> Vec<String> {
+    let mapping: Value = match serde_json::from_str(document) {
+        Ok(mapping) => mapping,
+        Err(e) => return vec![format!("mapping is not valid JSON: {}", e)],
+    };
+
+    let schema: Value =
+        serde_json::from_str(MAPPING_SCHEMA).expect("embedded mapping schema is valid JSON");
+    let compiled = JSONSchema::compile(&schema).expect("embedded mapping schema compiles");
+
+    let errors = match compiled.validate(&mapping) {
+        Ok(()) => return Vec::new(),
+        Err(errors) => errors,
+    };
+
+    errors
+        .map(|error| {
+            let path = error.instance_path.to_string();
+            let key = path.trim_start_matches('/');
+            if key.is_empty() {
+                format!("mapping: {}", error)
+            } else {
+                format!("{}: {}", key, error)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,82 @@
+use crate::environment_processor::{Provider, ResolvedSecret};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+pub const DEFAULT_LOCKFILE: &str = "resolve-aws-secrets.lock";
+
+const LOCKFILE_VERSION: u64 = 1;
+
+/// Secret versions pinned per reference, analogous to `Cargo.lock`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Lockfile {
+    pub versions: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read lockfile {}: {}", path.display(), e))?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let document: Value = serde_json::from_str(contents)?;
+        if document["version"] != LOCKFILE_VERSION {
+            return Err(format!("Unsupported lockfile version: {}", document["version"]).into());
+        }
+
+        let mut versions = BTreeMap::new();
+        if let Some(secrets) = document["secrets"].as_object() {
+            for (reference, version_id) in secrets {
+                let version_id = version_id
+                    .as_str()
+                    .ok_or_else(|| format!("Invalid version id for {} in lockfile", reference))?;
+                versions.insert(reference.clone(), version_id.to_string());
+            }
+        }
+        Ok(Lockfile { versions })
+    }
+
+    /// Builds a lockfile pinning every resolved Secrets Manager reference to
+    /// the version that was retrieved. Each secret of a merged list is pinned
+    /// on its own.
+    pub fn from_resolved(secrets: &[ResolvedSecret]) -> Result<Self, Box<dyn Error>> {
+        let mut versions = BTreeMap::new();
+        for secret in secrets {
+            if !matches!(
+                secret.provider,
+                Provider::SecretsManager | Provider::MergedSecrets
+            ) {
+                continue;
+            }
+            let version_id = secret
+                .version_id
+                .as_ref()
+                .ok_or_else(|| format!("No version id was returned for {}", secret.reference))?;
+            if secret.merged.is_empty() {
+                versions.insert(secret.reference.clone(), version_id.clone());
+            }
+            // Version ids are UUIDs, so the joined ids of a merged list split
+            // back unambiguously.
+            for (reference, version_id) in secret.merged.iter().zip(version_id.split(',')) {
+                versions.insert(reference.clone(), version_id.to_string());
+            }
+        }
+        Ok(Lockfile { versions })
+    }
+
+    pub fn to_json(&self) -> String {
+        let document = json!({
+            "version": LOCKFILE_VERSION,
+            "secrets": self.versions,
+        });
+        serde_json::to_string_pretty(&document).expect("lockfile serializes to JSON") + "\n"
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_json())
+            .map_err(|e| format!("Failed to write lockfile {}: {}", path.display(), e).into())
+    }
+}
@@ -0,0 +1,164 @@
+//! Logging facade: the `tracing` feature logs through tracing-subscriber,
+//! otherwise a minimal shim writes the messages to stderr, for size-constrained
+//! images that don't need the subscriber machinery.
+
+#[cfg(feature = "tracing")]
+pub use tracing::{error, info, warn};
+
+/// Format of the log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with the timestamp, level and fields, for
+    /// log pipelines. Requires the `tracing` feature, the minimal logger
+    /// always writes text.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format {}", other)),
+        }
+    }
+}
+
+/// The format given on the command line, or else by `LOG_FORMAT`, along
+/// with the error of an invalid `LOG_FORMAT`, which is logged once logging
+/// is initialized.
+fn log_format(format: Option<LogFormat>) -> (LogFormat, Option<String>) {
+    if let Some(format) = format {
+        return (format, None);
+    }
+    match std::env::var("LOG_FORMAT").as_deref().map(str::trim) {
+        Ok("") | Err(_) => (LogFormat::Text, None),
+        Ok(name) => match name.parse() {
+            Ok(format) => (format, None),
+            Err(e) => (LogFormat::Text, Some(e)),
+        },
+    }
+}
+
+/// Target of the AWS SDK events logging the response bodies at trace level,
+/// which hold the secret values when `LOG_SENSITIVE_BODIES` is set.
+#[cfg(feature = "tracing")]
+const SDK_BODY_TARGET: &str = "aws_smithy_runtime::client::orchestrator::http";
+
+/// Initializes logging to stderr in the format, keeping stdout for the
+/// command output. The level is taken from `RUST_LOG` and defaults to info,
+/// except for the response bodies of the AWS SDK, which are never logged.
+#[cfg(feature = "tracing")]
+pub fn init(format: Option<LogFormat>) {
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::EnvFilter;
+
+    let (format, invalid) = log_format(format);
+    let body_directive = format!("{}=debug", SDK_BODY_TARGET)
+        .parse()
+        .expect("the SDK body directive is valid");
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy()
+                .add_directive(body_directive),
+        )
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    if let Some(e) = invalid {
+        warn!("Ignoring LOG_FORMAT: {}", e);
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod shim {
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    pub const ERROR: u8 = 1;
+    pub const WARN: u8 = 2;
+    pub const INFO: u8 = 3;
+
+    static MAX_LEVEL: AtomicU8 = AtomicU8::new(INFO);
+
+    pub fn init(format: Option<super::LogFormat>) {
+        let (_, invalid) = super::log_format(format);
+        let level = max_level(std::env::var("RUST_LOG").ok().as_deref());
+        MAX_LEVEL.store(level, Ordering::Relaxed);
+        if let Some(e) = invalid {
+            log(WARN, format_args!("Ignoring LOG_FORMAT: {}", e));
+        }
+    }
+
+    /// The most verbose level logged for a `RUST_LOG` value, which only
+    /// selects a global level: `off`, `error`, `warn`, or info otherwise.
+    pub fn max_level(rust_log: Option<&str>) -> u8 {
+        match rust_log.map(str::trim) {
+            Some("off") => 0,
+            Some("error") => ERROR,
+            Some("warn") => WARN,
+            _ => INFO,
+        }
+    }
+
+    pub fn log(level: u8, args: std::fmt::Arguments) {
+        if level > MAX_LEVEL.load(Ordering::Relaxed) {
+            return;
+        }
+        let label = match level {
+            ERROR => "ERROR",
+            WARN => "WARN",
+            _ => "INFO",
+        };
+        eprintln!("{} {}", label, args);
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub use shim::init;
+
+#[cfg(all(test, not(feature = "tracing")))]
+pub(crate) use shim::max_level;
+
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+pub use shim::{log as __log, ERROR as __ERROR, INFO as __INFO, WARN as __WARN};
+
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_error {
+    ($($arg:tt)*) => {
+        $crate::logging::__log($crate::logging::__ERROR, format_args!($($arg)*))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_warn {
+    ($($arg:tt)*) => {
+        $crate::logging::__log($crate::logging::__WARN, format_args!($($arg)*))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::__log($crate::logging::__INFO, format_args!($($arg)*))
+    };
+}
+
+// Exported from the crate root, as the macros are used by the program too.
+#[cfg(not(feature = "tracing"))]
+pub use crate::{__log_error as error, __log_info as info, __log_warn as warn};
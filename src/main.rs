@@ -1,63 +1,72 @@
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretsManagerClient;
-use aws_sdk_ssm::Client as SsmClient;
+use resolve_aws_secrets::cli::{self, Command};
+use resolve_aws_secrets::errors::{self, ErrorFormat};
+use resolve_aws_secrets::logging::{self, error, info};
+use resolve_aws_secrets::{commands, lambda};
 use std::env;
 use std::error::Error;
-use tracing::{error, info, instrument};
-
-mod environment_processor;
-mod secret_manager;
-mod ssm_manager;
-
-#[cfg(test)]
-pub mod tests;
-
-use crate::environment_processor::process_environment;
 
 #[tokio::main]
-#[instrument]
+#[cfg_attr(feature = "tracing", tracing::instrument)]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+    // The arguments are parsed first, as they select the format of the logs.
+    let args: Vec<String> = env::args().collect();
+    let exec_wrapper = lambda::is_exec_wrapper(&args[0]);
+    let parsed = if exec_wrapper {
+        cli::exec_wrapper_args(&args[1..])
+            .map(Box::new)
+            .map(Command::Run)
+    } else {
+        cli::parse_args(&args[1..])
+    };
+    logging::init(parsed.as_ref().ok().and_then(Command::log_format));
 
     info!("Starting application");
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        error!("Insufficient arguments provided");
-        eprintln!("Usage: {} <program> [args...]", args[0]);
-        std::process::exit(1);
+    if exec_wrapper {
+        info!("Running as AWS_LAMBDA_EXEC_WRAPPER");
+        if let Err(e) = lambda::check_snapstart() {
+            error!("{}", e);
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
+    let command = match parsed {
+        Ok(command) => command,
+        Err(e) => {
+            error!("Invalid arguments provided: {}", e);
+            eprintln!("{}", e);
+            eprintln!("{}", cli::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
 
-    info!("Initializing AWS configuration");
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(region_provider)
-        .load()
-        .await;
+    let error_format = command.error_format();
+    let result = match command {
+        Command::Run(run_args) => commands::run(*run_args).await,
+        Command::Lint(lint_args) => commands::lint(lint_args).await,
+        Command::PushMapping(push_args) => commands::push_mapping(push_args).await,
+        Command::Lock(lock_args) => commands::lock(lock_args).await,
+        Command::GenEcs(options) => commands::gen_ecs(options).await,
+        Command::RotationReport(report_args) => commands::rotation_report(report_args).await,
+        Command::Bench(bench_args) => commands::bench(bench_args).await,
+        Command::Edit(edit_args) => commands::edit(edit_args).await,
+        Command::Get(get_args) => commands::get(get_args).await,
+        Command::Copy(copy_args) => commands::copy(copy_args).await,
+        Command::Help => {
+            println!("{}", cli::usage(&args[0]));
+            Ok(())
+        }
+    };
 
-    info!("Creating AWS clients");
-    let secretsmanager_client = SecretsManagerClient::new(&config);
-    let ssm_client = SsmClient::new(&config);
-
-    info!("Processing environment");
-    let secrets = process_environment(&secretsmanager_client, &ssm_client).await?;
-    info!("Processed {} environment variables", secrets.len());
-
-    // Create a new environment with both existing and new variables
-    let mut new_env: std::collections::HashMap<String, String> = env::vars().collect();
-    for (key, value) in &secrets {
-        info!("Setting environment variable: {}", key);
-        new_env.insert(key.clone(), value.clone());
+    // Report failures with their display form, which names the variable and
+    // reference being resolved, rather than the debug form main would print.
+    if let Err(e) = result {
+        error!("{}", e);
+        match error_format {
+            ErrorFormat::Text => eprintln!("Error: {}", e),
+            ErrorFormat::Json => eprintln!("{}", errors::error_json(e.as_ref())),
+        }
+        std::process::exit(errors::exit_code(e.as_ref()));
     }
-
-    info!("Executing command: {}", args[1]);
-    let status = std::process::Command::new(&args[1])
-        .args(&args[2..])
-        .envs(&new_env)
-        .status()?;
-
-    let exit_code = status.code().unwrap_or(1);
-    info!("Command exited with status code: {}", exit_code);
-    std::process::exit(exit_code)
+    Ok(())
 }
@@ -0,0 +1,146 @@
+use crate::environment_processor::{is_variable_name, ResolvedSecret};
+use crate::files::{check_storage, write_private_file, PersistentFiles};
+use crate::logging::info;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::path::Path;
+
+/// Format in which the resolved variables are written out instead of
+/// running a program with them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// `KEY='value'` lines, for `source` or dotenv libraries.
+    Dotenv,
+    /// A JSON object of the variables, optionally with their provenance.
+    Json,
+    /// `export KEY='value'` lines, for `eval` in shell scripts.
+    Shell,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "dotenv" => Ok(OutputFormat::Dotenv),
+            "json" => Ok(OutputFormat::Json),
+            "shell" => Ok(OutputFormat::Shell),
+            other => Err(format!("unknown output format {}", other)),
+        }
+    }
+}
+
+/// Renders the resolved variables in the format. With provenance, the JSON
+/// object maps each variable to its value and where it was resolved from.
+/// The dotenv and shell formats fail on keys that aren't valid variable
+/// names, which would otherwise be written unquoted into the output.
+pub fn render(
+    format: OutputFormat,
+    secrets: &[ResolvedSecret],
+    provenance: bool,
+) -> Result<String, Box<dyn Error>> {
+    let rendered = match format {
+        OutputFormat::Dotenv => secrets
+            .iter()
+            .map(|secret| {
+                Ok(format!(
+                    "{}={}\n",
+                    variable_name(secret)?,
+                    dotenv_quote(secret.value.expose())
+                ))
+            })
+            .collect::<Result<_, String>>()?,
+        OutputFormat::Json => {
+            let object: Map<String, Value> = secrets
+                .iter()
+                .map(|secret| {
+                    let value = match provenance {
+                        true => json!({
+                            "value": secret.value.expose(),
+                            "source": secret.source.to_string(),
+                            "type": secret.provider.to_string(),
+                            "reference": secret.reference,
+                            "version_id": secret.version_id,
+                        }),
+                        false => json!(secret.value.expose()),
+                    };
+                    (secret.key.clone(), value)
+                })
+                .collect();
+            serde_json::to_string_pretty(&object).expect("variables serialize to JSON") + "\n"
+        }
+        OutputFormat::Shell => secrets
+            .iter()
+            .map(|secret| {
+                Ok(format!(
+                    "export {}={}\n",
+                    variable_name(secret)?,
+                    shell_quote(secret.value.expose())
+                ))
+            })
+            .collect::<Result<_, String>>()?,
+    };
+    Ok(rendered)
+}
+
+/// The key of the secret, when it's a valid variable name.
+fn variable_name(secret: &ResolvedSecret) -> Result<&str, String> {
+    match is_variable_name(&secret.key) {
+        true => Ok(&secret.key),
+        false => Err(format!(
+            "{} ({}) is not a valid variable name",
+            secret.key, secret.reference
+        )),
+    }
+}
+
+/// Quotes a value for POSIX shells, in single quotes, where nothing is
+/// expanded, closing them around each single quote of the value to escape
+/// it, so newlines, double quotes and `$` are kept as they are.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quotes a value for dotenv files, in single quotes, which both shells and
+/// dotenv libraries take literally, including newlines. Values containing
+/// single quotes are double-quoted instead, escaping the characters shells
+/// expand in double quotes.
+fn dotenv_quote(value: &str) -> String {
+    if !value.contains('\'') {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes the resolved variables in the format to a private file, or to
+/// stdout when no file is given.
+pub fn write(
+    format: OutputFormat,
+    secrets: &[ResolvedSecret],
+    provenance: bool,
+    out_file: Option<&Path>,
+    policy: PersistentFiles,
+) -> Result<(), Box<dyn Error>> {
+    let contents = render(format, secrets, provenance)?;
+    match out_file {
+        Some(path) => {
+            check_storage(path, policy)?;
+            write_private_file(path, contents.as_bytes())?;
+            info!(
+                "Wrote {} resolved variables to {}",
+                secrets.len(),
+                path.display()
+            );
+        }
+        None => print!("{}", contents),
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use crate::lint::lint_mapping;
+use crate::logging::{info, warn};
+use crate::ssm_manager::{get_ssm_parameter, is_parameter_not_found, SsmClientTrait};
+use serde_json::{Map, Value};
+use std::error::Error;
+
+/// Lists the entries added (`+`), removed (`-`) and changed (`~`) by the new
+/// mapping, one line per key, in the order of the keys.
+pub fn mapping_diff(current: &Map<String, Value>, new: &Map<String, Value>) -> Vec<String> {
+    let mut keys: Vec<&String> = current.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| match (current.get(key), new.get(key)) {
+            (None, Some(value)) => Some(format!("+ {}: {}", key, value)),
+            (Some(value), None) => Some(format!("- {}: {}", key, value)),
+            (Some(old), Some(value)) if old != value => {
+                Some(format!("~ {}: {} -> {}", key, old, value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes the mapping document to the SSM parameter, after validating it and
+/// passing the changes to the live mapping to `confirm`. Returns false when
+/// nothing was written, because the mapping is up to date or the changes
+/// weren't confirmed.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(client, document, confirm))
+)]
+pub async fn push_mapping<T, F>(
+    client: &T,
+    parameter: &str,
+    document: &str,
+    confirm: F,
+) -> Result<bool, Box<dyn Error>>
+where
+    T: SsmClientTrait + ?Sized,
+    F: FnOnce(&[String]) -> Result<bool, Box<dyn Error>>,
+{
+    let errors = lint_mapping(document);
+    if !errors.is_empty() {
+        return Err(format!("The mapping is invalid:\n{}", errors.join("\n")).into());
+    }
+    let new: Map<String, Value> = serde_json::from_str(document)?;
+
+    let current = match get_ssm_parameter(client, parameter, true).await {
+        Ok(current) => Some(current),
+        Err(e) if is_parameter_not_found(e.as_ref()) => None,
+        Err(e) => return Err(e),
+    };
+    let current_mapping = match current.as_deref().map(serde_json::from_str) {
+        Some(Ok(Value::Object(mapping))) => mapping,
+        Some(_) => {
+            warn!("The current value of {} is not a JSON object", parameter);
+            Map::new()
+        }
+        None => Map::new(),
+    };
+
+    let diff = mapping_diff(&current_mapping, &new);
+    if current.is_some() && diff.is_empty() {
+        info!("The mapping in {} is up to date", parameter);
+        return Ok(false);
+    }
+    if !confirm(&diff)? {
+        return Ok(false);
+    }
+
+    info!("Writing the mapping to {}", parameter);
+    client
+        .put_parameter(parameter, document.trim(), current.is_some())
+        .await?;
+    Ok(true)
+}
@@ -0,0 +1,233 @@
+use crate::aws_clients::{LazyClient, LazyConfig};
+use crate::commands::spawn_aws_config;
+use crate::environment_processor::{
+    process_environment, DeadlinePolicy, MissingPolicy, ResolveOptions, ResolvedSecret, Variables,
+};
+use crate::secret_manager::SecretsManagerClientTrait;
+use crate::ssm_manager::SsmClientTrait;
+use aws_config::SdkConfig;
+use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+use aws_sdk_ssm::Client as SsmClient;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// Resolves secret references into a map of variable names to values, as
+/// the program does before running a command, for embedding the resolution
+/// in other programs.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let values = resolve_aws_secrets::Resolver::builder()
+///     .secret("DB_PASSWORD", "arn:aws:secretsmanager:us-east-1:123456789012:secret:db")
+///     .parameter("API_URL", "/myapp/api-url")
+///     .concurrency(4)
+///     .strict(true)
+///     .build()
+///     .resolve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    options: ResolveOptions,
+    config: Option<SdkConfig>,
+}
+
+impl Resolver {
+    pub fn builder() -> ResolverBuilder {
+        ResolverBuilder::default()
+    }
+
+    /// The options the references are resolved with.
+    pub fn options(&self) -> &ResolveOptions {
+        &self.options
+    }
+
+    /// Resolves the references with clients built from the AWS configuration
+    /// given to the builder, or loaded from the environment.
+    pub async fn resolve(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        let config = match &self.config {
+            Some(config) => LazyConfig::spawn(std::future::ready(config.clone())),
+            None => spawn_aws_config(&self.options)?,
+        };
+        let secretsmanager_client = LazyClient::new(&config, SecretsManagerClient::new);
+        let ssm_client = LazyClient::new(&config, SsmClient::new);
+        self.resolve_with(&secretsmanager_client, &ssm_client).await
+    }
+
+    /// Resolves the references with the given clients.
+    pub async fn resolve_with<S, T>(
+        &self,
+        secretsmanager_client: &S,
+        ssm_client: &T,
+    ) -> Result<BTreeMap<String, String>, Box<dyn Error>>
+    where
+        S: SecretsManagerClientTrait + ?Sized,
+        T: SsmClientTrait + ?Sized,
+    {
+        let secrets = self
+            .resolve_secrets_with(secretsmanager_client, ssm_client)
+            .await?;
+        Ok(secrets
+            .into_iter()
+            .map(|secret| (secret.key, secret.value.into_inner()))
+            .collect())
+    }
+
+    /// Resolves the references with the given clients, keeping where each
+    /// value was resolved from.
+    pub async fn resolve_secrets_with<S, T>(
+        &self,
+        secretsmanager_client: &S,
+        ssm_client: &T,
+    ) -> Result<Vec<ResolvedSecret>, Box<dyn Error>>
+    where
+        S: SecretsManagerClientTrait + ?Sized,
+        T: SsmClientTrait + ?Sized,
+    {
+        process_environment(secretsmanager_client, ssm_client, &self.options).await
+    }
+}
+
+/// Builds a [`Resolver`]. The references are collected from the variables
+/// of the process, as by the program, unless sources are given with
+/// [`variables`](Self::variables), [`secret`](Self::secret),
+/// [`parameter`](Self::parameter) or
+/// [`mapping_parameter`](Self::mapping_parameter).
+#[derive(Debug, Default)]
+pub struct ResolverBuilder {
+    options: ResolveOptions,
+    config: Option<SdkConfig>,
+    variables: Option<Vec<(String, String)>>,
+    added: Vec<(String, String)>,
+    prefix: Option<String>,
+}
+
+impl ResolverBuilder {
+    /// Collects the references from these variables, declared as in the
+    /// environment of the program, such as `SECRET_DB_PASSWORD=arn:...`.
+    pub fn variables<I, K, V>(mut self, variables: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let variables = variables
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()));
+        self.variables
+            .get_or_insert_with(Vec::new)
+            .extend(variables);
+        self
+    }
+
+    /// Resolves the Secrets Manager or key-value store reference into the
+    /// variable, as a `SECRET_` variable does.
+    pub fn secret(mut self, key: &str, reference: &str) -> Self {
+        self.added
+            .push((format!("SECRET_{}", key), reference.to_string()));
+        self
+    }
+
+    /// Resolves the SSM parameter into the variable, as an `SSM_PARAM_`
+    /// variable does.
+    pub fn parameter(mut self, key: &str, name: &str) -> Self {
+        self.added
+            .push((format!("SSM_PARAM_{}", key), name.to_string()));
+        self
+    }
+
+    /// Resolves the mapping stored in the SSM parameter, given by name or
+    /// ARN, as `SECRETS_PARAMETER_NAME` does.
+    pub fn mapping_parameter(mut self, parameter: &str) -> Self {
+        self.added
+            .push(("SECRETS_PARAMETER_NAME".to_string(), parameter.to_string()));
+        self
+    }
+
+    /// Only collects the variables starting with the prefix, with the prefix
+    /// removed, so that `MYAPP_SECRET_DB=arn:...` declares `DB` with the
+    /// `MYAPP_` prefix. The sources added with [`secret`](Self::secret),
+    /// [`parameter`](Self::parameter) and
+    /// [`mapping_parameter`](Self::mapping_parameter) aren't prefixed.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Resolves up to this many references at the same time.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.options.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Fails on malformed references instead of skipping them with a warning.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Gives up on the references still pending after this long, failing
+    /// the resolution or continuing without them.
+    pub fn deadline(mut self, deadline: Duration, policy: DeadlinePolicy) -> Self {
+        self.options.resolve_deadline = Some(deadline);
+        self.options.on_deadline = policy;
+        self
+    }
+
+    /// Continues without, or with empty values for, the secrets and
+    /// parameters that don't exist or can't be accessed, instead of failing.
+    pub fn missing(mut self, policy: MissingPolicy) -> Self {
+        self.options.missing = policy;
+        self
+    }
+
+    /// Reuses the retrieved values in the next resolutions of the resolver
+    /// until they are older than the TTL, instead of retrieving them again.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.options.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Replaces the options, for the settings without a method of their own.
+    /// The sources and prefix given to the builder still apply.
+    pub fn options(mut self, options: ResolveOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Builds the clients from this configuration instead of loading it
+    /// from the environment.
+    pub fn sdk_config(mut self, config: SdkConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn build(self) -> Resolver {
+        let mut options = self.options;
+        if self.variables.is_some() || !self.added.is_empty() || self.prefix.is_some() {
+            let mut variables = match self.variables {
+                Some(variables) => variables,
+                None if self.added.is_empty() => std::env::vars().collect(),
+                None => Vec::new(),
+            };
+            if let Some(prefix) = &self.prefix {
+                variables = variables
+                    .into_iter()
+                    .filter_map(|(key, value)| {
+                        key.strip_prefix(prefix.as_str())
+                            .map(|key| (key.to_string(), value))
+                    })
+                    .collect();
+            }
+            variables.extend(self.added);
+            options.variables = Some(Variables(variables));
+        }
+        Resolver {
+            options,
+            config: self.config,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Resource limits applied to the command, so the tool can replace the
+//! `ulimit` calls of entrypoint scripts in images without a shell.
+
+use std::error::Error;
+use std::str::FromStr;
+
+/// A resource whose limit can be set with `--rlimit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    /// Size of core dumps, in bytes.
+    Core,
+    /// CPU time, in seconds.
+    Cpu,
+    /// Size of the data segment, in bytes.
+    Data,
+    /// Size of the files written, in bytes.
+    Fsize,
+    /// Locked memory, in bytes.
+    Memlock,
+    /// Open file descriptors.
+    Nofile,
+    /// Processes of the user.
+    Nproc,
+    /// Size of the stack, in bytes.
+    Stack,
+    /// Size of the virtual memory, in bytes.
+    As,
+}
+
+impl FromStr for Resource {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "core" => Ok(Resource::Core),
+            "cpu" => Ok(Resource::Cpu),
+            "data" => Ok(Resource::Data),
+            "fsize" => Ok(Resource::Fsize),
+            "memlock" => Ok(Resource::Memlock),
+            "nofile" => Ok(Resource::Nofile),
+            "nproc" => Ok(Resource::Nproc),
+            "stack" => Ok(Resource::Stack),
+            "as" => Ok(Resource::As),
+            other => Err(format!("unknown resource {}", other)),
+        }
+    }
+}
+
+/// A limit value, where `None` means unlimited.
+type Value = Option<u64>;
+
+/// A limit to set, given as `<resource>=<soft>[:<hard>]`. Without a hard
+/// limit, only the soft limit is changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rlimit {
+    pub resource: Resource,
+    pub soft: Value,
+    pub hard: Option<Value>,
+}
+
+impl FromStr for Rlimit {
+    type Err = Box<dyn Error>;
+
+    fn from_str(limit: &str) -> Result<Self, Self::Err> {
+        fn value(value: &str) -> Result<Value, String> {
+            match value {
+                "unlimited" => Ok(None),
+                value => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("invalid limit {}", value)),
+            }
+        }
+
+        let (resource, values) = limit
+            .split_once('=')
+            .ok_or_else(|| format!("expected <resource>=<soft>[:<hard>], got {}", limit))?;
+        let (soft, hard) = match values.split_once(':') {
+            Some((soft, hard)) => (value(soft)?, Some(value(hard)?)),
+            None => (value(values)?, None),
+        };
+        // Unlimited is greater than any finite limit.
+        if let Some(hard) = hard {
+            if soft.unwrap_or(u64::MAX) > hard.unwrap_or(u64::MAX) {
+                return Err(format!("the soft limit exceeds the hard limit in {}", limit).into());
+            }
+        }
+        Ok(Rlimit {
+            resource: resource.parse()?,
+            soft,
+            hard,
+        })
+    }
+}
+
+/// Sets the limits in the command process before it executes the program.
+#[cfg(unix)]
+pub fn apply(command: &mut std::process::Command, limits: &[Rlimit]) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::process::CommandExt;
+
+    if limits.is_empty() {
+        return Ok(());
+    }
+    let limits = limits.to_vec();
+    // SAFETY: the closure only calls getrlimit and setrlimit, which are
+    // async-signal-safe, and doesn't allocate.
+    unsafe {
+        command.pre_exec(move || {
+            for limit in &limits {
+                set(limit)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply(
+    _command: &mut std::process::Command,
+    limits: &[Rlimit],
+) -> Result<(), Box<dyn Error>> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    Err("Resource limits are only supported on Unix".into())
+}
+
+#[cfg(unix)]
+fn set(limit: &Rlimit) -> std::io::Result<()> {
+    fn raw(value: Value) -> libc::rlim_t {
+        value.map_or(libc::RLIM_INFINITY, |value| value as libc::rlim_t)
+    }
+
+    let resource = match limit.resource {
+        Resource::Core => libc::RLIMIT_CORE,
+        Resource::Cpu => libc::RLIMIT_CPU,
+        Resource::Data => libc::RLIMIT_DATA,
+        Resource::Fsize => libc::RLIMIT_FSIZE,
+        Resource::Memlock => libc::RLIMIT_MEMLOCK,
+        Resource::Nofile => libc::RLIMIT_NOFILE,
+        Resource::Nproc => libc::RLIMIT_NPROC,
+        Resource::Stack => libc::RLIMIT_STACK,
+        Resource::As => libc::RLIMIT_AS,
+    };
+    let mut current = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: current is a valid rlimit to write to.
+    if unsafe { libc::getrlimit(resource, &mut current) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let new = libc::rlimit {
+        rlim_cur: raw(limit.soft),
+        rlim_max: limit.hard.map_or(current.rlim_max, raw),
+    };
+    // SAFETY: new is a valid rlimit to read from.
+    if unsafe { libc::setrlimit(resource, &new) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use aws_config::credential_process::CredentialProcessProvider;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Credential helper implementing the IAM Roles Anywhere signing protocol,
+/// looked up in the PATH.
+const SIGNING_HELPER: &str = "aws_signing_helper";
+
+/// IAM Roles Anywhere settings, for authenticating with an X.509 certificate
+/// instead of static keys when running outside AWS.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RolesAnywhere {
+    pub certificate: Option<PathBuf>,
+    pub private_key: Option<PathBuf>,
+    pub trust_anchor_arn: Option<String>,
+    pub profile_arn: Option<String>,
+    pub role_arn: Option<String>,
+}
+
+impl RolesAnywhere {
+    /// Returns the provider exchanging the certificate for temporary
+    /// credentials, or None when Roles Anywhere isn't configured. Fails when
+    /// only some of the settings are given.
+    pub fn credentials_provider(
+        &self,
+    ) -> Result<Option<CredentialProcessProvider>, Box<dyn Error>> {
+        if *self == RolesAnywhere::default() {
+            return Ok(None);
+        }
+        Ok(Some(CredentialProcessProvider::new(
+            self.credential_process()?,
+        )))
+    }
+
+    /// Builds the `credential_process` command running the signing helper.
+    pub fn credential_process(&self) -> Result<String, Box<dyn Error>> {
+        fn required<'a>(value: Option<&'a str>, option: &str) -> Result<&'a str, String> {
+            value.ok_or_else(|| format!("IAM Roles Anywhere requires {}", option))
+        }
+
+        let arguments = [
+            (
+                "--certificate",
+                required(
+                    self.certificate.as_deref().and_then(|path| path.to_str()),
+                    "--roles-anywhere-certificate",
+                )?,
+            ),
+            (
+                "--private-key",
+                required(
+                    self.private_key.as_deref().and_then(|path| path.to_str()),
+                    "--roles-anywhere-private-key",
+                )?,
+            ),
+            (
+                "--trust-anchor-arn",
+                required(
+                    self.trust_anchor_arn.as_deref(),
+                    "--roles-anywhere-trust-anchor",
+                )?,
+            ),
+            (
+                "--profile-arn",
+                required(self.profile_arn.as_deref(), "--roles-anywhere-profile")?,
+            ),
+            (
+                "--role-arn",
+                required(self.role_arn.as_deref(), "--roles-anywhere-role")?,
+            ),
+        ];
+
+        let mut command = format!("{} credential-process", SIGNING_HELPER);
+        for (option, value) in arguments {
+            command.push_str(&format!(" {} {}", option, quote(value)));
+        }
+        Ok(command)
+    }
+}
+
+/// Quotes an argument for the shell running the credential process.
+fn quote(argument: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", argument)
+    } else {
+        format!("'{}'", argument.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,156 @@
+use crate::environment_processor::{Provider, Reference};
+use crate::errors::error_message;
+use crate::secret_manager::SecretsManagerClientTrait;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use std::error::Error;
+use std::time::{Duration, SystemTime};
+
+/// Age beyond which a secret is flagged as stale when no threshold is given.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(90 * 24 * 3600);
+
+const HEADERS: [&str; 5] = ["KEY", "REFERENCE", "LAST ROTATED", "ROTATION", "STATUS"];
+
+/// Rotation state of one referenced secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationStatus {
+    pub key: String,
+    pub reference: String,
+    /// When the secret was last rotated, or created if it never was.
+    pub last_rotated: Option<DateTime>,
+    pub rotated: bool,
+    /// The rotation schedule, if rotation is enabled.
+    pub schedule: Option<String>,
+    /// Whether the secret wasn't rotated within the threshold.
+    pub stale: bool,
+}
+
+/// Describes every Secrets Manager secret among the references, one entry
+/// per secret of a merged list, flagging those not rotated within `max_age`
+/// of `now`.
+pub async fn rotation_report<S: SecretsManagerClientTrait + ?Sized>(
+    client: &S,
+    references: &[Reference],
+    max_age: Duration,
+    now: SystemTime,
+) -> Result<Vec<RotationStatus>, Box<dyn Error>> {
+    let oldest = DateTime::from(now - max_age);
+    let mut statuses = Vec::new();
+
+    for reference in references {
+        if !matches!(
+            reference.provider,
+            Provider::SecretsManager | Provider::MergedSecrets | Provider::JsonObject
+        ) {
+            continue;
+        }
+        if reference.options.targets_other_client() {
+            let client = client
+                .with_target(
+                    reference.options.region.as_deref(),
+                    reference.options.role.as_deref(),
+                )
+                .await?;
+            statuses.extend(describe_reference(client.as_ref(), reference, oldest).await?);
+        } else {
+            statuses.extend(describe_reference(client, reference, oldest).await?);
+        }
+    }
+
+    Ok(statuses)
+}
+
+async fn describe_reference<S: SecretsManagerClientTrait + ?Sized>(
+    client: &S,
+    reference: &Reference,
+    oldest: DateTime,
+) -> Result<Vec<RotationStatus>, Box<dyn Error>> {
+    let mut statuses = Vec::new();
+    let arns = match reference.provider {
+        Provider::MergedSecrets => &reference.options.merge[..],
+        _ => std::slice::from_ref(&reference.reference),
+    };
+    for arn in arns {
+        let response = client
+            .describe_secret(arn)
+            .await
+            .map_err(|e| format!("Failed to describe {}: {}", arn, error_message(&e)))?;
+        let last_rotated = response.last_rotated_date().copied();
+        let since = last_rotated.or(response.created_date().copied());
+        let schedule = match (response.rotation_enabled(), response.rotation_rules()) {
+            (Some(true), Some(rules)) => Some(
+                match (
+                    rules.schedule_expression(),
+                    rules.automatically_after_days(),
+                ) {
+                    (Some(expression), _) => expression.to_string(),
+                    (None, Some(days)) => format!("every {} days", days),
+                    (None, None) => "enabled".to_string(),
+                },
+            ),
+            (Some(true), None) => Some("enabled".to_string()),
+            _ => None,
+        };
+        statuses.push(RotationStatus {
+            key: reference.key.clone(),
+            reference: arn.to_string(),
+            last_rotated: since,
+            rotated: last_rotated.is_some(),
+            schedule,
+            stale: since.is_none_or(|since| since.secs() < oldest.secs()),
+        });
+    }
+    Ok(statuses)
+}
+
+/// Renders the report as a table, with the stale secrets flagged.
+pub fn report_table(statuses: &[RotationStatus]) -> String {
+    let rows: Vec<[String; 5]> = statuses
+        .iter()
+        .map(|status| {
+            let last_rotated = match (&status.last_rotated, status.rotated) {
+                (Some(date), rotated) => {
+                    let date = date
+                        .fmt(Format::DateTime)
+                        .unwrap_or_else(|_| date.to_string());
+                    if rotated {
+                        date
+                    } else {
+                        format!("never (created {})", date)
+                    }
+                }
+                (None, _) => "unknown".to_string(),
+            };
+            [
+                status.key.clone(),
+                status.reference.clone(),
+                last_rotated,
+                status
+                    .schedule
+                    .clone()
+                    .unwrap_or_else(|| "disabled".to_string()),
+                if status.stale { "STALE" } else { "ok" }.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    let header = HEADERS.map(String::from);
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
@@ -0,0 +1,365 @@
+//! Seccomp filters applied to the command just before it executes the
+//! program, compiled from profiles in the Docker JSON format, so the tool
+//! can enforce them where no container runtime does.
+
+mod syscalls;
+
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGT_K: u16 = 0x25;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_AND_K: u16 = 0x54;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// Offsets of the fields of `struct seccomp_data`.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const ARGS_OFFSET: u32 = 16;
+
+/// Errno returned by `SCMP_ACT_ERRNO` when the profile doesn't give one.
+const EPERM: u32 = 1;
+
+/// System calls of the x32 ABI, which share the x86_64 audit architecture.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// An architecture a profile can be compiled for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arch {
+    /// Name of the architecture in Docker profiles.
+    pub name: &'static str,
+    audit: u32,
+    syscalls: &'static [(&'static str, u32)],
+}
+
+pub const X86_64: Arch = Arch {
+    name: "amd64",
+    audit: 0xc000_003e,
+    syscalls: syscalls::X86_64,
+};
+
+pub const AARCH64: Arch = Arch {
+    name: "arm64",
+    audit: 0xc000_00b7,
+    syscalls: syscalls::AARCH64,
+};
+
+impl Arch {
+    /// The architecture of the running program, if profiles are supported
+    /// on it.
+    pub fn current() -> Option<Arch> {
+        match std::env::consts::ARCH {
+            "x86_64" => Some(X86_64),
+            "aarch64" => Some(AARCH64),
+            _ => None,
+        }
+    }
+
+    fn syscall(&self, name: &str) -> Option<u32> {
+        self.syscalls
+            .iter()
+            .find(|(syscall, _)| *syscall == name)
+            .map(|(_, number)| *number)
+    }
+}
+
+/// A classic BPF instruction, laid out like `struct sock_filter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// Where a jump of an instruction within a rule goes: forward by a number of
+/// instructions, or to the next rule when a condition doesn't hold.
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Skip(u8),
+    NextRule,
+}
+
+use Target::{NextRule, Skip};
+
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    code: u16,
+    jt: Target,
+    jf: Target,
+    k: u32,
+}
+
+fn statement(code: u16, k: u32) -> Pending {
+    jump(code, k, Skip(0), Skip(0))
+}
+
+fn jump(code: u16, k: u32, jt: Target, jf: Target) -> Pending {
+    Pending { code, jt, jf, k }
+}
+
+/// Compiles the Docker seccomp profile at the path for the architecture.
+pub fn load_profile(path: &Path, arch: Arch) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let profile = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let profile: Value = serde_json::from_str(&profile)
+        .map_err(|e| format!("Invalid seccomp profile {}: {}", path.display(), e))?;
+    compile(&profile, arch)
+}
+
+/// Compiles a profile in the Docker format into a BPF program. Rules
+/// conditioned on capabilities are skipped, as the tool grants none, and
+/// system calls unknown on the architecture are ignored, like Docker does.
+pub fn compile(profile: &Value, arch: Arch) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let default_errno = profile["defaultErrnoRet"]
+        .as_u64()
+        .map(|errno| errno as u32);
+    let default_action = action(&profile["defaultAction"], default_errno)?;
+
+    let mut program = vec![
+        statement(BPF_LD_W_ABS, ARCH_OFFSET),
+        jump(BPF_JEQ_K, arch.audit, Skip(1), Skip(0)),
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, NR_OFFSET),
+    ];
+    if arch == X86_64 {
+        program.push(jump(BPF_JGE_K, X32_SYSCALL_BIT, Skip(0), Skip(1)));
+        program.push(statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS));
+    }
+    let mut instructions = resolve(program)?;
+
+    let rules = profile["syscalls"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut number_loaded = true;
+    for rule in rules {
+        if !applies(rule, arch) {
+            continue;
+        }
+        let errno = rule["errnoRet"].as_u64().map(|errno| errno as u32);
+        let action = action(&rule["action"], errno)?;
+        let conditions = rule["args"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let names = match (&rule["names"], &rule["name"]) {
+            (Value::Array(names), _) => names.iter().filter_map(Value::as_str).collect(),
+            (_, Value::String(name)) => vec![name.as_str()],
+            _ => return Err("seccomp rule without names".into()),
+        };
+        for number in names.into_iter().filter_map(|name| arch.syscall(name)) {
+            let mut block = Vec::new();
+            if !number_loaded {
+                block.push(statement(BPF_LD_W_ABS, NR_OFFSET));
+            }
+            block.push(jump(BPF_JEQ_K, number, Skip(0), NextRule));
+            for condition in conditions {
+                block.extend(argument_check(condition)?);
+            }
+            block.push(statement(BPF_RET_K, action));
+            instructions.extend(resolve(block)?);
+            number_loaded = conditions.is_empty();
+        }
+    }
+    instructions.push(Instruction {
+        code: BPF_RET_K,
+        jt: 0,
+        jf: 0,
+        k: default_action,
+    });
+    Ok(instructions)
+}
+
+/// Whether a rule applies to the architecture when the tool grants no
+/// capabilities.
+fn applies(rule: &Value, arch: Arch) -> bool {
+    let listed = |list: &Value| {
+        list.as_array()
+            .is_some_and(|values| values.iter().any(|value| value == arch.name))
+    };
+    let includes = &rule["includes"];
+    let requires_caps = includes["caps"]
+        .as_array()
+        .is_some_and(|caps| !caps.is_empty());
+    let other_arch = includes["arches"].is_array() && !listed(&includes["arches"]);
+    !requires_caps && !other_arch && !listed(&rule["excludes"]["arches"])
+}
+
+/// The return value of the filter for an action.
+fn action(action: &Value, errno: Option<u32>) -> Result<u32, Box<dyn Error>> {
+    let errno = errno.unwrap_or(EPERM) & 0xffff;
+    match action.as_str() {
+        Some("SCMP_ACT_ALLOW") => Ok(SECCOMP_RET_ALLOW),
+        Some("SCMP_ACT_ERRNO") => Ok(SECCOMP_RET_ERRNO | errno),
+        Some("SCMP_ACT_KILL") | Some("SCMP_ACT_KILL_THREAD") => Ok(SECCOMP_RET_KILL_THREAD),
+        Some("SCMP_ACT_KILL_PROCESS") => Ok(SECCOMP_RET_KILL_PROCESS),
+        Some("SCMP_ACT_TRAP") => Ok(SECCOMP_RET_TRAP),
+        Some("SCMP_ACT_TRACE") => Ok(SECCOMP_RET_TRACE | errno),
+        Some("SCMP_ACT_LOG") => Ok(SECCOMP_RET_LOG),
+        other => Err(format!("unsupported seccomp action {}", other.unwrap_or_default()).into()),
+    }
+}
+
+/// Checks a 64-bit argument with 32-bit loads, jumping to the next rule
+/// when the condition doesn't hold and falling through otherwise.
+fn argument_check(condition: &Value) -> Result<Vec<Pending>, Box<dyn Error>> {
+    let index = condition["index"]
+        .as_u64()
+        .filter(|index| *index < 6)
+        .ok_or("seccomp argument condition without a valid index")?;
+    let value = condition["value"].as_u64().unwrap_or_default();
+    let value_two = condition["valueTwo"].as_u64().unwrap_or_default();
+    let low = ARGS_OFFSET + 8 * index as u32;
+    let high = low + 4;
+    let (value_high, value_low) = ((value >> 32) as u32, value as u32);
+    let load_high = statement(BPF_LD_W_ABS, high);
+    let load_low = statement(BPF_LD_W_ABS, low);
+
+    let check = match condition["op"].as_str() {
+        Some("SCMP_CMP_EQ") => vec![
+            load_high,
+            jump(BPF_JEQ_K, value_high, Skip(0), NextRule),
+            load_low,
+            jump(BPF_JEQ_K, value_low, Skip(0), NextRule),
+        ],
+        Some("SCMP_CMP_NE") => vec![
+            load_high,
+            jump(BPF_JEQ_K, value_high, Skip(0), Skip(2)),
+            load_low,
+            jump(BPF_JEQ_K, value_low, NextRule, Skip(0)),
+        ],
+        Some("SCMP_CMP_MASKED_EQ") => vec![
+            load_high,
+            statement(BPF_AND_K, value_high),
+            jump(BPF_JEQ_K, (value_two >> 32) as u32, Skip(0), NextRule),
+            load_low,
+            statement(BPF_AND_K, value_low),
+            jump(BPF_JEQ_K, value_two as u32, Skip(0), NextRule),
+        ],
+        Some(op @ ("SCMP_CMP_GT" | "SCMP_CMP_GE")) => vec![
+            load_high,
+            jump(BPF_JGT_K, value_high, Skip(3), Skip(0)),
+            jump(BPF_JEQ_K, value_high, Skip(0), NextRule),
+            load_low,
+            jump(
+                if op == "SCMP_CMP_GT" {
+                    BPF_JGT_K
+                } else {
+                    BPF_JGE_K
+                },
+                value_low,
+                Skip(0),
+                NextRule,
+            ),
+        ],
+        Some(op @ ("SCMP_CMP_LT" | "SCMP_CMP_LE")) => vec![
+            load_high,
+            jump(BPF_JGT_K, value_high, NextRule, Skip(0)),
+            jump(BPF_JEQ_K, value_high, Skip(0), Skip(2)),
+            load_low,
+            jump(
+                if op == "SCMP_CMP_LT" {
+                    BPF_JGE_K
+                } else {
+                    BPF_JGT_K
+                },
+                value_low,
+                NextRule,
+                Skip(0),
+            ),
+        ],
+        other => {
+            return Err(format!(
+                "unsupported seccomp comparison {}",
+                other.unwrap_or_default()
+            )
+            .into())
+        }
+    };
+    Ok(check)
+}
+
+/// Resolves the jumps to the next rule into offsets past the end of the
+/// block.
+fn resolve(block: Vec<Pending>) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let length = block.len();
+    let offset = |index: usize, target: Target| match target {
+        Skip(skip) => Ok(skip),
+        NextRule => u8::try_from(length - index - 1)
+            .map_err(|_| "seccomp rule with too many argument conditions"),
+    };
+    block
+        .iter()
+        .enumerate()
+        .map(|(index, pending)| {
+            Ok(Instruction {
+                code: pending.code,
+                jt: offset(index, pending.jt)?,
+                jf: offset(index, pending.jf)?,
+                k: pending.k,
+            })
+        })
+        .collect()
+}
+
+/// Installs the filter in the command process just before it executes the
+/// program, after setting `no_new_privs` as required for unprivileged users.
+#[cfg(target_os = "linux")]
+pub fn apply(
+    command: &mut std::process::Command,
+    program: Vec<Instruction>,
+) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::process::CommandExt;
+
+    let mut filters: Vec<libc::sock_filter> = program
+        .iter()
+        .map(|instruction| libc::sock_filter {
+            code: instruction.code,
+            jt: instruction.jt,
+            jf: instruction.jf,
+            k: instruction.k,
+        })
+        .collect();
+    let len = u16::try_from(filters.len()).map_err(|_| "The seccomp profile is too large")?;
+    // SAFETY: the closure only calls prctl, which is async-signal-safe, with
+    // a program allocated beforehand and outliving the call.
+    unsafe {
+        command.pre_exec(move || {
+            let program = libc::sock_fprog {
+                len,
+                filter: filters.as_mut_ptr(),
+            };
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(
+    _command: &mut std::process::Command,
+    _program: Vec<Instruction>,
+) -> Result<(), Box<dyn Error>> {
+    Err("seccomp profiles are only supported on Linux".into())
+}
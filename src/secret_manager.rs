@@ -1,15 +1,89 @@
+use crate::api_calls;
+use crate::logging::{info, warn};
+use crate::redact::{SecretString, REDACTED};
 use aws_sdk_secretsmanager::error::SdkError;
+use aws_sdk_secretsmanager::operation::batch_get_secret_value::BatchGetSecretValueOutput;
+use aws_sdk_secretsmanager::operation::create_secret::{CreateSecretError, CreateSecretOutput};
+use aws_sdk_secretsmanager::operation::describe_secret::{
+    DescribeSecretError, DescribeSecretOutput,
+};
+use aws_sdk_secretsmanager::operation::get_random_password::{
+    GetRandomPasswordError, GetRandomPasswordOutput,
+};
 use aws_sdk_secretsmanager::operation::get_secret_value::GetSecretValueError;
 use aws_sdk_secretsmanager::operation::get_secret_value::GetSecretValueOutput;
+use aws_sdk_secretsmanager::operation::put_secret_value::{
+    PutSecretValueError, PutSecretValueOutput,
+};
+use aws_sdk_secretsmanager::primitives::Blob;
+use aws_sdk_secretsmanager::types::{SecretValueEntry, Tag};
+use std::collections::HashMap;
 use std::error::Error;
-use tracing::{info, instrument};
+use std::fmt;
 
 #[async_trait::async_trait]
-pub trait SecretsManagerClientTrait {
+pub trait SecretsManagerClientTrait: Send + Sync {
     async fn get_secret_value(
         &self,
         secret_id: &str,
     ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>>;
+
+    async fn get_secret_value_version(
+        &self,
+        secret_id: &str,
+        version_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>>;
+
+    async fn get_secret_value_stage(
+        &self,
+        secret_id: &str,
+        version_stage: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>>;
+
+    async fn describe_secret(
+        &self,
+        secret_id: &str,
+    ) -> Result<DescribeSecretOutput, SdkError<DescribeSecretError>>;
+
+    async fn get_random_password(
+        &self,
+        spec: &PasswordSpec,
+    ) -> Result<GetRandomPasswordOutput, SdkError<GetRandomPasswordError>>;
+
+    async fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>>;
+
+    async fn put_secret_value(
+        &self,
+        secret_id: &str,
+        value: &str,
+    ) -> Result<PutSecretValueOutput, SdkError<PutSecretValueError>>;
+
+    async fn create_secret_copy(
+        &self,
+        copy: &SecretCopy,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>>;
+
+    /// Retrieves the current version of up to `BATCH_SIZE` secrets at once.
+    async fn batch_get_secret_value(
+        &self,
+        _secret_ids: &[String],
+    ) -> Result<BatchGetSecretValueOutput, Box<dyn Error>> {
+        Err("This client can't retrieve secrets in batches".into())
+    }
+
+    /// Returns a client retrieving secrets from another region, or with
+    /// another role assumed, for the mapping entries that override them.
+    async fn with_target(
+        &self,
+        _region: Option<&str>,
+        _role: Option<&str>,
+    ) -> Result<Box<dyn SecretsManagerClientTrait>, Box<dyn Error>> {
+        Err("This client can't retrieve secrets from another region or role".into())
+    }
 }
 
 #[async_trait::async_trait]
@@ -18,16 +92,536 @@ impl SecretsManagerClientTrait for aws_sdk_secretsmanager::Client {
         &self,
         secret_id: &str,
     ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        api_calls::record("secretsmanager", "GetSecretValue");
         self.get_secret_value().secret_id(secret_id).send().await
     }
+
+    async fn get_secret_value_version(
+        &self,
+        secret_id: &str,
+        version_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        api_calls::record("secretsmanager", "GetSecretValue");
+        self.get_secret_value()
+            .secret_id(secret_id)
+            .version_id(version_id)
+            .send()
+            .await
+    }
+
+    async fn get_secret_value_stage(
+        &self,
+        secret_id: &str,
+        version_stage: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        api_calls::record("secretsmanager", "GetSecretValue");
+        self.get_secret_value()
+            .secret_id(secret_id)
+            .version_stage(version_stage)
+            .send()
+            .await
+    }
+
+    async fn describe_secret(
+        &self,
+        secret_id: &str,
+    ) -> Result<DescribeSecretOutput, SdkError<DescribeSecretError>> {
+        api_calls::record("secretsmanager", "DescribeSecret");
+        self.describe_secret().secret_id(secret_id).send().await
+    }
+
+    async fn get_random_password(
+        &self,
+        spec: &PasswordSpec,
+    ) -> Result<GetRandomPasswordOutput, SdkError<GetRandomPasswordError>> {
+        api_calls::record("secretsmanager", "GetRandomPassword");
+        self.get_random_password()
+            .set_password_length(spec.length)
+            .exclude_punctuation(!spec.symbols)
+            .exclude_numbers(!spec.numbers)
+            .exclude_uppercase(!spec.uppercase)
+            .exclude_lowercase(!spec.lowercase)
+            .include_space(spec.spaces)
+            .set_exclude_characters(spec.exclude_characters.clone())
+            .require_each_included_type(spec.require_each_type)
+            .send()
+            .await
+    }
+
+    async fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        api_calls::record("secretsmanager", "CreateSecret");
+        self.create_secret()
+            .name(name)
+            .secret_string(value)
+            .send()
+            .await
+    }
+
+    async fn put_secret_value(
+        &self,
+        secret_id: &str,
+        value: &str,
+    ) -> Result<PutSecretValueOutput, SdkError<PutSecretValueError>> {
+        api_calls::record("secretsmanager", "PutSecretValue");
+        self.put_secret_value()
+            .secret_id(secret_id)
+            .secret_string(value)
+            .send()
+            .await
+    }
+
+    async fn create_secret_copy(
+        &self,
+        copy: &SecretCopy,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        api_calls::record("secretsmanager", "CreateSecret");
+        let tags: Vec<Tag> = copy
+            .tags
+            .iter()
+            .map(|(key, value)| Tag::builder().key(key).value(value).build())
+            .collect();
+        self.create_secret()
+            .name(&copy.name)
+            .set_secret_string(copy.value.as_ref().map(|value| value.expose().to_string()))
+            .set_secret_binary(copy.binary.clone().map(Blob::new))
+            .set_description(copy.description.clone())
+            .set_kms_key_id(copy.kms_key_id.clone())
+            .set_tags((!tags.is_empty()).then_some(tags))
+            .send()
+            .await
+    }
+
+    async fn batch_get_secret_value(
+        &self,
+        secret_ids: &[String],
+    ) -> Result<BatchGetSecretValueOutput, Box<dyn Error>> {
+        api_calls::record("secretsmanager", "BatchGetSecretValue");
+        Ok(self
+            .batch_get_secret_value()
+            .set_secret_id_list(Some(secret_ids.to_vec()))
+            .send()
+            .await?)
+    }
+}
+
+/// Staging label of the version being created by an in-progress rotation.
+pub const AWSPENDING: &str = "AWSPENDING";
+
+/// Staging label of the version that was current before the last rotation.
+pub const AWSPREVIOUS: &str = "AWSPREVIOUS";
+
+/// Which version of a secret to retrieve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionSelector<'a> {
+    Current,
+    Id(&'a str),
+    Stage(&'a str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecretValue {
+    pub value: SecretString,
+    pub version_id: Option<String>,
+    /// ARN of the secret a generated value was written back to.
+    pub stored_arn: Option<String>,
+    /// The value is the base64 encoding of a binary secret to deliver in a
+    /// file, written when the command is launched.
+    pub binary: bool,
+}
+
+/// A secret to create as a copy of another one, possibly in another region
+/// or account. Exactly one of `value` and `binary` is set.
+#[derive(Clone, Default, PartialEq)]
+pub struct SecretCopy {
+    pub name: String,
+    pub value: Option<SecretString>,
+    pub binary: Option<Vec<u8>>,
+    pub description: Option<String>,
+    pub tags: Vec<(String, String)>,
+    /// KMS key encrypting the copy, instead of the default key of the
+    /// account, as keys don't cross regions.
+    pub kms_key_id: Option<String>,
+}
+
+// The binary value is redacted, as the string value is by SecretString.
+impl fmt::Debug for SecretCopy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretCopy")
+            .field("name", &self.name)
+            .field("value", &self.value)
+            .field("binary", &self.binary.as_ref().map(|_| REDACTED))
+            .field("description", &self.description)
+            .field("tags", &self.tags)
+            .field("kms_key_id", &self.kms_key_id)
+            .finish()
+    }
+}
+
+/// Parameters of a generated password, given as `length=32;symbols=false`.
+/// Options that aren't given keep the Secrets Manager defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordSpec {
+    /// Name of the secret to create or update with the generated password.
+    pub store: Option<String>,
+    pub length: Option<i64>,
+    pub symbols: bool,
+    pub numbers: bool,
+    pub uppercase: bool,
+    pub lowercase: bool,
+    pub spaces: bool,
+    pub exclude_characters: Option<String>,
+    pub require_each_type: bool,
+}
+
+impl Default for PasswordSpec {
+    fn default() -> Self {
+        PasswordSpec {
+            store: None,
+            length: None,
+            symbols: true,
+            numbers: true,
+            uppercase: true,
+            lowercase: true,
+            spaces: false,
+            exclude_characters: None,
+            require_each_type: true,
+        }
+    }
+}
+
+impl std::str::FromStr for PasswordSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut parsed = PasswordSpec::default();
+        for option in spec.split(';').filter(|option| !option.is_empty()) {
+            let (name, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("invalid password option {}", option))?;
+            let flag = || {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| format!("{} must be true or false", name))
+            };
+            match name {
+                "length" => {
+                    parsed.length = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid password length {}", value))?,
+                    )
+                }
+                "symbols" => parsed.symbols = flag()?,
+                "numbers" => parsed.numbers = flag()?,
+                "uppercase" => parsed.uppercase = flag()?,
+                "lowercase" => parsed.lowercase = flag()?,
+                "spaces" => parsed.spaces = flag()?,
+                "exclude" => parsed.exclude_characters = Some(value.to_string()),
+                "require_each_type" => parsed.require_each_type = flag()?,
+                "store" if !value.is_empty() => parsed.store = Some(value.to_string()),
+                other => return Err(format!("unknown password option {}", other)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Generates a password with GetRandomPassword.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client)))]
+pub async fn generate_password<T: SecretsManagerClientTrait + ?Sized>(
+    client: &T,
+    spec: &PasswordSpec,
+) -> Result<String, Box<dyn Error>> {
+    info!("Generating a random password");
+    let response = client.get_random_password(spec).await?;
+    Ok(response
+        .random_password()
+        .ok_or("GetRandomPassword returned no password")?
+        .to_string())
 }
 
-#[instrument(skip(client))]
+/// Writes a generated value to the named secret, creating it if it doesn't
+/// exist yet, and returns its ARN and the id of the new version.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client, value)))]
+pub async fn store_secret<T: SecretsManagerClientTrait + ?Sized>(
+    client: &T,
+    name: &str,
+    value: &str,
+) -> Result<(String, Option<String>), Box<dyn Error>> {
+    info!("Storing the generated value in Secrets Manager: {}", name);
+    let (arn, version_id) = match client.create_secret(name, value).await {
+        Ok(response) => (
+            response.arn().map(str::to_string),
+            response.version_id().map(str::to_string),
+        ),
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(CreateSecretError::is_resource_exists_exception) =>
+        {
+            let response = client.put_secret_value(name, value).await?;
+            (
+                response.arn().map(str::to_string),
+                response.version_id().map(str::to_string),
+            )
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let arn = arn.ok_or_else(|| format!("No ARN was returned for {}", name))?;
+    Ok((arn, version_id))
+}
+
+/// How a `SecretBinary` that isn't valid UTF-8 is delivered, since it can't
+/// be passed through as text without being mangled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BinaryPolicy {
+    /// Fail the resolution.
+    #[default]
+    Fail,
+    /// Deliver the binary encoded as base64.
+    Base64,
+    /// Write the binary to a private file and deliver its path.
+    File,
+}
+
+impl std::str::FromStr for BinaryPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "fail" => Ok(BinaryPolicy::Fail),
+            "base64" => Ok(BinaryPolicy::Base64),
+            "file" => Ok(BinaryPolicy::File),
+            other => Err(format!("unknown binary policy {}", other)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client)))]
 pub async fn get_secret<T: SecretsManagerClientTrait + ?Sized>(
     client: &T,
     arn: &str,
-) -> Result<String, Box<dyn Error>> {
+    version: VersionSelector<'_>,
+    binary: BinaryPolicy,
+) -> Result<SecretValue, Box<dyn Error>> {
     info!("Retrieving secret from Secrets Manager: {}", arn);
-    let response = client.get_secret_value(arn).await?;
-    Ok(response.secret_string().unwrap_or_default().to_string())
+    let response = match version {
+        VersionSelector::Current => client.get_secret_value(arn).await?,
+        VersionSelector::Id(version_id) => client.get_secret_value_version(arn, version_id).await?,
+        VersionSelector::Stage(stage) => client.get_secret_value_stage(arn, stage).await?,
+    };
+
+    let file = binary == BinaryPolicy::File;
+    let (value, binary) = match (response.secret_string(), response.secret_binary()) {
+        (Some(value), _) => (value.to_string(), false),
+        (None, Some(blob)) => match (std::str::from_utf8(blob.as_ref()), binary) {
+            (Ok(value), _) => (value.to_string(), false),
+            (Err(_), BinaryPolicy::Base64 | BinaryPolicy::File) => {
+                (aws_smithy_types::base64::encode(blob.as_ref()), file)
+            }
+            (Err(_), BinaryPolicy::Fail) => {
+                return Err(format!(
+                    "The SecretBinary of {} is not valid UTF-8, use --binary base64 or --binary file to deliver it",
+                    arn
+                )
+                .into())
+            }
+        },
+        (None, None) => (String::new(), false),
+    };
+    Ok(SecretValue {
+        value: value.into(),
+        version_id: response.version_id().map(str::to_string),
+        stored_arn: None,
+        binary,
+    })
+}
+
+/// Secrets retrieved with one BatchGetSecretValue call at most.
+pub const BATCH_SIZE: usize = 20;
+
+/// Client answering GetSecretValue for the current version of the secrets
+/// prefetched with BatchGetSecretValue, and forwarding every other call to
+/// the wrapped client, including for the secrets the batches failed to
+/// retrieve.
+pub struct BatchedClient<'a, T: ?Sized> {
+    client: &'a T,
+    secrets: HashMap<String, GetSecretValueOutput>,
+}
+
+impl<'a, T: SecretsManagerClientTrait + ?Sized> BatchedClient<'a, T> {
+    /// A client without prefetched secrets, forwarding every call.
+    pub fn new(client: &'a T) -> Self {
+        BatchedClient {
+            client,
+            secrets: HashMap::new(),
+        }
+    }
+
+    /// Retrieves the secrets in batches of `BATCH_SIZE`. The secrets that
+    /// can't be retrieved this way, because the client or the permissions
+    /// don't allow batches or because of an error for that secret, are left
+    /// to be retrieved one by one.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(client)))]
+    pub async fn prefetch(client: &'a T, secret_ids: &[String]) -> Self {
+        let mut batched = BatchedClient::new(client);
+        for batch in secret_ids.chunks(BATCH_SIZE) {
+            info!("Retrieving {} secrets from Secrets Manager", batch.len());
+            let response = match client.batch_get_secret_value(batch).await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!(
+                        "Failed to retrieve the secrets in batches, retrieving them one by one: {}",
+                        e
+                    );
+                    break;
+                }
+            };
+            for error in response.errors() {
+                warn!(
+                    "Failed to retrieve {} in a batch, retrying it alone: {}",
+                    error.secret_id().unwrap_or_default(),
+                    error.message().unwrap_or_default()
+                );
+            }
+            for entry in response.secret_values() {
+                for secret_id in batch.iter().filter(|id| entry_matches(entry, id)) {
+                    batched
+                        .secrets
+                        .insert(secret_id.clone(), entry_output(entry));
+                }
+            }
+        }
+        batched
+    }
+}
+
+/// Whether the batch entry is the secret requested by name, ARN or partial
+/// ARN, without the random suffix Secrets Manager appends to the names.
+fn entry_matches(entry: &SecretValueEntry, secret_id: &str) -> bool {
+    let arn = entry.arn().unwrap_or_default();
+    secret_id == arn
+        || Some(secret_id) == entry.name()
+        || arn
+            .strip_prefix(secret_id)
+            .is_some_and(|suffix| suffix.len() == 7 && suffix.starts_with('-'))
+}
+
+fn entry_output(entry: &SecretValueEntry) -> GetSecretValueOutput {
+    GetSecretValueOutput::builder()
+        .set_arn(entry.arn.clone())
+        .set_name(entry.name.clone())
+        .set_version_id(entry.version_id.clone())
+        .set_secret_binary(entry.secret_binary.clone())
+        .set_secret_string(entry.secret_string.clone())
+        .set_version_stages(entry.version_stages.clone())
+        .set_created_date(entry.created_date)
+        .build()
+}
+
+#[async_trait::async_trait]
+impl<T: SecretsManagerClientTrait + ?Sized> SecretsManagerClientTrait for BatchedClient<'_, T> {
+    async fn get_secret_value(
+        &self,
+        secret_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        match self.secrets.get(secret_id) {
+            Some(output) => Ok(output.clone()),
+            None => self.client.get_secret_value(secret_id).await,
+        }
+    }
+
+    async fn get_secret_value_version(
+        &self,
+        secret_id: &str,
+        version_id: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        self.client
+            .get_secret_value_version(secret_id, version_id)
+            .await
+    }
+
+    async fn get_secret_value_stage(
+        &self,
+        secret_id: &str,
+        version_stage: &str,
+    ) -> Result<GetSecretValueOutput, SdkError<GetSecretValueError>> {
+        self.client
+            .get_secret_value_stage(secret_id, version_stage)
+            .await
+    }
+
+    async fn describe_secret(
+        &self,
+        secret_id: &str,
+    ) -> Result<DescribeSecretOutput, SdkError<DescribeSecretError>> {
+        self.client.describe_secret(secret_id).await
+    }
+
+    async fn get_random_password(
+        &self,
+        spec: &PasswordSpec,
+    ) -> Result<GetRandomPasswordOutput, SdkError<GetRandomPasswordError>> {
+        self.client.get_random_password(spec).await
+    }
+
+    async fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        self.client.create_secret(name, value).await
+    }
+
+    async fn put_secret_value(
+        &self,
+        secret_id: &str,
+        value: &str,
+    ) -> Result<PutSecretValueOutput, SdkError<PutSecretValueError>> {
+        self.client.put_secret_value(secret_id, value).await
+    }
+
+    async fn create_secret_copy(
+        &self,
+        copy: &SecretCopy,
+    ) -> Result<CreateSecretOutput, SdkError<CreateSecretError>> {
+        self.client.create_secret_copy(copy).await
+    }
+
+    async fn batch_get_secret_value(
+        &self,
+        secret_ids: &[String],
+    ) -> Result<BatchGetSecretValueOutput, Box<dyn Error>> {
+        self.client.batch_get_secret_value(secret_ids).await
+    }
+
+    async fn with_target(
+        &self,
+        region: Option<&str>,
+        role: Option<&str>,
+    ) -> Result<Box<dyn SecretsManagerClientTrait>, Box<dyn Error>> {
+        self.client.with_target(region, role).await
+    }
+}
+
+/// Returns true when a rotation of the secret is in progress, meaning that a
+/// version is labeled AWSPENDING without being AWSCURRENT yet.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client)))]
+pub async fn rotation_in_progress<T: SecretsManagerClientTrait + ?Sized>(
+    client: &T,
+    arn: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let response = client.describe_secret(arn).await?;
+    Ok(response
+        .version_ids_to_stages()
+        .map(|versions| {
+            versions.values().any(|stages| {
+                stages.iter().any(|stage| stage == AWSPENDING)
+                    && !stages.iter().any(|stage| stage == "AWSCURRENT")
+            })
+        })
+        .unwrap_or(false))
 }
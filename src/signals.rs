@@ -0,0 +1,312 @@
+use crate::errors::Cancelled;
+use crate::files;
+use crate::logging::warn;
+use std::error::Error;
+use std::future::Future;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Process ids of the running child processes the signals are forwarded to,
+/// which then decide when the tool exits, in the order they were started.
+/// Watch mode runs two of them while replacing the command.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Whether SIGUSR2 asks watch mode for a dump of its state, instead of
+/// being forwarded to the command.
+static DUMPING_STATE: AtomicBool = AtomicBool::new(false);
+
+/// Signals forwarded to the command run as a child process, so that stop
+/// requests such as the SIGTERM of `docker stop` reach it. The signals that
+/// can't be caught, those reporting faults of the tool itself and the job
+/// control ones aren't, nor is SIGUSR2 in watch mode, which dumps its state.
+#[cfg(unix)]
+const FORWARDED_SIGNALS: &[libc::c_int] = &[
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGWINCH,
+    libc::SIGALRM,
+];
+
+/// Waits for SIGINT or SIGTERM, returning the name of the signal received.
+#[cfg(unix)]
+pub async fn termination_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        _ => return std::future::pending().await,
+    };
+    tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn termination_signal() -> &'static str {
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => "SIGINT",
+        Err(_) => std::future::pending().await,
+    }
+}
+
+/// The SIGUSR2 signals received by the tool, asking watch mode for a dump of
+/// its state. They aren't forwarded to the command while listened to.
+pub struct StateDumpRequests {
+    #[cfg(unix)]
+    stream: Option<tokio::signal::unix::Signal>,
+}
+
+impl StateDumpRequests {
+    #[cfg(unix)]
+    pub fn listen() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let stream = signal(SignalKind::user_defined2())
+            .map_err(|e| warn!("Failed to listen for SIGUSR2: {}", e))
+            .ok();
+        DUMPING_STATE.store(stream.is_some(), Ordering::SeqCst);
+        StateDumpRequests { stream }
+    }
+
+    #[cfg(not(unix))]
+    pub fn listen() -> Self {
+        StateDumpRequests {}
+    }
+
+    /// Waits for the next request.
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(stream) = &mut self.stream {
+            if stream.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+impl Drop for StateDumpRequests {
+    fn drop(&mut self) {
+        DUMPING_STATE.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Runs the future until it completes or a termination signal is received.
+/// On a signal the future is dropped, cancelling its outstanding AWS calls,
+/// and a `Cancelled` error is returned.
+pub async fn cancellable<T, F>(future: F) -> Result<T, Box<dyn Error>>
+where
+    F: Future<Output = Result<T, Box<dyn Error>>>,
+{
+    tokio::select! {
+        result = future => result,
+        signal = termination_signal() => {
+            warn!("Received {}, cancelling secret resolution", signal);
+            files::remove_temporary_files();
+            Err(Cancelled { signal }.into())
+        }
+    }
+}
+
+/// Exits when a termination signal is received, as the process did before
+/// handlers were installed for cancelling the resolution, unless the signal
+/// is forwarded to a child process.
+pub fn exit_on_signal() {
+    tokio::spawn(async {
+        loop {
+            let signal = termination_signal().await;
+            if children().is_empty() {
+                files::remove_temporary_files();
+                std::process::exit(Cancelled { signal }.exit_code());
+            }
+        }
+    });
+}
+
+/// Waits for the child process to exit, forwarding the signals received by
+/// the tool to it in the meantime. When reaping, the other child processes
+/// of the tool, such as the orphans it adopted, are waited for as they exit.
+pub fn wait_forwarding_signals(child: &mut Child, reap: bool) -> std::io::Result<ExitStatus> {
+    children().push(child.id());
+    let forwarders = match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => forward_signals(&runtime, child.id()),
+        Err(_) => Vec::new(),
+    };
+    // The worker thread is handed over, so that the forwarders keep running
+    // on runtimes with a single worker.
+    let mut wait = || match reap {
+        true => wait_reaping(child),
+        false => child.wait(),
+    };
+    let status = match tokio::runtime::Handle::try_current() {
+        Ok(_) => tokio::task::block_in_place(wait),
+        Err(_) => wait(),
+    };
+    for forwarder in forwarders {
+        forwarder.abort();
+    }
+    children().retain(|&pid| pid != child.id());
+    status
+}
+
+fn children() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    CHILDREN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Process id of the child process started last among the running ones.
+pub fn child_pid() -> Option<u32> {
+    children().last().copied()
+}
+
+/// Asks the child process to stop, with SIGTERM.
+#[cfg(unix)]
+pub fn stop_process(pid: u32) {
+    // SAFETY: kill has no memory safety requirements.
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+}
+
+/// Sends the signal to the child process started last, returning false
+/// when there is no such process.
+#[cfg(unix)]
+pub fn signal_child(signum: i32) -> bool {
+    let Some(pid) = child_pid() else {
+        return false;
+    };
+    // SAFETY: kill has no memory safety requirements.
+    unsafe { libc::kill(pid as libc::pid_t, signum) };
+    true
+}
+
+/// The number of the signal named with or without its `SIG` prefix, such
+/// as `SIGHUP` or `USR1`, among those that can be forwarded.
+#[cfg(unix)]
+pub fn parse_signal(name: &str) -> Option<i32> {
+    let name = name.strip_prefix("SIG").unwrap_or(name);
+    let signum = match name {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "TERM" => libc::SIGTERM,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "WINCH" => libc::SIGWINCH,
+        "ALRM" => libc::SIGALRM,
+        _ => return None,
+    };
+    Some(signum)
+}
+
+/// Commands are only stopped or signalled on Unix, where watch mode is
+/// supported.
+#[cfg(not(unix))]
+pub fn stop_process(_pid: u32) {}
+
+#[cfg(not(unix))]
+pub fn signal_child(_signum: i32) -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+pub fn parse_signal(_name: &str) -> Option<i32> {
+    None
+}
+#[cfg(unix)]
+fn forward_signals(runtime: &tokio::runtime::Handle, pid: u32) -> Vec<tokio::task::JoinHandle<()>> {
+    use crate::logging::info;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let _guard = runtime.enter();
+    FORWARDED_SIGNALS
+        .iter()
+        .filter(|&&signum| signum != libc::SIGUSR2 || !DUMPING_STATE.load(Ordering::SeqCst))
+        .filter_map(|&signum| {
+            let mut stream = signal(SignalKind::from_raw(signum)).ok()?;
+            Some(runtime.spawn(async move {
+                while stream.recv().await.is_some() {
+                    info!("Forwarding signal {} to the command", signum);
+                    // SAFETY: kill has no memory safety requirements.
+                    unsafe { libc::kill(pid as libc::pid_t, signum) };
+                }
+            }))
+        })
+        .collect()
+}
+
+/// On other platforms, console interrupts reach the command directly.
+#[cfg(not(unix))]
+fn forward_signals(
+    _runtime: &tokio::runtime::Handle,
+    _pid: u32,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    Vec::new()
+}
+
+/// Waits for any child process until the given one exits, returning its
+/// status, so that no orphan adopted by the tool is left as a zombie.
+#[cfg(unix)]
+fn wait_reaping(child: &Child) -> std::io::Result<ExitStatus> {
+    use crate::logging::info;
+    use std::os::unix::process::ExitStatusExt;
+
+    loop {
+        let mut status = 0;
+        // SAFETY: status is a valid pointer for the duration of the call.
+        let reaped = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if reaped == -1 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if reaped as u32 == child.id() {
+            return Ok(ExitStatus::from_raw(status));
+        }
+        info!("Reaped orphaned process {}", reaped);
+    }
+}
+
+/// Orphans are only adopted by the tool on Unix.
+#[cfg(not(unix))]
+fn wait_reaping(child: &mut Child) -> std::io::Result<ExitStatus> {
+    child.wait()
+}
+
+/// Makes the orphaned descendants of the tool its children, as they are
+/// when it runs as PID 1, so that init mode reaps them in any case.
+#[cfg(target_os = "linux")]
+pub fn adopt_orphans() {
+    // SAFETY: prctl with PR_SET_CHILD_SUBREAPER only reads its arguments.
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } == -1 {
+        warn!(
+            "Failed to become the subreaper of the command: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn adopt_orphans() {}
+
+/// The exit code of the tool for the exit status of the command, which is
+/// 128 plus the signal number when the command was killed by a signal, as
+/// reported by shells.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
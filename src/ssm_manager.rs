@@ -1,16 +1,39 @@
+use crate::api_calls;
+use crate::logging::info;
 use aws_sdk_ssm::error::SdkError;
 use aws_sdk_ssm::operation::get_parameter::GetParameterError;
 use aws_sdk_ssm::operation::get_parameter::GetParameterOutput;
+use aws_sdk_ssm::operation::get_parameters_by_path::{
+    GetParametersByPathError, GetParametersByPathOutput,
+};
+use aws_sdk_ssm::operation::put_parameter::{PutParameterError, PutParameterOutput};
+use aws_sdk_ssm::types::ParameterType;
 use std::error::Error;
-use tracing::{info, instrument};
 
 #[async_trait::async_trait]
-pub trait SsmClientTrait {
+pub trait SsmClientTrait: Send + Sync {
     async fn get_parameter(
         &self,
         name: &str,
         with_decryption: bool,
     ) -> Result<GetParameterOutput, SdkError<GetParameterError>>;
+
+    /// Lists a page of the parameters under the path, recursively.
+    async fn get_parameters_by_path(
+        &self,
+        path: &str,
+        with_decryption: bool,
+        next_token: Option<String>,
+    ) -> Result<GetParametersByPathOutput, SdkError<GetParametersByPathError>>;
+
+    /// Writes the parameter, overwriting an existing one while keeping its
+    /// type, or creating a `String` parameter otherwise.
+    async fn put_parameter(
+        &self,
+        name: &str,
+        value: &str,
+        overwrite: bool,
+    ) -> Result<PutParameterOutput, SdkError<PutParameterError>>;
 }
 
 #[async_trait::async_trait]
@@ -20,24 +43,101 @@ impl SsmClientTrait for aws_sdk_ssm::Client {
         name: &str,
         with_decryption: bool,
     ) -> Result<GetParameterOutput, SdkError<GetParameterError>> {
+        api_calls::record("ssm", "GetParameter");
         self.get_parameter()
             .name(name)
             .with_decryption(with_decryption)
             .send()
             .await
     }
+
+    async fn get_parameters_by_path(
+        &self,
+        path: &str,
+        with_decryption: bool,
+        next_token: Option<String>,
+    ) -> Result<GetParametersByPathOutput, SdkError<GetParametersByPathError>> {
+        api_calls::record("ssm", "GetParametersByPath");
+        self.get_parameters_by_path()
+            .path(path)
+            .recursive(true)
+            .with_decryption(with_decryption)
+            .set_next_token(next_token)
+            .send()
+            .await
+    }
+
+    async fn put_parameter(
+        &self,
+        name: &str,
+        value: &str,
+        overwrite: bool,
+    ) -> Result<PutParameterOutput, SdkError<PutParameterError>> {
+        api_calls::record("ssm", "PutParameter");
+        self.put_parameter()
+            .name(name)
+            .value(value)
+            .overwrite(overwrite)
+            .set_type((!overwrite).then_some(ParameterType::String))
+            .send()
+            .await
+    }
 }
 
-#[instrument(skip(client))]
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client)))]
 pub async fn get_ssm_parameter<T: SsmClientTrait + ?Sized>(
     client: &T,
     arn: &str,
+    with_decryption: bool,
 ) -> Result<String, Box<dyn Error>> {
     info!("Retrieving SSM parameter: {}", arn);
-    let response = client.get_parameter(arn, true).await?;
+    let response = client.get_parameter(arn, with_decryption).await?;
     Ok(response
         .parameter()
         .and_then(|p| p.value())
         .unwrap_or_default()
         .to_string())
 }
+
+/// Retrieves the names and values of every parameter under the path,
+/// recursively, following the pages of results.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client)))]
+pub async fn get_parameters_by_path<T: SsmClientTrait + ?Sized>(
+    client: &T,
+    path: &str,
+    with_decryption: bool,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    info!("Retrieving SSM parameters under path: {}", path);
+    let mut parameters = Vec::new();
+    let mut next_token = None;
+    loop {
+        let response = client
+            .get_parameters_by_path(path, with_decryption, next_token)
+            .await?;
+        parameters.extend(response.parameters().iter().filter_map(|parameter| {
+            Some((
+                parameter.name()?.to_string(),
+                parameter.value().unwrap_or_default().to_string(),
+            ))
+        }));
+        next_token = response.next_token().map(str::to_string);
+        if next_token.is_none() {
+            return Ok(parameters);
+        }
+    }
+}
+
+/// Returns true for the public parameters published by AWS under
+/// `/aws/service/`, such as the latest AMI ids.
+pub fn is_public_parameter(name: &str) -> bool {
+    name.starts_with("/aws/service/") || name.contains(":parameter/aws/service/")
+}
+
+/// Returns true when the error means that the parameter doesn't exist.
+pub fn is_parameter_not_found(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<SdkError<GetParameterError>>()
+        .and_then(|e| e.as_service_error())
+        .map(|e| e.is_parameter_not_found())
+        .unwrap_or(false)
+}
@@ -0,0 +1,68 @@
+//! The status file written on exit with `--status-file`, so log collectors
+//! can enrich "essential container exited" events without parsing logs.
+
+use crate::errors::{error_message, exit_code};
+use serde_json::json;
+use std::error::Error;
+use std::path::Path;
+use std::time::Instant;
+
+/// When the run started and when the command was launched, to tell which
+/// phase a failure happened in and how long each phase took.
+#[derive(Debug)]
+pub struct Timings {
+    started: Instant,
+    launched: Option<Instant>,
+}
+
+impl Timings {
+    pub fn start() -> Self {
+        Timings {
+            started: Instant::now(),
+            launched: None,
+        }
+    }
+
+    /// Records that the resolution is done and the command is launched.
+    pub fn launched(&mut self) {
+        self.launched.get_or_insert_with(Instant::now);
+    }
+}
+
+/// Describes how the run ended: the phase that failed, if any, the exit code
+/// of the tool, and the time spent resolving and running the command.
+pub fn status_json(result: &Result<i32, Box<dyn Error>>, timings: &Timings) -> serde_json::Value {
+    let ended = Instant::now();
+    let resolved = timings.launched.unwrap_or(ended);
+    let phase = if timings.launched.is_some() {
+        "child"
+    } else {
+        "resolution"
+    };
+    let (failed_phase, code, error) = match result {
+        Ok(0) => (None, 0, None),
+        Ok(code) => (Some(phase), *code, None),
+        Err(e) => (
+            Some(phase),
+            exit_code(e.as_ref()),
+            Some(error_message(e.as_ref())),
+        ),
+    };
+    json!({
+        "failed_phase": failed_phase,
+        "exit_code": code,
+        "error": error,
+        "resolution_seconds": (resolved - timings.started).as_secs_f64(),
+        "child_seconds": timings.launched.map(|launched| (ended - launched).as_secs_f64()),
+    })
+}
+
+/// Writes the status of the run to the file.
+pub fn write(
+    path: &Path,
+    result: &Result<i32, Box<dyn Error>>,
+    timings: &Timings,
+) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, format!("{}\n", status_json(result, timings)))
+        .map_err(|e| format!("Failed to write the status file {}: {}", path.display(), e).into())
+}
@@ -0,0 +1,105 @@
+use crate::environment_processor::ResolvedSecret;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const MASK: &str = "********";
+
+const HEADERS: [&str; 5] = ["KEY", "SOURCE", "TYPE", "REFERENCE", "STATUS"];
+
+/// Renders a table of the resolved secrets for the startup logs. Only keys,
+/// sources and references are shown, values never are.
+pub fn summary_table(secrets: &[ResolvedSecret]) -> String {
+    let rows: Vec<[String; 5]> = secrets
+        .iter()
+        .map(|secret| {
+            [
+                secret.key.clone(),
+                secret.source.to_string(),
+                secret.provider.to_string(),
+                secret.reference.clone(),
+                secret.status.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    let header = HEADERS.map(String::from);
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Renders the environment the child would receive, sorted by key, with the
+/// inherited values shown as-is and the resolved secrets masked.
+pub fn masked_environment(inherited: &[(String, String)], secrets: &[ResolvedSecret]) -> String {
+    let mut lines: BTreeMap<&str, String> = inherited
+        .iter()
+        .map(|(key, value)| (key.as_str(), format!("{}={}", key, value)))
+        .collect();
+
+    for secret in secrets {
+        let overrides = if lines.contains_key(secret.key.as_str()) {
+            ", overrides inherited value"
+        } else {
+            ""
+        };
+        lines.insert(
+            &secret.key,
+            format!(
+                "{}={} (from {} {}{})",
+                secret.key, MASK, secret.source, secret.reference, overrides
+            ),
+        );
+    }
+
+    lines.into_values().map(|line| line + "\n").collect()
+}
+
+/// Renders the state of watch mode dumped on SIGUSR2: when the secrets were
+/// last refreshed and will be next, how many refreshes failed since, and the
+/// source, reference and version of each of them, with the values masked.
+pub fn state_dump(
+    secrets: &[ResolvedSecret],
+    since_refresh: Duration,
+    until_refresh: Duration,
+    failed_refreshes: u32,
+) -> String {
+    let seconds = |duration: Duration| Duration::from_secs(duration.as_secs());
+    let mut dump = format!(
+        "Watch mode state: {} secrets, last refreshed {:?} ago, {} failed refreshes since, next refresh in {:?}\n",
+        secrets.len(),
+        seconds(since_refresh),
+        failed_refreshes,
+        seconds(until_refresh)
+    );
+    for secret in secrets {
+        let refresh = match secret.refresh {
+            Some(refresh) => format!(", refreshed every {:?}", refresh),
+            None => String::new(),
+        };
+        dump.push_str(&format!(
+            "{}={} (from {} {}, version {}{})\n",
+            secret.key,
+            MASK,
+            secret.source,
+            secret.reference,
+            secret.version_id.as_deref().unwrap_or("unknown"),
+            refresh
+        ));
+    }
+    dump
+}
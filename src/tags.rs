@@ -0,0 +1,86 @@
+//! Tags of the ECS task or EC2 instance the tool runs on, used to select the
+//! mapping of the environment, so one image or AMI resolves the right
+//! secrets in every environment.
+
+use crate::kv_store::send;
+use crate::logging::info;
+use hyper::{Body, Method, Request};
+use serde_json::Value;
+use std::error::Error;
+
+const IMDS_DEFAULT_ENDPOINT: &str = "http://169.254.169.254";
+const IMDS_TOKEN_TTL_SECONDS: &str = "300";
+
+/// Reads the value of a tag of the ECS task, when running in ECS, or of the
+/// EC2 instance otherwise. Instance tags must be allowed in the instance
+/// metadata.
+pub async fn tag_value(key: &str) -> Result<String, Box<dyn Error>> {
+    let value = match std::env::var("ECS_CONTAINER_METADATA_URI_V4") {
+        Ok(uri) => ecs_tag_value(&uri, key).await?,
+        Err(_) => instance_tag_value(key).await?,
+    };
+    let value = value.ok_or_else(|| format!("Tag {} was not found", key))?;
+    info!("Selecting the environment from tag {}={}", key, value);
+    Ok(value)
+}
+
+/// Reads a tag of the task, or of its container instance on the EC2 launch
+/// type, from the task metadata endpoint.
+async fn ecs_tag_value(uri: &str, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let request =
+        Request::get(format!("{}/taskWithTags", uri.trim_end_matches('/'))).body(Body::empty())?;
+    let Some(body) = send(request).await? else {
+        return Ok(None);
+    };
+    let metadata: Value = serde_json::from_slice(&body)?;
+    Ok(["TaskTags", "ContainerInstanceTags"]
+        .iter()
+        .find_map(|tags| metadata[tags][key].as_str())
+        .map(str::to_string))
+}
+
+/// Reads an instance tag from the instance metadata service, with IMDSv2.
+async fn instance_tag_value(key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let endpoint = std::env::var("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+        .unwrap_or_else(|_| IMDS_DEFAULT_ENDPOINT.to_string());
+    let endpoint = endpoint.trim_end_matches('/');
+    let request = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("{}/latest/api/token", endpoint))
+        .header(
+            "X-aws-ec2-metadata-token-ttl-seconds",
+            IMDS_TOKEN_TTL_SECONDS,
+        )
+        .body(Body::empty())?;
+    let token = send(request)
+        .await?
+        .ok_or("The instance metadata service returned no token")?;
+
+    let request = Request::get(format!(
+        "{}/latest/meta-data/tags/instance/{}",
+        endpoint, key
+    ))
+    .header("X-aws-ec2-metadata-token", token)
+    .body(Body::empty())?;
+    let Some(value) = send(request).await? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        String::from_utf8(value).map_err(|_| "The tag value is not valid UTF-8")?,
+    ))
+}
+
+/// The suffix of the variables giving the mapping parameter of an
+/// environment, such as `STAGING` for `staging`.
+pub fn variable_suffix(environment: &str) -> String {
+    environment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,111 @@
+/// Renders a template such as `postgres://{DB_USER}:{DB_PASSWORD}@{DB_HOST}`,
+/// replacing each `{KEY}` placeholder with the value returned by `lookup`.
+/// Literal braces are written as `{{` and `}}`.
+pub fn render<'a, F>(template: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                        _ => return Err(format!("invalid placeholder in template {}", template)),
+                    }
+                }
+                let value =
+                    lookup(&name).ok_or_else(|| format!("no resolved value for {{{}}}", name))?;
+                rendered.push_str(value);
+            }
+            '}' => return Err(format!("unmatched }} in template {}", template)),
+            c => rendered.push(c),
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Replaces the `{{SECRET:KEY}}` tokens of a command line argument with the
+/// values returned by `lookup`, returning the keys that were substituted.
+pub fn substitute_secret_tokens<'a, F>(
+    argument: &str,
+    lookup: F,
+) -> Result<(String, Vec<String>), String>
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    const OPEN: &str = "{{SECRET:";
+    const CLOSE: &str = "}}";
+
+    let mut substituted = String::with_capacity(argument.len());
+    let mut keys = Vec::new();
+    let mut rest = argument;
+
+    while let Some(start) = rest.find(OPEN) {
+        substituted.push_str(&rest[..start]);
+        let token = &rest[start + OPEN.len()..];
+        let end = token
+            .find(CLOSE)
+            .ok_or_else(|| format!("unterminated {}...{} token", OPEN, CLOSE))?;
+        let key = &token[..end];
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid secret token {}{}{}", OPEN, key, CLOSE));
+        }
+        let value = lookup(key).ok_or_else(|| format!("no resolved value for {}", key))?;
+        substituted.push_str(value);
+        keys.push(key.to_string());
+        rest = &token[end + CLOSE.len()..];
+    }
+    substituted.push_str(rest);
+
+    Ok((substituted, keys))
+}
+
+/// Renders a configuration file template, replacing each `{{KEY}}`
+/// placeholder with the value returned by `lookup`. Other uses of braces,
+/// such as `{{ .Values.key }}` or JSON objects, are left as they are, so
+/// templates of other tools can be rendered too.
+pub fn render_config<'a, F>(template: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let token = &rest[start + 2..];
+        let key = token.find("}}").map(|end| &token[..end]).filter(|key| {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        match key {
+            Some(key) => {
+                let value =
+                    lookup(key).ok_or_else(|| format!("no resolved value for {{{{{}}}}}", key))?;
+                rendered.push_str(value);
+                rest = &token[key.len() + 2..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
@@ -0,0 +1,294 @@
+use crate::logging::{info, warn};
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_credential_types::Credentials;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Region and account of the ARNs made up for secrets named without one.
+const REGION: &str = "us-east-1";
+const ACCOUNT: &str = "000000000000";
+/// Version id of every fixture secret.
+const VERSION_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Secrets and parameters served by the fake Secrets Manager and SSM server,
+/// loaded from a JSON file such as:
+///
+/// ```json
+/// {
+///   "secrets": {"myapp/db": {"username": "app", "password": "hunter2"}},
+///   "parameters": {"/myapp/api-key": "api-key"}
+/// }
+/// ```
+///
+/// Secrets are keyed by name or ARN, and values that aren't strings are
+/// served as their JSON text.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Fixtures {
+    pub secrets: Map<String, Value>,
+    pub parameters: Map<String, Value>,
+}
+
+impl Fixtures {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let document = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&document)
+            .map_err(|e| format!("Invalid fixtures {}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(document: &str) -> Result<Self, Box<dyn Error>> {
+        let mut fixtures = Fixtures::default();
+        let Value::Object(document) = serde_json::from_str(document)? else {
+            return Err("the fixtures must be a JSON object".into());
+        };
+        for (section, entries) in document {
+            let Value::Object(entries) = entries else {
+                return Err(format!("{} must be an object", section).into());
+            };
+            match section.as_str() {
+                "secrets" => fixtures.secrets = entries,
+                "parameters" => fixtures.parameters = entries,
+                other => return Err(format!("unknown section {}", other).into()),
+            }
+        }
+        Ok(fixtures)
+    }
+
+    /// Answers an AWS JSON protocol request for the operation named by the
+    /// `X-Amz-Target` header, returning the HTTP status and the body.
+    pub fn handle(&self, target: &str, request: &[u8]) -> (u16, Value) {
+        let request: Value = match serde_json::from_slice(request) {
+            Ok(request) => request,
+            Err(e) => return error_response("SerializationException", &e.to_string()),
+        };
+        let field = |name: &str| request.get(name).and_then(Value::as_str);
+
+        match target {
+            "secretsmanager.GetSecretValue" => {
+                let secret_id = field("SecretId").unwrap_or_default();
+                let stage = field("VersionStage").unwrap_or("AWSCURRENT");
+                let version = field("VersionId").unwrap_or(VERSION_ID);
+                match self.secret(secret_id) {
+                    Some((arn, name, value)) if stage == "AWSCURRENT" && version == VERSION_ID => (
+                        200,
+                        json!({
+                            "ARN": arn,
+                            "Name": name,
+                            "SecretString": value,
+                            "VersionId": VERSION_ID,
+                            "VersionStages": ["AWSCURRENT"],
+                        }),
+                    ),
+                    _ => secret_not_found(secret_id),
+                }
+            }
+            "secretsmanager.BatchGetSecretValue" => {
+                let mut values = Vec::new();
+                let mut errors = Vec::new();
+                let secret_ids = request.get("SecretIdList").and_then(Value::as_array);
+                for secret_id in secret_ids.into_iter().flatten().filter_map(Value::as_str) {
+                    match self.secret(secret_id) {
+                        Some((arn, name, value)) => values.push(json!({
+                            "ARN": arn,
+                            "Name": name,
+                            "SecretString": value,
+                            "VersionId": VERSION_ID,
+                            "VersionStages": ["AWSCURRENT"],
+                        })),
+                        None => errors.push(json!({
+                            "SecretId": secret_id,
+                            "ErrorCode": "ResourceNotFoundException",
+                            "Message": "Secrets Manager can't find the specified secret.",
+                        })),
+                    }
+                }
+                (200, json!({"SecretValues": values, "Errors": errors}))
+            }
+            "secretsmanager.DescribeSecret" => {
+                let secret_id = field("SecretId").unwrap_or_default();
+                match self.secret(secret_id) {
+                    Some((arn, name, _)) => (
+                        200,
+                        json!({
+                            "ARN": arn,
+                            "Name": name,
+                            "VersionIdsToStages": {VERSION_ID: ["AWSCURRENT"]},
+                        }),
+                    ),
+                    None => secret_not_found(secret_id),
+                }
+            }
+            "AmazonSSM.GetParameter" => {
+                let name = field("Name").unwrap_or_default();
+                match self.parameters.get(name) {
+                    Some(value) => (
+                        200,
+                        json!({
+                            "Parameter": {
+                                "Name": name,
+                                "Type": "String",
+                                "Value": fixture_text(value),
+                                "Version": 1,
+                            }
+                        }),
+                    ),
+                    None => error_response(
+                        "ParameterNotFound",
+                        &format!("Parameter {} not found.", name),
+                    ),
+                }
+            }
+            other => error_response(
+                "UnknownOperationException",
+                &format!("The test harness doesn't support {}", other),
+            ),
+        }
+    }
+
+    /// Finds a secret by name or ARN, returning its ARN, name and value.
+    fn secret(&self, secret_id: &str) -> Option<(String, String, String)> {
+        let (key, value) = self.secrets.iter().find(|(key, _)| {
+            key.as_str() == secret_id || secret_name(key) == secret_name(secret_id)
+        })?;
+        let name = secret_name(key).to_string();
+        let arn = if key.starts_with("arn:") {
+            key.clone()
+        } else {
+            format!(
+                "arn:aws:secretsmanager:{}:{}:secret:{}",
+                REGION, ACCOUNT, name
+            )
+        };
+        Some((arn, name, fixture_text(value)))
+    }
+}
+
+/// The name of a secret given by name or ARN, without the random suffix
+/// Secrets Manager appends to the names in ARNs.
+fn secret_name(secret_id: &str) -> &str {
+    let Some((_, name)) = secret_id.split_once(":secret:") else {
+        return secret_id;
+    };
+    match name.rsplit_once('-') {
+        Some((name, suffix))
+            if suffix.len() == 6 && suffix.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            name
+        }
+        _ => name,
+    }
+}
+
+fn fixture_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn secret_not_found(secret_id: &str) -> (u16, Value) {
+    error_response(
+        "ResourceNotFoundException",
+        &format!(
+            "Secrets Manager can't find the specified secret: {}",
+            secret_id
+        ),
+    )
+}
+
+fn error_response(error_type: &str, message: &str) -> (u16, Value) {
+    (400, json!({"__type": error_type, "message": message}))
+}
+
+/// Starts serving the fixtures on a local port, in the background of the
+/// current runtime, and returns the endpoint URL to point the clients at.
+pub fn start(fixtures: Fixtures) -> Result<String, Box<dyn Error>> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let fixtures = Arc::new(fixtures);
+
+    info!("Serving the test harness fixtures on {}", endpoint);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let fixtures = fixtures.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_connection(stream, &fixtures).await {
+                            warn!("Test harness connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Test harness failed to accept a connection: {}", e),
+            }
+        }
+    });
+    Ok(endpoint)
+}
+
+/// Answers the HTTP/1.1 requests sent on a connection until the client
+/// closes it.
+async fn serve_connection(stream: TcpStream, fixtures: &Fixtures) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let mut target = String::new();
+        let mut length = 0;
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                match name.to_ascii_lowercase().as_str() {
+                    "content-length" => length = value.trim().parse().unwrap_or(0),
+                    "x-amz-target" => target = value.trim().to_string(),
+                    _ => {}
+                }
+            }
+        }
+        let mut request = vec![0; length];
+        stream.read_exact(&mut request).await?;
+
+        let (status, body) = fixtures.handle(&target, &request);
+        let body = body.to_string();
+        let reason = if status == 200 { "OK" } else { "Bad Request" };
+        let response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/x-amz-json-1.1\r\nContent-Length: {}\r\nx-amzn-RequestId: test-harness\r\n\r\n{}",
+            status,
+            reason,
+            body.len(),
+            body
+        );
+        stream.get_mut().write_all(response.as_bytes()).await?;
+    }
+}
+
+/// Loads an AWS configuration sending every call to the test harness, with
+/// static credentials, so no AWS account is needed.
+pub async fn load_config(endpoint: String) -> SdkConfig {
+    let loader = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(REGION))
+        .endpoint_url(endpoint)
+        .credentials_provider(Credentials::new(
+            "test-harness",
+            "test-harness",
+            None,
+            None,
+            "test-harness",
+        ));
+    crate::tls::with_http_client(loader).load().await
+}
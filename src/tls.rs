@@ -0,0 +1,19 @@
+use aws_config::ConfigLoader;
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("Either the rustls or the native-tls feature must be enabled");
+
+/// Uses the platform TLS stack (OpenSSL, Secure Transport or SChannel) for the
+/// AWS API calls instead of the built-in rustls one.
+#[cfg(feature = "native-tls")]
+pub fn with_http_client(loader: ConfigLoader) -> ConfigLoader {
+    use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
+
+    loader.http_client(HyperClientBuilder::new().build(hyper_tls::HttpsConnector::new()))
+}
+
+/// Keeps the default HTTP client of the SDK, which uses rustls.
+#[cfg(not(feature = "native-tls"))]
+pub fn with_http_client(loader: ConfigLoader) -> ConfigLoader {
+    loader
+}
@@ -0,0 +1,332 @@
+use crate::cli::RunArgs;
+use crate::commands::{launch, write_files};
+use crate::environment_processor::{process_environment, ResolvedSecret};
+use crate::errors::{self, error_message};
+use crate::logging::{info, warn};
+use crate::secret_manager::SecretsManagerClientTrait;
+use crate::signals;
+use crate::ssm_manager::SsmClientTrait;
+use crate::summary;
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Interval between the resolutions of watch mode, unless one is given.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Largest fraction of the interval added to or removed from each wait, so
+/// that the tools started by the same deployment don't all call AWS at the
+/// same time.
+const REFRESH_JITTER: f64 = 0.1;
+
+/// Shortest wait between two resolutions, whatever the jitter.
+const MIN_REFRESH_DELAY: Duration = Duration::from_secs(1);
+
+/// How often stopping the command is retried while it's still starting.
+const STOP_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the replacement command has to pass its readiness check, unless
+/// a timeout is given.
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the readiness check of the replacement command is run.
+const READY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What watch mode does when the values changed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OnChange {
+    /// Stop the command and run it again with the new values.
+    #[default]
+    Restart,
+    /// Rewrite the files of the command with the new values and send it
+    /// this signal, for commands reloading their configuration.
+    Signal(i32),
+    /// Start the command again with the new values next to the running one,
+    /// and stop the running one once the new one passed the readiness check,
+    /// for servers sharing their sockets.
+    Replace,
+}
+
+impl std::str::FromStr for OnChange {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.split_once(':') {
+            None if name == "restart" => Ok(OnChange::Restart),
+            None if name == "replace" => Ok(OnChange::Replace),
+            Some(("signal", signal)) => signals::parse_signal(signal)
+                .map(OnChange::Signal)
+                .ok_or_else(|| format!("unknown signal {}", signal)),
+            _ => Err(format!("unknown change policy {}", name)),
+        }
+    }
+}
+
+/// Whether any variable was added, removed or changed value.
+pub fn secrets_changed(current: &[ResolvedSecret], updated: &[ResolvedSecret]) -> bool {
+    current.len() != updated.len()
+        || current
+            .iter()
+            .zip(updated)
+            .any(|(current, updated)| current.key != updated.key || current.value != updated.value)
+}
+
+/// The wait before the next resolution: the interval give or take up to
+/// `REFRESH_JITTER` of it, picked by the random sample, and no shorter than
+/// `MIN_REFRESH_DELAY`.
+pub fn refresh_delay(interval: Duration, sample: u64) -> Duration {
+    let offset = sample as f64 / u64::MAX as f64 * 2.0 - 1.0;
+    interval
+        .mul_f64(1.0 + offset * REFRESH_JITTER)
+        .max(MIN_REFRESH_DELAY)
+}
+
+/// The interval until the next resolution: the refresh interval, or the
+/// shortest refresh period of the secrets when some need retrieving more
+/// often. The secrets with a longer period are answered from the cache
+/// until it elapses.
+pub fn next_interval(interval: Duration, secrets: &[ResolvedSecret]) -> Duration {
+    secrets
+        .iter()
+        .filter_map(|secret| secret.refresh)
+        .fold(interval, Duration::min)
+}
+
+/// A random wait before the next resolution.
+fn jittered(interval: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    let sample = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    refresh_delay(interval, sample)
+}
+
+/// Runs the command, resolving the references again about every interval,
+/// or more often for the secrets with a shorter refresh period, with some
+/// jitter, and restarts or signals it with the new values whenever they
+/// changed, until it exits on its own. Failed resolutions are logged and the
+/// command keeps running with the values it has. SIGUSR2 logs the state of
+/// the secrets, masked, instead of being forwarded to the command.
+pub async fn supervise<S, T>(
+    secretsmanager_client: &S,
+    ssm_client: &T,
+    run_args: &RunArgs,
+    mut secrets: Vec<ResolvedSecret>,
+    interval: Duration,
+) -> Result<i32, Box<dyn Error>>
+where
+    S: SecretsManagerClientTrait + ?Sized,
+    T: SsmClientTrait + ?Sized,
+{
+    // The replacement and the running command would both be reaped by the
+    // process waiting for any child.
+    if run_args.on_change == OnChange::Replace && std::process::id() == 1 {
+        return Err("--on-change replace isn't supported as PID 1, run the tool under an init process such as docker --init".into());
+    }
+    let mut dump_requests = signals::StateDumpRequests::listen();
+    let mut refreshed = Instant::now();
+    let mut failed_refreshes = 0;
+    loop {
+        let mut exited = spawn_launch(run_args, &secrets);
+        let updated = loop {
+            let next_refresh = Instant::now() + jittered(next_interval(interval, &secrets));
+            loop {
+                tokio::select! {
+                    result = &mut exited => return command_result(result),
+                    _ = tokio::time::sleep_until(next_refresh) => break,
+                    _ = dump_requests.recv() => info!(
+                        "{}",
+                        summary::state_dump(
+                            &secrets,
+                            refreshed.elapsed(),
+                            next_refresh.saturating_duration_since(Instant::now()),
+                            failed_refreshes
+                        )
+                    ),
+                }
+            }
+            let updated = match process_environment(
+                secretsmanager_client,
+                ssm_client,
+                &run_args.resolve,
+            )
+            .await
+            {
+                Ok(updated) => {
+                    refreshed = Instant::now();
+                    failed_refreshes = 0;
+                    if !secrets_changed(&secrets, &updated) {
+                        info!("The secrets are unchanged");
+                        continue;
+                    }
+                    updated
+                }
+                // The clients refresh expiring credentials, such as
+                // those of assumed roles and web identity tokens, by
+                // themselves, but a failure to do so fails every call.
+                Err(e) if errors::is_credentials_error(e.as_ref()) => {
+                    failed_refreshes += 1;
+                    warn!(
+                            "Failed to refresh the AWS credentials, keeping the current values, {} refreshes failed in a row: {}",
+                            failed_refreshes,
+                            error_message(e.as_ref())
+                        );
+                    continue;
+                }
+                Err(e) => {
+                    failed_refreshes += 1;
+                    warn!(
+                            "Failed to refresh the secrets, keeping the current values, {} refreshes failed in a row: {}",
+                            failed_refreshes,
+                            e
+                        );
+                    continue;
+                }
+            };
+            let signum = match run_args.on_change {
+                OnChange::Restart => break updated,
+                OnChange::Signal(signum) => signum,
+                OnChange::Replace => {
+                    info!("The secrets changed, starting the replacement command");
+                    let running = signals::child_pid();
+                    let mut replacement = spawn_launch(run_args, &updated);
+                    let ready = tokio::select! {
+                        result = &mut exited => Err(result),
+                        ready = wait_ready(run_args, running, &mut replacement) => Ok(ready),
+                    };
+                    match ready {
+                        // The command exited on its own while being replaced.
+                        Err(result) => {
+                            let _ = stop_latest(running, &mut replacement).await;
+                            return command_result(result);
+                        }
+                        Ok(Ok(())) => {
+                            info!("The replacement command is ready, stopping the previous one");
+                            if let Some(pid) = running {
+                                signals::stop_process(pid);
+                            }
+                            command_result((&mut exited).await)?;
+                            exited = replacement;
+                            secrets = updated;
+                        }
+                        Ok(Err(reason)) => warn!(
+                            "Failed to replace the command, keeping the running one: {}",
+                            reason
+                        ),
+                    }
+                    continue;
+                }
+            };
+            info!("The secrets changed, updating the files of the command");
+            if let Err(e) = write_files(run_args, &updated) {
+                warn!("Failed to update the files of the command: {}", e);
+                continue;
+            }
+            if !signals::signal_child(signum) {
+                warn!("The command isn't running, it wasn't signalled");
+            }
+            secrets = updated;
+        };
+
+        info!("The secrets changed, restarting the command");
+        command_result(stop_latest(None, &mut exited).await)?;
+        secrets = updated;
+    }
+}
+
+type Exited = oneshot::Receiver<Result<i32, String>>;
+
+/// Stops the command started last, unless it's the `running` one, retrying
+/// while it's still starting, and waits for it to exit.
+async fn stop_latest(
+    running: Option<u32>,
+    exited: &mut Exited,
+) -> Result<Result<i32, String>, oneshot::error::RecvError> {
+    loop {
+        if let Some(pid) = signals::child_pid().filter(|&pid| Some(pid) != running) {
+            signals::stop_process(pid);
+            return exited.await;
+        }
+        tokio::select! {
+            result = &mut *exited => return result,
+            _ = tokio::time::sleep(STOP_RETRY_INTERVAL) => {}
+        }
+    }
+}
+
+/// Runs the readiness check until it succeeds once the replacement command
+/// started, failing when the command exits first or the timeout elapses, in
+/// which case it's stopped.
+async fn wait_ready(
+    run_args: &RunArgs,
+    running: Option<u32>,
+    replacement: &mut Exited,
+) -> Result<(), String> {
+    let deadline = Instant::now() + run_args.ready_timeout;
+    loop {
+        let started = signals::child_pid().is_some_and(|pid| Some(pid) != running);
+        if started && ready_check_passes(&run_args.ready_check).await {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            let _ = stop_latest(running, replacement).await;
+            return Err(format!(
+                "the replacement command didn't pass the readiness check within {:?}",
+                run_args.ready_timeout
+            ));
+        }
+        tokio::select! {
+            result = &mut *replacement => return Err(match command_result(result) {
+                Ok(code) => format!("the replacement command exited with status code {}", code),
+                Err(e) => e.to_string(),
+            }),
+            _ = tokio::time::sleep(READY_CHECK_INTERVAL) => {}
+        }
+    }
+}
+
+/// Whether the readiness check command, split on whitespace, exits
+/// successfully.
+async fn ready_check_passes(check: &str) -> bool {
+    let mut words = check.split_whitespace().map(String::from);
+    let Some(program) = words.next() else {
+        return true;
+    };
+    let arguments: Vec<String> = words.collect();
+    let status = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(program)
+            .args(arguments)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .status()
+    })
+    .await;
+    matches!(status, Ok(Ok(status)) if status.success())
+}
+
+/// Launches the command from another thread, so the resolutions can go on
+/// while it runs, returning the channel receiving its exit code.
+fn spawn_launch(run_args: &RunArgs, secrets: &[ResolvedSecret]) -> Exited {
+    let (sender, receiver) = oneshot::channel();
+    let runtime = tokio::runtime::Handle::current();
+    let run_args = run_args.clone();
+    let secrets = secrets.to_vec();
+    std::thread::spawn(move || {
+        // Entering the runtime lets the signals be forwarded to the command.
+        let _guard = runtime.enter();
+        let result = launch(&run_args, &secrets).map_err(|e| e.to_string());
+        let _ = sender.send(result);
+    });
+    receiver
+}
+
+fn command_result(
+    result: Result<Result<i32, String>, oneshot::error::RecvError>,
+) -> Result<i32, Box<dyn Error>> {
+    match result {
+        Ok(result) => result.map_err(|e| e.into()),
+        Err(_) => Err("The command supervision thread panicked".into()),
+    }
+}